@@ -0,0 +1,139 @@
+//! A bitmap allocator usable from several cores at once without a lock.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crate::MAX_BITS;
+
+const WORDS: usize = MAX_BITS.div_ceil(64);
+
+/// A bitmap of up to MAX_BITS bits stored in atomic words and shared through
+/// `&self`, for allocations of at most 64 bits that never cross a word.
+///
+/// A request scans the words for room and claims it with a single
+/// compare-and-exchange, or a fetch_or for single bits, retrying if another
+/// core changed the word in between, so it never blocks. Frees clear the
+/// bits with fetch_and, a word at a time, and may span any number of words.
+/// Placement is first-fit, among the runs that do not cross a word.
+///
+/// Claiming bits acquires, and freeing them releases, so whoever allocates
+/// bits sees what the previous owner wrote before freeing them.
+pub struct AtomicBitMap {
+    size: usize,
+    words: [AtomicU64; WORDS], // Bits past size count as allocated.
+    free: AtomicUsize,
+}
+
+impl AtomicBitMap {
+    /// Create a bitmap with size bits. By default, all bits are unallocated.
+    pub const fn new(size: usize) -> Self {
+        assert!(size <= MAX_BITS);
+        #[allow(clippy::declare_interior_mutable_const)]
+        const FREE: AtomicU64 = AtomicU64::new(0);
+        let mut words = [FREE; WORDS];
+        let mut w = size / 64;
+        while w < WORDS {
+            let padding = if w == size / 64 { !0 << (size % 64) } else { !0 };
+            words[w] = AtomicU64::new(padding);
+            w += 1;
+        }
+        AtomicBitMap { size, words, free: AtomicUsize::new(size) }
+    }
+
+    /// Number of bits.
+    pub fn size(&self) -> usize { self.size }
+
+    /// Number of unallocated bits, which may be out of date by the time it
+    /// is returned.
+    pub fn free_count(&self) -> usize { self.free.load(Ordering::Relaxed) }
+
+    /// Whether the index-th bit is allocated.
+    ///
+    /// Panics if index is not smaller than the size.
+    pub fn is_allocated(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.words[index / 64].load(Ordering::Acquire) >> (index % 64) & 1 == 1
+    }
+
+    /// Allocate n consecutive bits within a word, and return the index of the
+    /// first one, or `None` if no word has room.
+    ///
+    /// Panics if n is 0 or greater than 64.
+    pub fn alloc(&self, n: usize) -> Option<usize> {
+        self.alloc_aligned(n, 1)
+    }
+
+    /// Like alloc, but the index is a multiple of alignment.
+    ///
+    /// Panics if n is 0 or greater than 64, or if alignment is not a power
+    /// of two.
+    pub fn alloc_aligned(&self, n: usize, alignment: usize) -> Option<usize> {
+        assert!(0 < n && n <= 64);
+        assert!(alignment.is_power_of_two());
+        let step = alignment.min(64);
+        let mask = !0u64 >> (64 - n);
+        for (w, word) in self.words.iter().enumerate() {
+            // Words not beginning at a multiple of a larger alignment hold
+            // no aligned index.
+            if (w * 64) % alignment != 0 { continue; }
+            let mut v = word.load(Ordering::Relaxed);
+            loop {
+                let pos = if n == 1 && step == 1 {
+                    Some((!v).trailing_zeros() as usize).filter(|&p| p < 64)
+                } else {
+                    (0..=64 - n).step_by(step).find(|&p| v & (mask << p) == 0)
+                };
+                let p = match pos {
+                    Some(p) => p,
+                    None => break,
+                };
+                let claimed = if n == 1 {
+                    let old = word.fetch_or(1 << p, Ordering::Acquire);
+                    if old & (1 << p) == 0 { Ok(()) } else { Err(old | (1 << p)) }
+                } else {
+                    word.compare_exchange_weak(v, v | (mask << p), Ordering::Acquire, Ordering::Relaxed).map(|_| ())
+                };
+                match claimed {
+                    Ok(()) => {
+                        self.free.fetch_sub(n, Ordering::Relaxed);
+                        return Some(w * 64 + p);
+                    }
+                    Err(now) => v = now,
+                }
+            }
+        }
+        None
+    }
+
+    /// Free the n bits at begin, which may span several words.
+    ///
+    /// Panics if the range reaches past the end of the bitmap.
+    pub fn dealloc(&self, begin: usize, n: usize) {
+        self.set_range(begin, n, false);
+    }
+
+    /// Allocate the n bits at begin, whether or not they were free, e.g. to
+    /// reserve them before other cores start allocating.
+    ///
+    /// Panics if the range reaches past the end of the bitmap.
+    pub fn mark(&self, begin: usize, n: usize) {
+        self.set_range(begin, n, true);
+    }
+
+    fn set_range(&self, begin: usize, n: usize, allocated: bool) {
+        assert!(begin + n <= self.size);
+        let end = begin + n;
+        let mut i = begin;
+        while i < end {
+            let len = (64 - i % 64).min(end - i);
+            let mask = (!0u64 >> (64 - len)) << (i % 64);
+            let word = &self.words[i / 64];
+            if allocated {
+                let old = word.fetch_or(mask, Ordering::Acquire);
+                self.free.fetch_sub((!old & mask).count_ones() as usize, Ordering::Relaxed);
+            } else {
+                let old = word.fetch_and(!mask, Ordering::Release);
+                self.free.fetch_add((old & mask).count_ones() as usize, Ordering::Relaxed);
+            }
+            i += len;
+        }
+    }
+}
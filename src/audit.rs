@@ -0,0 +1,182 @@
+//! A log of the last operations on an allocator, enabled by the `audit`
+//! feature.
+
+use core::fmt;
+use crate::{AllocError, BitAllocator, ReserveConflict, exhaustion};
+
+/// What an [`Operation`] was.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OpKind {
+    /// An allocation at an index congruent to offset modulo align; plain
+    /// alloc has an align of 1.
+    Alloc { align: usize, offset: usize },
+    /// A dealloc.
+    Dealloc,
+    /// A mark.
+    Mark,
+}
+
+/// An operation recorded by an [`AuditLog`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Operation {
+    /// Number of operations recorded before this one.
+    pub seq: u64,
+    /// The method called.
+    pub kind: OpKind,
+    /// The first bit of the range: for an allocation, the index returned,
+    /// or 0 if it failed.
+    pub begin: usize,
+    /// Number of bits.
+    pub n: usize,
+    /// Why an allocation failed. For a dealloc or a mark, which never fail,
+    /// `AllocError::DoubleFree` or `AllocError::Occupied` if some bits were
+    /// already free or allocated, hinting at a bug in the caller.
+    pub result: Result<(), AllocError>,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} ", self.seq)?;
+        match self.kind {
+            OpKind::Alloc { align: 1, .. } => write!(f, "alloc({})", self.n)?,
+            OpKind::Alloc { align, offset } => write!(f, "alloc({}, {} mod {})", self.n, offset, align)?,
+            OpKind::Dealloc => write!(f, "dealloc({}, {})", self.begin, self.n)?,
+            OpKind::Mark => write!(f, "mark({}, {})", self.begin, self.n)?,
+        }
+        match (self.kind, self.result) {
+            (OpKind::Alloc { .. }, Ok(())) => write!(f, " = {}", self.begin),
+            (_, Ok(())) => Ok(()),
+            (_, Err(e)) => write!(f, ": {}", e),
+        }
+    }
+}
+
+/// Wraps an allocator with a ring buffer of the last N operations on it, so
+/// that when the bitmap is found corrupted the recent history can be dumped
+/// without attaching a debugger.
+pub struct AuditLog<A, const N: usize = 32> {
+    inner: A,
+    ops: [Option<Operation>; N],
+    seq: u64,
+}
+
+impl<A: BitAllocator, const N: usize> AuditLog<A, N> {
+    /// Wrap inner, with an empty log.
+    pub fn new(inner: A) -> Self {
+        AuditLog { inner, ops: [None; N], seq: 0 }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator, dropping the log.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// Number of operations recorded so far, including those that dropped
+    /// out of the log.
+    pub fn op_count(&self) -> u64 { self.seq }
+
+    /// Iterate over the operations in the log, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = Operation> + '_ {
+        let first = self.seq.saturating_sub(N as u64);
+        (first..self.seq).filter_map(move |seq| self.ops[(seq % N as u64) as usize])
+    }
+
+    /// Write the operations in the log to out, oldest first, one per line.
+    pub fn dump(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        self.iter().try_for_each(|op| writeln!(out, "{}", op))
+    }
+
+    /// Forget the operations in the log.
+    pub fn clear_log(&mut self) {
+        self.ops = [None; N];
+    }
+
+    fn record(&mut self, kind: OpKind, begin: usize, n: usize, result: Result<(), AllocError>) {
+        if N == 0 { return; }
+        self.ops[(self.seq % N as u64) as usize] = Some(Operation { seq: self.seq, kind, begin, n, result });
+        self.seq += 1;
+    }
+
+    // Run f on inner to allocate n bits, and record it as kind.
+    fn logged<F: FnOnce(&mut A) -> Option<usize>>(&mut self, n: usize, kind: OpKind, f: F) -> Option<usize> {
+        let begin = f(&mut self.inner);
+        let result = begin.map(|_| ()).ok_or_else(|| exhaustion(&self.inner, n));
+        self.record(kind, begin.unwrap_or(0), n, result);
+        begin
+    }
+
+    // Record a dealloc of [begin, begin+n) about to be made.
+    fn record_dealloc(&mut self, begin: usize, n: usize) {
+        let result = if self.all(begin, n, true) { Ok(()) } else { Err(AllocError::DoubleFree) };
+        self.record(OpKind::Dealloc, begin, n, result);
+    }
+
+    // Record a mark of [begin, begin+n) about to be made.
+    fn record_mark(&mut self, begin: usize, n: usize) {
+        let result = if self.all(begin, n, false) { Ok(()) } else { Err(AllocError::Occupied) };
+        self.record(OpKind::Mark, begin, n, result);
+    }
+
+    // Whether the bits [begin, begin+n) are all in the given state. A range
+    // past the end counts, leaving the panic to inner.
+    fn all(&self, begin: usize, n: usize, allocated: bool) -> bool {
+        begin + n > self.inner.size() || self.inner.find_next(begin, !allocated) >= begin + n
+    }
+}
+
+impl<A: BitAllocator, const N: usize> BitAllocator for AuditLog<A, N> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        self.logged(n, OpKind::Alloc { align: 1, offset: 0 }, |a| a.alloc(n))
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        self.logged(n, OpKind::Alloc { align: alignment, offset: 0 }, |a| a.alloc_aligned(n, alignment))
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        self.logged(n, OpKind::Alloc { align, offset }, |a| a.alloc_aligned_offset(n, align, offset))
+    }
+
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.record_dealloc(begin, n);
+        self.inner.dealloc(begin, n);
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.record_mark(begin, n);
+        self.inner.mark(begin, n);
+    }
+
+    /// Record every range as a dealloc, checked against the state before
+    /// the batch, then free them in one call to the wrapped allocator.
+    fn dealloc_batch(&mut self, ranges: &[(usize, usize)]) {
+        for &(begin, n) in ranges { self.record_dealloc(begin, n); }
+        self.inner.dealloc_batch(ranges);
+    }
+
+    /// Recorded as a mark or a dealloc of the whole bitmap.
+    fn set_all(&mut self, allocated: bool) {
+        let size = self.inner.size();
+        if size > 0 {
+            if allocated { self.record_mark(0, size) } else { self.record_dealloc(0, size) }
+        }
+        self.inner.set_all(allocated);
+    }
+
+    /// Recorded as a mark if it succeeds.
+    fn alloc_at(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.inner.alloc_at(begin, n)?;
+        self.record(OpKind::Mark, begin, n, Ok(()));
+        Ok(())
+    }
+
+    /// Recorded as a mark if it succeeds.
+    fn reserve(&mut self, begin: usize, n: usize) -> Result<(), ReserveConflict> {
+        self.inner.reserve(begin, n)?;
+        self.record(OpKind::Mark, begin, n, Ok(()));
+        Ok(())
+    }
+
+    forward_bit_allocator!();
+}
@@ -1,26 +1,34 @@
-use core::ops::{Index, IndexMut, Add, AddAssign, Sub};
-use crate::BitAllocator;
+//! An unoptimized bitmap allocator, first-fit unless given another policy.
 
-/// An unoptimized first-fit bitmap allocator.
+use core::cmp::min;
+use core::fmt;
+use core::mem::align_of;
+use core::ops::{Add, AddAssign, Sub};
+use core::ptr::{self, addr_of_mut};
+use crate::{AllocError, BitAllocator, BitAllocatorInit, BitIndex, FitPolicy, FirstFit, MAX_BITS, OfflineError};
 
 const ELEM_WIDTH: usize = 64;
-const ELEM_CNT: usize = 0x600000 / 4096 / ELEM_WIDTH;
+const ELEM_CNT: usize = MAX_BITS / ELEM_WIDTH;
+
+// The summary of full words is a single word. This makes it the top level
+// too: a group of 64 words would already hold more than MAX_BITS bits, so a
+// coarser summary of groups with a free bit would always be the one bit
+// `full != all ones`, which the scans read from the summary directly. If
+// MAX_BITS ever grows past 64 words, this assertion fails and the summary
+// needs a second level.
+const _: () = assert!(ELEM_CNT <= ELEM_WIDTH);
 
 #[derive(PartialOrd, PartialEq, Debug, Copy, Clone)]
 struct RawIndex(usize, usize); // index, bit
 
 impl RawIndex {
-    fn new() -> Self { RawIndex(0, 0) }
-
-    fn max() -> Self { RawIndex(ELEM_CNT - 1, ELEM_WIDTH - 1) }
-
-    fn to_int(&self) -> usize { self.0 * ELEM_WIDTH + self.1 }
+    fn to_int(self) -> usize { self.0 * ELEM_WIDTH + self.1 }
 
     fn from_int(x: usize) -> Self { RawIndex(x / ELEM_WIDTH, x % ELEM_WIDTH) }
 
     fn next_aligned(&self, alignment: usize) -> Self {
-        let mask = alignment - 1;
-        Self::from_int((self.to_int() + alignment - 1) & !mask)
+        let i = self.to_int();
+        Self::from_int(i + (alignment - i % alignment) % alignment)
     }
 }
 
@@ -29,7 +37,7 @@ impl Add<usize> for RawIndex {
 
     fn add(self, rhs: usize) -> Self::Output {
         let (mut a, mut b) = (self.0, self.1 + rhs);
-        a += (b / ELEM_WIDTH);
+        a += b / ELEM_WIDTH;
         b %= ELEM_WIDTH;
         RawIndex(a, b)
     }
@@ -51,7 +59,8 @@ impl Sub for RawIndex {
     }
 }
 
-struct RawBitMap([i64; ELEM_CNT]);
+#[derive(Clone)]
+struct RawBitMap([u64; ELEM_CNT]);
 
 impl RawBitMap {
     fn get(&self, i: &RawIndex) -> bool {
@@ -60,89 +69,505 @@ impl RawBitMap {
         (self.0[i.0] >> i.1) & 1 == 1
     }
 
-    fn set(&mut self, i: &RawIndex, b: bool) {
-        assert!(i.0 <= ELEM_CNT);
-        assert!(i.1 < 64);
-        self.0[i.0] &= !(1 << i.1);
-        self.0[i.0] |= (if b {1} else {0} << i.1);
+    // Calls f(word, mask) for each word overlapping the bits [begin, end),
+    // where mask selects the overlapping bits of the word.
+    fn for_each_word<F: FnMut(usize, u64)>(begin: usize, end: usize, mut f: F) {
+        let mut i = begin;
+        while i < end {
+            let (w, lo) = (i / ELEM_WIDTH, i % ELEM_WIDTH);
+            let hi = core::cmp::min(end - w * ELEM_WIDTH, ELEM_WIDTH);
+            f(w, (!0u64 >> (ELEM_WIDTH - (hi - lo))) << lo);
+            i = (w + 1) * ELEM_WIDTH;
+        }
     }
 
-    fn set_range(&mut self, begin: &RawIndex, end: &RawIndex, b: bool) {
-        let mut i = *begin;
-        while i < *end {
-            self.set(&i, b);
-            i += 1;
-        }
+    // Returns the number of bits that changed.
+    //
+    // Each word is updated once, through the mask of the bits it holds.
+    fn set_range(&mut self, begin: &RawIndex, end: &RawIndex, b: bool) -> usize {
+        let words = &mut self.0;
+        let mut changed = 0;
+        Self::for_each_word(begin.to_int(), end.to_int(), |w, mask| {
+            let target = if b { mask } else { 0 };
+            changed += ((words[w] ^ target) & mask).count_ones() as usize;
+            words[w] = (words[w] & !mask) | target;
+        });
+        changed
+    }
+}
+
+// The free_bits of an empty bitmap with size bits.
+const fn free_bits_below(size: usize) -> [u8; ELEM_CNT] {
+    let mut free_bits = [0; ELEM_CNT];
+    let mut w = 0;
+    while w * ELEM_WIDTH < size {
+        let left = size - w * ELEM_WIDTH;
+        free_bits[w] = if left < ELEM_WIDTH { left as u8 } else { ELEM_WIDTH as u8 };
+        w += 1;
     }
+    free_bits
 }
 
-pub struct LinearBitMap {
+#[derive(Clone)]
+pub struct LinearBitMap<P = FirstFit, I = usize> {
     size: usize,
     bitmap: RawBitMap,
-    end: RawIndex
+    full: u64, // Bit w is set if word w of bitmap is all allocated, up to size.
+    free_bits: [u8; ELEM_CNT], // Free bits of each word, up to size.
+    holes: RawBitMap, // Always allocated in bitmap too.
+    bad: RawBitMap,   // Always holes too.
+    pinned: RawBitMap, // Always allocated, never holes.
+    end: RawIndex,
+    first_free_hint: I, // No bit below it is free.
+    used: usize,
+    hole_count: usize,
+    bad_count: usize,
+    pinned_count: usize,
+    policy: P,
 }
 
-fn div_ceil(a: usize, b: usize) -> usize { (a+b-1)/b }
-
 impl LinearBitMap {
+    /// Create a first-fit bitmap with size bits. By default, all bits are unallocated.
+    ///
+    /// This is a const fn, so the bitmap can be the initializer of a static.
+    pub const fn new(size: usize) -> Self {
+        Self::with_policy(size, FirstFit)
+    }
+
+    /// Create a first-fit bitmap with size bits in the memory at ptr, e.g. at
+    /// a fixed physical address during early boot, rather than in a static.
+    ///
+    /// The bitmap takes `size_of::<LinearBitMap>()` bytes whatever its size.
+    ///
+    /// # Safety
+    ///
+    /// ptr must be valid for writes of `size_of::<LinearBitMap>()` bytes, and
+    /// that memory must not be accessed other than through the returned
+    /// reference for the rest of the program.
+    ///
+    /// Panics if ptr is not suitably aligned or size exceeds the capacity.
+    pub unsafe fn init_at(ptr: *mut u8, size: usize) -> &'static mut Self {
+        assert_eq!(0, ptr as usize % align_of::<Self>());
+        assert!(size <= ELEM_CNT * ELEM_WIDTH);
+        // Write the fields one by one: building Self::new(size) and moving it
+        // would need the whole bitmap on the stack first.
+        let p = ptr as *mut Self;
+        addr_of_mut!((*p).size).write(size);
+        for map in [addr_of_mut!((*p).bitmap), addr_of_mut!((*p).holes),
+                    addr_of_mut!((*p).bad), addr_of_mut!((*p).pinned)] {
+            ptr::write_bytes(map, 0, 1);
+        }
+        addr_of_mut!((*p).full).write(0);
+        addr_of_mut!((*p).free_bits).write(free_bits_below(size));
+        addr_of_mut!((*p).end).write(RawIndex::from_int(size));
+        addr_of_mut!((*p).first_free_hint).write(0);
+        for count in [addr_of_mut!((*p).used), addr_of_mut!((*p).hole_count),
+                      addr_of_mut!((*p).bad_count), addr_of_mut!((*p).pinned_count)] {
+            count.write(0);
+        }
+        addr_of_mut!((*p).policy).write(FirstFit);
+        &mut *p
+    }
+
+    /// Create a first-fit bitmap with size bits whose state is taken from
+    /// words: bit i is allocated if bit `i % 64` of `words[i / 64]` is set.
+    /// Bits of words past size are ignored.
+    ///
+    /// Panics if size exceeds the capacity or words holds fewer than size bits.
+    pub fn from_words(size: usize, words: &[u64]) -> Self {
+        assert!(size <= words.len() * ELEM_WIDTH);
+        let mut bm = Self::new(size);
+        RawBitMap::for_each_word(0, size, |w, mask| bm.bitmap.0[w] = words[w] & mask);
+        bm.used = bm.bitmap.0.iter().map(|w| w.count_ones() as usize).sum();
+        bm.sync_words(0, size);
+        bm
+    }
+}
+
+impl<P: FitPolicy> LinearBitMap<P> {
+    /// Create a bitmap with size bits that places allocations according to policy.
+    pub const fn with_policy(size: usize, policy: P) -> Self {
+        Self::with_index_type(size, policy)
+    }
+}
+
+impl<P: FitPolicy, I: BitIndex> LinearBitMap<P, I> {
+    /// Like with_policy, but the first-free hint is stored as I.
+    ///
+    /// Panics if size exceeds the capacity or what I can hold.
+    pub const fn with_index_type(size: usize, policy: P) -> Self {
+        assert!(size <= ELEM_CNT * ELEM_WIDTH && size <= I::MAX);
+        LinearBitMap {
+            size,
+            bitmap: RawBitMap([0; ELEM_CNT]),
+            full: 0,
+            free_bits: free_bits_below(size),
+            holes: RawBitMap([0; ELEM_CNT]),
+            bad: RawBitMap([0; ELEM_CNT]),
+            pinned: RawBitMap([0; ELEM_CNT]),
+            end: RawIndex(size / ELEM_WIDTH, size % ELEM_WIDTH),
+            first_free_hint: I::ZERO,
+            used: 0,
+            hole_count: 0,
+            bad_count: 0,
+            pinned_count: 0,
+            policy,
+        }
+    }
+
+    /// Change the number of managed bits to new_size, keeping the state of
+    /// the bits below it. Bits added at the end are free.
+    ///
+    /// Fails, without changing anything, if new_size exceeds the capacity or
+    /// if a bit that would be cut off is allocated.
+    pub fn resize(&mut self, new_size: usize) -> Result<(), AllocError> {
+        if new_size > ELEM_CNT * ELEM_WIDTH || new_size > I::MAX {
+            return Err(AllocError::OutOfBounds);
+        }
+        if self.next_of(&RawIndex::from_int(new_size), true) < self.end {
+            return Err(AllocError::Occupied);
+        }
+        self.size = new_size;
+        self.end = RawIndex::from_int(new_size);
+        self.full = 0;
+        self.free_bits = [0; ELEM_CNT];
+        self.sync_words(0, new_size);
+        Ok(())
+    }
+
+    /// Move the bits [at, size) into a new bitmap, with a default policy,
+    /// leaving self with the bits [0, at).
+    ///
+    /// Panics if at is greater than the size.
+    pub fn split_off(&mut self, at: usize) -> Self where P: Default {
+        assert!(at <= self.size);
+        let mut other = Self::with_index_type(self.size - at, P::default());
+        for i in at..self.size {
+            if self.is_bad(i) {
+                other.mark_bad(i - at, 1);
+            } else if self.is_hole(i) {
+                other.mark_hole(i - at, 1);
+            } else if self.is_allocated(i) {
+                other.mark(i - at, 1);
+            }
+        }
+        for i in (at..self.size).filter(|&i| self.is_pinned(i)) {
+            other.pin(i - at, 1).unwrap();
+        }
+        self.clear_holes(at, self.size);
+        self.unpin(at, self.size - at);
+        self.dealloc(at, self.size - at);
+        self.resize(at).unwrap();
+        other
+    }
+
+    /// Append the bits of other after those of self, undoing split_off.
+    ///
+    /// Panics if the combined size exceeds the capacity.
+    pub fn merge(mut self, other: Self) -> Self {
+        let at = self.size;
+        self.resize(at + other.size).expect("merged bitmap exceeds the capacity");
+        for i in (0..other.size).filter(|&i| other.is_hole(i)) {
+            self.mark_hole(at + i, 1);
+        }
+        for (begin, len) in other.iter_bad_ranges() {
+            self.mark_bad(at + begin, len);
+        }
+        for (begin, len) in other.iter_allocated_ranges() {
+            self.mark(at + begin, len);
+        }
+        for i in (0..other.size).filter(|&i| other.is_pinned(i)) {
+            self.pin(at + i, 1).unwrap();
+        }
+        self
+    }
+
+    /// Turn the bits [begin, begin+n) into holes, which stay allocated for
+    /// good, and are no longer pinned. See `BitAllocator::is_hole`.
+    pub fn mark_hole(&mut self, begin: usize, n: usize) {
+        assert!(begin + n <= self.size);
+        self.unpin(begin, n);
+        let (b, e) = (RawIndex::from_int(begin), RawIndex::from_int(begin + n));
+        self.hole_count += self.holes.set_range(&b, &e, true);
+        self.used += self.bitmap.set_range(&b, &e, true);
+        self.sync_words(begin, begin + n);
+    }
+
+    /// Retire the faulty bits [begin, begin+n) for good, whether they are
+    /// allocated or not: they become holes that are reported as bad. See
+    /// `BitAllocator::is_bad`.
+    pub fn mark_bad(&mut self, begin: usize, n: usize) {
+        self.mark_hole(begin, n);
+        self.bad_count += self.bad.set_range(&RawIndex::from_int(begin),
+                                             &RawIndex::from_int(begin + n), true);
+    }
+
+    /// Make the bits [begin, begin+n) available for allocation, e.g. when
+    /// memory is hotplugged. The bitmap grows if they reach past its end; any
+    /// bits between the old end and begin become holes. Within the bitmap,
+    /// only holes can be added.
+    ///
+    /// Fails, without changing anything, if n is zero, if the range reaches
+    /// past the capacity, or if it covers bits of the bitmap that are not
+    /// holes or are bad.
+    pub fn hot_add(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        let (end, old) = (begin + n, self.size);
+        if n == 0 {
+            return Err(AllocError::InvalidSize);
+        }
+        if end > self.capacity() {
+            return Err(AllocError::OutOfBounds);
+        }
+        if (begin..min(end, old)).any(|i| !self.is_hole(i) || self.is_bad(i)) {
+            return Err(AllocError::Occupied);
+        }
+        if end > old {
+            self.resize(end)?;
+        }
+        if begin > old {
+            self.mark_hole(old, begin - old);
+        }
+        if begin < old {
+            self.clear_holes(begin, min(end, old));
+        }
+        self.dealloc(begin, n);
+        Ok(())
+    }
+
+    /// Take the bits [begin, begin+n) offline, e.g. before memory is
+    /// unplugged, by turning them into holes.
+    ///
+    /// Fails, without changing anything, unless every bit of the range is
+    /// free or already a hole.
+    pub fn try_offline(&mut self, begin: usize, n: usize) -> Result<(), OfflineError> {
+        crate::check_offline(self, begin, n)?;
+        self.mark_hole(begin, n);
+        Ok(())
+    }
+
+    /// Pin the allocated bits [begin, begin+n), e.g. for a long-lived DMA
+    /// mapping: until unpinned, dealloc leaves them allocated and
+    /// dealloc_checked and try_dealloc fail. See `BitAllocator::is_pinned`.
+    ///
+    /// Fails, without changing anything, if the range is empty or out of
+    /// bounds, or if some bit of it is free or a hole.
+    pub fn pin(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        crate::check_pin(self, begin, n)?;
+        self.pinned_count += self.pinned.set_range(&RawIndex::from_int(begin),
+                                                   &RawIndex::from_int(begin + n), true);
+        Ok(())
+    }
+
+    /// Unpin the bits [begin, begin+n), so that they can be freed again.
+    /// Bits that are not pinned are left alone.
+    ///
+    /// Panics if the range reaches past the end of the bitmap.
+    pub fn unpin(&mut self, begin: usize, n: usize) {
+        assert!(begin + n <= self.size);
+        self.pinned_count -= self.pinned.set_range(&RawIndex::from_int(begin),
+                                                   &RawIndex::from_int(begin + n), false);
+    }
+
+    // Turn the holes among the bits [begin, end) back into allocated bits,
+    // forgetting which were bad.
+    fn clear_holes(&mut self, begin: usize, end: usize) {
+        let (b, e) = (RawIndex::from_int(begin), RawIndex::from_int(end));
+        self.hole_count -= self.holes.set_range(&b, &e, false);
+        self.bad_count -= self.bad.set_range(&b, &e, false);
+    }
+
+    /// The words holding the bitmap, in the layout taken by from_words.
+    /// Bits of the last word past size are clear.
+    pub fn as_words(&self) -> &[u64] {
+        &self.bitmap.0[..self.size.div_ceil(ELEM_WIDTH)]
+    }
+
+    /// Copy as_words to the beginning of out, returning how many words were
+    /// written.
+    ///
+    /// Panics if out is too short.
+    pub fn copy_words_into(&self, out: &mut [u64]) -> usize {
+        let words = self.as_words();
+        out[..words.len()].copy_from_slice(words);
+        words.len()
+    }
+
+    /// Number of free bits among the bits [begin, begin+n), counted a word at
+    /// a time from the free bits kept for every word.
+    ///
+    /// Panics if the range reaches past the end of the bitmap.
+    pub fn free_count_in(&self, begin: usize, n: usize) -> usize {
+        assert!(begin + n <= self.size);
+        let mut free = 0;
+        RawBitMap::for_each_word(begin, begin + n, |w, mask| {
+            if mask == !0 {
+                free += self.free_bits[w] as usize;
+            } else {
+                free += (mask & !self.bitmap.0[w]).count_ones() as usize;
+            }
+        });
+        free
+    }
+
+    /// Allocate through the policy rather than the first-fit scan below.
+    fn alloc_placed(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        let begin = self.policy.place(self, n, align, offset)?;
+        self.mark(begin, n);
+        self.policy.allocated(begin, n);
+        Some(begin)
+    }
+
     /// Allocate one bit. Fast-path.
+    ///
+    /// The first word that is not full is found in the summary, and its
+    /// first free bit with trailing_zeros of the inverted word, so this takes
+    /// the same time however full the bitmap is.
     fn alloc_1(&mut self) -> Option<usize> {
-        let bm = &mut self.bitmap;
-        let mut i = RawIndex::new();
-        while i < self.end {
-            if !bm.get(&i) {
-                bm.set(&i, true);
-                return Some(i.to_int());
-            }
-            i += 1;
+        let w = (!self.full).trailing_zeros() as usize;
+        if w * ELEM_WIDTH >= self.size { return None; }
+        let i = RawIndex(w, (!self.bitmap.0[w]).trailing_zeros() as usize);
+        self.set_allocated(&i, &(i + 1));
+        Some(i.to_int())
+    }
+
+    // Allocate the bits [begin, end), keeping used, full and the hint in sync.
+    fn set_allocated(&mut self, begin: &RawIndex, end: &RawIndex) {
+        self.used += self.bitmap.set_range(begin, end, true);
+        self.sync_words(begin.to_int(), end.to_int());
+        let hint = self.first_free_hint.to_usize();
+        if begin.to_int() <= hint {
+            self.first_free_hint = I::from_usize(core::cmp::max(hint, end.to_int()));
         }
-        None
+    }
+
+    // Note that the bits from begin on may have been freed.
+    fn lower_hint(&mut self, begin: usize) {
+        self.first_free_hint = I::from_usize(min(self.first_free_hint.to_usize(), begin));
+    }
+
+    // Recompute the bits of full and the free_bits of the words overlapping
+    // [begin, end), up to size.
+    fn sync_words(&mut self, begin: usize, end: usize) {
+        let (bitmap, size, free_bits) = (&self.bitmap.0, self.size, &mut self.free_bits);
+        let mut full = self.full;
+        RawBitMap::for_each_word(begin, min(end, size), |w, _| {
+            // The bits of word w below size; the last word may be partial.
+            let valid = !0u64 >> (ELEM_WIDTH - min(size - w * ELEM_WIDTH, ELEM_WIDTH));
+            free_bits[w] = (valid & !bitmap[w]).count_ones() as u8;
+            if free_bits[w] == 0 { full |= 1 << w; } else { full &= !(1 << w); }
+        });
+        self.full = full;
+    }
+
+    // Whether no free run of n bits can begin in word w: it has fewer than n
+    // free bits and its last bit is allocated, so a run cannot go on into the
+    // next word either.
+    fn too_few_free(&self, w: usize, n: usize) -> bool {
+        (self.free_bits[w] as usize) < n && self.bitmap.0[w] >> (ELEM_WIDTH - 1) == 1
     }
 
     // If some, result > begin and bm[result] != bm[begin]
     // Could return self.end
     //
-    // TODO: to speed up, multiple bits can be skipped at once
-    fn next_toggle(&mut self, begin: &RawIndex) -> RawIndex {
+    // Scans a word at a time: the bits of a word that differ from bm[begin]
+    // are found with trailing_zeros. Runs of allocated bits skip all the full
+    // words that follow at once, with trailing_ones on the summary.
+    fn next_toggle(&self, begin: &RawIndex) -> RawIndex {
+        if *begin >= self.end { return self.end; }
         let b = self.bitmap.get(begin);
-        let mut i = *begin;
-        while i < self.end && self.bitmap.get(&i) == b {
-            i += 1;
+        let (mut w, mut from) = (begin.0, begin.1);
+        while w * ELEM_WIDTH < self.size {
+            if b && from == 0 && (self.full >> w) & 1 == 1 {
+                w += (self.full >> w).trailing_ones() as usize;
+                continue;
+            }
+            let word = if b { !self.bitmap.0[w] } else { self.bitmap.0[w] };
+            let toggles = word & (!0 << from);
+            if toggles != 0 {
+                return RawIndex::from_int(min(w * ELEM_WIDTH + toggles.trailing_zeros() as usize, self.size));
+            }
+            w += 1;
+            from = 0;
         }
-        return i;
+        self.end
+    }
+
+    // The first free bit, scanning from the hint, which is stale if that
+    // bit has been allocated since.
+    fn first_free(&self) -> RawIndex {
+        self.next_of(&RawIndex::from_int(self.first_free_hint.to_usize()), false)
+    }
+
+    // First index at or after begin whose bit is b. Could return self.end
+    fn next_of(&self, begin: &RawIndex, b: bool) -> RawIndex {
+        if *begin >= self.end { return self.end; }
+        if self.bitmap.get(begin) == b { return *begin; }
+        self.next_toggle(begin)
     }
+}
+
+impl<P: FitPolicy + Clone, I: BitIndex> LinearBitMap<P, I> {
+    /// Save the whole allocator state, including that of the policy.
+    pub fn snapshot(&self) -> Self { self.clone() }
+
+    /// Roll back to the state saved by snapshot.
+    pub fn restore(&mut self, snapshot: &Self) { self.clone_from(snapshot) }
+}
+
+impl<P: FitPolicy + fmt::Debug, I: BitIndex> fmt::Debug for LinearBitMap<P, I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        crate::debug_summary(self, "LinearBitMap", &self.policy, f)
+    }
+}
+
+impl<P: FitPolicy, I: BitIndex> fmt::Display for LinearBitMap<P, I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.visualize().fmt(f)
+    }
+}
 
-    // TODO: same optimization as above
-    fn first_of(&mut self, b: bool) -> RawIndex {
-        let i = RawIndex::new();
-        if self.bitmap.get(&i) == b { return i; }
-        self.next_toggle(&i)
+#[cfg(feature = "serde")]
+impl<P: FitPolicy, I: BitIndex> serde::Serialize for LinearBitMap<P, I> {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        crate::serde_impl::serialize(self.size, self.as_words(), s)
     }
 }
 
-impl BitAllocator for LinearBitMap {
+#[cfg(feature = "serde")]
+impl<'de, P: FitPolicy + Default, I: BitIndex> serde::Deserialize<'de> for LinearBitMap<P, I> {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        crate::serde_impl::deserialize(d, |size| Self::with_index_type(size, P::default()))
+    }
+}
+
+impl<P: FitPolicy + Default, I: BitIndex> BitAllocatorInit for LinearBitMap<P, I> {
     fn new(size: usize) -> Self {
-        assert!(size <= ELEM_CNT * ELEM_WIDTH);
-        LinearBitMap {
-            size: size,
-            bitmap: RawBitMap([0; ELEM_CNT]),
-            end: RawIndex(size / ELEM_WIDTH, size % ELEM_WIDTH),
-        }
+        Self::with_index_type(size, P::default())
     }
+}
 
+impl<P: FitPolicy, I: BitIndex> BitAllocator for LinearBitMap<P, I> {
     fn alloc(&mut self, n: usize) -> Option<usize> {
         assert!(0 < n && n <= self.size * ELEM_WIDTH);
+        if !P::FIRST_FIT {
+            return self.alloc_placed(n, 1, 0);
+        }
         // fast path
         if n == 1 {
             return self.alloc_1();
         }
         // general case
-        let mut begin = self.first_of(false);
+        let mut begin = self.first_free();
+        self.first_free_hint = I::from_usize(begin.to_int());
         loop {
             if begin == self.end { return None; }
+            if self.too_few_free(begin.0, n) {
+                begin = self.next_of(&RawIndex(begin.0 + 1, 0), false);
+                continue;
+            }
             let end = self.next_toggle(&begin);
             if end - begin >= n {
-                self.bitmap.set_range(&begin, &(begin + n), true);
+                self.set_allocated(&begin, &(begin + n));
                 return Some(begin.to_int());
             }
             begin = self.next_toggle(&end);
@@ -151,22 +576,120 @@ impl BitAllocator for LinearBitMap {
 
     fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
         assert!(0 < n && n <= self.size);
-        assert!(alignment >= 1 && alignment & (alignment -1) == 0); // alignment must be a power of 2
-        let mut begin = self.first_of(false);
+        assert!(alignment >= 1);
+        if !P::FIRST_FIT {
+            return self.alloc_placed(n, alignment, 0);
+        }
+        let mut begin = self.first_free();
+        self.first_free_hint = I::from_usize(begin.to_int());
         loop {
             if begin == self.end { return None; }
+            if self.too_few_free(begin.0, n) {
+                begin = self.next_of(&RawIndex(begin.0 + 1, 0), false);
+                continue;
+            }
             let end = self.next_toggle(&begin);
             begin = begin.next_aligned(alignment);
             if end > begin && end - begin >= n {
-                self.bitmap.set_range(&begin, &(begin + n), true);
+                self.set_allocated(&begin, &(begin + n));
                 return Some(begin.to_int());
             }
             begin = self.next_toggle(&end);
         }
     }
 
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        if offset == 0 {
+            return self.alloc_aligned(n, align);
+        }
+        assert!(0 < n && n <= self.size);
+        assert!(offset < align);
+        self.alloc_placed(n, align, offset)
+    }
+
     fn dealloc(&mut self, begin: usize, n: usize) {
-        self.bitmap.set_range(&RawIndex::from_int(begin),
-                              &RawIndex::from_int(begin + n), false);
+        let (bitmap, holes, pinned) = (&mut self.bitmap.0, &self.holes.0, &self.pinned.0);
+        let mut freed = 0;
+        RawBitMap::for_each_word(begin, min(begin + n, self.size), |w, mask| {
+            let mask = mask & !(holes[w] | pinned[w]);
+            freed += (bitmap[w] & mask).count_ones() as usize;
+            bitmap[w] &= !mask;
+        });
+        self.used -= freed;
+        self.sync_words(begin, begin + n);
+        self.lower_hint(begin);
     }
+
+    // Collect a mask of bits to clear first, so each word is cleared only once.
+    fn dealloc_batch(&mut self, ranges: &[(usize, usize)]) {
+        let mut masks = [0u64; ELEM_CNT];
+        for &(begin, n) in ranges {
+            RawBitMap::for_each_word(begin, min(begin + n, self.size), |w, mask| masks[w] |= mask);
+            self.lower_hint(begin);
+        }
+        for (w, (word, mask)) in self.bitmap.0.iter_mut().zip(masks.iter()).enumerate() {
+            let mask = mask & !(self.holes.0[w] | self.pinned.0[w]);
+            self.used -= (*word & mask).count_ones() as usize;
+            *word &= !mask;
+        }
+        self.sync_words(0, self.size);
+    }
+
+    fn set_all(&mut self, allocated: bool) {
+        self.bitmap.0 = [0; ELEM_CNT];
+        self.first_free_hint = if allocated { I::from_usize(self.size) } else { I::ZERO };
+        if allocated {
+            RawBitMap::for_each_word(0, self.size, |w, mask| self.bitmap.0[w] = mask);
+            self.used = self.size;
+        } else {
+            for (w, word) in self.bitmap.0.iter_mut().enumerate() {
+                *word = self.holes.0[w] | self.pinned.0[w];
+            }
+            self.used = self.bitmap.0.iter().map(|w| w.count_ones() as usize).sum();
+        }
+        self.sync_words(0, self.size);
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        let end = min(begin + n, self.size);
+        if begin >= end { return; }
+        self.set_allocated(&RawIndex::from_int(begin), &RawIndex::from_int(end));
+    }
+
+    fn size(&self) -> usize { self.size }
+
+    fn capacity(&self) -> usize { min(ELEM_CNT * ELEM_WIDTH, I::MAX) }
+
+    fn free_count(&self) -> usize { self.size - self.used }
+
+    fn find_next(&self, from: usize, allocated: bool) -> usize {
+        let from = if allocated { from } else { core::cmp::max(from, self.first_free_hint.to_usize()) };
+        self.next_of(&RawIndex::from_int(from), allocated).to_int()
+    }
+
+    fn is_allocated(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.bitmap.get(&RawIndex::from_int(index))
+    }
+
+    fn is_hole(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.holes.get(&RawIndex::from_int(index))
+    }
+
+    fn hole_count(&self) -> usize { self.hole_count }
+
+    fn is_bad(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.bad.get(&RawIndex::from_int(index))
+    }
+
+    fn bad_count(&self) -> usize { self.bad_count }
+
+    fn is_pinned(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.pinned.get(&RawIndex::from_int(index))
+    }
+
+    fn pinned_count(&self) -> usize { self.pinned_count }
 }
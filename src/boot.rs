@@ -0,0 +1,98 @@
+//! Helpers for setting up a frame allocator at boot.
+
+use core::cmp::{max, min};
+use core::mem::{align_of, size_of};
+use core::ops::Range;
+use core::ptr;
+use crate::BitAllocatorInit;
+
+/// A range of physical memory, as described by a Multiboot memory map entry
+/// or a UEFI memory descriptor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    /// Address of the first byte.
+    pub base: usize,
+    /// Length in bytes.
+    pub len: usize,
+    /// Whether the memory is free for general use. Any other memory is
+    /// treated as reserved.
+    pub usable: bool,
+}
+
+/// Set up an allocator for the frames of frame_size bytes found in a memory
+/// map, returning it with the range of frame numbers it covers.
+///
+/// Bit i of the allocator stands for frame `first + i`, where first is the
+/// start of the returned range, i.e. for the memory at `(first + i) *
+/// frame_size`. The range spans from the lowest to the highest frame that
+/// lies entirely within a usable region, cut short if the allocator cannot
+/// hold that many. Frames in it that are not entirely usable, because they
+/// are in holes between regions or overlap a reserved region, are allocated.
+///
+/// regions is walked twice, so it must be cheap to clone.
+pub fn from_memory_map<T, I>(regions: I, frame_size: usize) -> (T, Range<usize>)
+    where T: BitAllocatorInit, I: Iterator<Item = MemoryRegion> + Clone {
+    // Whole frames of a usable region, and frames touched by a reserved one.
+    // A region may end at the top of the address space.
+    let usable = |r: &MemoryRegion| r.base.div_ceil(frame_size)..r.base.saturating_add(r.len) / frame_size;
+    let touched = |r: &MemoryRegion| r.base / frame_size..r.base.saturating_add(r.len).div_ceil(frame_size);
+    let (lo, hi) = regions.clone().filter(|r| r.usable).map(|r| usable(&r))
+        .filter(|f| f.start < f.end)
+        .fold((usize::MAX, 0), |(lo, hi), f| (min(lo, f.start), max(hi, f.end)));
+    if lo >= hi {
+        return (T::new(0), 0..0);
+    }
+    let hi = min(hi, lo + T::new(0).capacity());
+    let mut bm = T::new_filled(hi - lo);
+    let clip = |f: Range<usize>| max(f.start, lo) - lo..min(f.end, hi).saturating_sub(lo);
+    for r in regions.clone().filter(|r| r.usable) {
+        let f = clip(usable(&r));
+        if f.start < f.end { bm.dealloc(f.start, f.end - f.start); }
+    }
+    for r in regions.filter(|r| !r.usable) {
+        let f = clip(touched(&r));
+        if f.start < f.end { bm.mark(f.start, f.end - f.start); }
+    }
+    (bm, lo..hi)
+}
+
+/// Like from_memory_map, but for the `(base, size)` pairs found in the reg
+/// properties of the `/memory` nodes of a device tree and of the children of
+/// its `/reserved-memory` node.
+///
+/// Memory bases that are not a multiple of block_size are rounded up to the
+/// next block, and reserved ranges cover every block they touch.
+pub fn from_device_tree<T: BitAllocatorInit>(memory: &[(usize, usize)], reserved: &[(usize, usize)],
+                                             block_size: usize) -> (T, Range<usize>) {
+    let region = |usable| move |&(base, len): &(usize, usize)| MemoryRegion { base, len, usable };
+    let regions = memory.iter().map(region(true)).chain(reserved.iter().map(region(false)));
+    from_memory_map(regions, block_size)
+}
+
+/// Set up an allocator for the frames of a memory region, kept in the first
+/// frames of the region itself. Those frames are marked allocated.
+///
+/// Bit i of the allocator stands for the frame at `region + i * frame_size`.
+/// The allocator covers `len / frame_size` frames, or as many as it can hold
+/// if that is fewer.
+///
+/// # Safety
+///
+/// region must be valid for reads and writes of len bytes, and that memory
+/// must not be accessed other than through the returned allocator and the
+/// frames it hands out for the rest of the program.
+///
+/// Panics if region is not aligned for T, or if the region is too small to
+/// hold the allocator and at least one more frame.
+pub unsafe fn self_hosted<T: BitAllocatorInit + 'static>(region: *mut u8, len: usize,
+                                                         frame_size: usize) -> &'static mut T {
+    assert_eq!(0, region as usize % align_of::<T>());
+    let meta = size_of::<T>().div_ceil(frame_size);
+    let frames = core::cmp::min(len / frame_size, T::new(0).capacity());
+    assert!(meta < frames);
+    let bm = region as *mut T;
+    ptr::write(bm, T::new(frames));
+    let bm = &mut *bm;
+    bm.mark(0, meta);
+    bm
+}
@@ -1,39 +1,353 @@
-use crate::BitAllocator;
+//! An unoptimized bitmap allocator, first-fit unless given another policy.
 
-/// An unoptimized first-fit bitmap allocator.
+use core::cmp::min;
+use core::fmt;
+use core::mem::align_of;
+use core::ptr::{self, addr_of_mut};
+use crate::{AllocError, BitAllocator, BitAllocatorInit, FitPolicy, FirstFit, MAX_BITS, OfflineError};
 
-const MAX_LEN: usize = 0x600000 / 4096;
+const MAX_LEN: usize = MAX_BITS;
 
-pub struct LinearBitMap {
+#[derive(Clone)]
+pub struct LinearBitMap<P = FirstFit> {
     size: usize,
-    bitmap: [bool; MAX_LEN] // Allow concurrent access.
+    bitmap: [bool; MAX_LEN], // Allow concurrent access.
+    holes: [bool; MAX_LEN],  // Always allocated in bitmap too.
+    bad: [bool; MAX_LEN],    // Always holes too.
+    pinned: [bool; MAX_LEN], // Always allocated, never holes.
+    used: usize,
+    hole_count: usize,
+    bad_count: usize,
+    pinned_count: usize,
+    policy: P,
 }
 
 impl LinearBitMap {
-    /// Allocate one bit. Fast-path.
-    fn alloc_1(&mut self) -> Option<usize> {
-        let bm = &mut self.bitmap;
-        for i in 0..self.size {
-            if !bm[i] {
-                bm[i] = true;
-                return Some(i);
-            }
+    /// Create a first-fit bitmap with size bits. By default, all bits are unallocated.
+    ///
+    /// This is a const fn, so the bitmap can be the initializer of a static.
+    pub const fn new(size: usize) -> Self {
+        Self::with_policy(size, FirstFit)
+    }
+
+    /// Create a first-fit bitmap with size bits in the memory at ptr, e.g. at
+    /// a fixed physical address during early boot, rather than in a static.
+    ///
+    /// The bitmap takes `size_of::<LinearBitMap>()` bytes whatever its size.
+    ///
+    /// # Safety
+    ///
+    /// ptr must be valid for writes of `size_of::<LinearBitMap>()` bytes, and
+    /// that memory must not be accessed other than through the returned
+    /// reference for the rest of the program.
+    ///
+    /// Panics if ptr is not suitably aligned or size exceeds the capacity.
+    pub unsafe fn init_at(ptr: *mut u8, size: usize) -> &'static mut Self {
+        assert_eq!(0, ptr as usize % align_of::<Self>());
+        assert!(size <= MAX_LEN);
+        // Write the fields one by one: building Self::new(size) and moving it
+        // would need the whole bitmap on the stack first.
+        let p = ptr as *mut Self;
+        addr_of_mut!((*p).size).write(size);
+        for map in [addr_of_mut!((*p).bitmap), addr_of_mut!((*p).holes),
+                    addr_of_mut!((*p).bad), addr_of_mut!((*p).pinned)] {
+            ptr::write_bytes(map, 0, 1);
         }
-        None
+        for count in [addr_of_mut!((*p).used), addr_of_mut!((*p).hole_count),
+                      addr_of_mut!((*p).bad_count), addr_of_mut!((*p).pinned_count)] {
+            count.write(0);
+        }
+        addr_of_mut!((*p).policy).write(FirstFit);
+        &mut *p
+    }
+
+    /// Create a first-fit bitmap with one bit per element of bits, allocated
+    /// where the element is true.
+    ///
+    /// Panics if there are more elements than the capacity.
+    pub fn from_bools(bits: &[bool]) -> Self {
+        let mut bm = Self::new(bits.len());
+        bm.bitmap[..bits.len()].copy_from_slice(bits);
+        bm.used = bits.iter().filter(|&&b| b).count();
+        bm
     }
 }
 
-impl BitAllocator for LinearBitMap {
-    fn new(size: usize) -> Self {
+impl<P: FitPolicy> LinearBitMap<P> {
+    /// Create a bitmap with size bits that places allocations according to policy.
+    pub const fn with_policy(size: usize, policy: P) -> Self {
         assert!(size <= MAX_LEN);
         LinearBitMap {
             size,
-            bitmap: [false; MAX_LEN]
+            bitmap: [false; MAX_LEN],
+            holes: [false; MAX_LEN],
+            bad: [false; MAX_LEN],
+            pinned: [false; MAX_LEN],
+            used: 0,
+            hole_count: 0,
+            bad_count: 0,
+            pinned_count: 0,
+            policy,
+        }
+    }
+
+    /// Change the number of managed bits to new_size, keeping the state of
+    /// the bits below it. Bits added at the end are free.
+    ///
+    /// Fails, without changing anything, if new_size exceeds the capacity or
+    /// if a bit that would be cut off is allocated.
+    pub fn resize(&mut self, new_size: usize) -> Result<(), AllocError> {
+        if new_size > MAX_LEN {
+            return Err(AllocError::OutOfBounds);
+        }
+        if new_size < self.size && self.bitmap[new_size..self.size].contains(&true) {
+            return Err(AllocError::Occupied);
+        }
+        self.size = new_size;
+        Ok(())
+    }
+
+    /// Move the bits [at, size) into a new bitmap, with a default policy,
+    /// leaving self with the bits [0, at).
+    ///
+    /// Panics if at is greater than the size.
+    pub fn split_off(&mut self, at: usize) -> Self where P: Default {
+        assert!(at <= self.size);
+        let mut other = Self::with_policy(self.size - at, P::default());
+        for i in at..self.size {
+            if self.bad[i] {
+                other.mark_bad(i - at, 1);
+            } else if self.holes[i] {
+                other.mark_hole(i - at, 1);
+            } else if self.bitmap[i] {
+                other.mark(i - at, 1);
+            }
+        }
+        for i in (at..self.size).filter(|&i| self.pinned[i]) {
+            other.pin(i - at, 1).unwrap();
+        }
+        self.clear_holes(at, self.size);
+        self.unpin(at, self.size - at);
+        self.dealloc(at, self.size - at);
+        self.resize(at).unwrap();
+        other
+    }
+
+    /// Append the bits of other after those of self, undoing split_off.
+    ///
+    /// Panics if the combined size exceeds the capacity.
+    pub fn merge(mut self, other: Self) -> Self {
+        let at = self.size;
+        self.resize(at + other.size).expect("merged bitmap exceeds the capacity");
+        for i in (0..other.size).filter(|&i| other.holes[i]) {
+            self.mark_hole(at + i, 1);
+        }
+        for i in (0..other.size).filter(|&i| other.bad[i]) {
+            self.mark_bad(at + i, 1);
+        }
+        for (begin, len) in other.iter_allocated_ranges() {
+            self.mark(at + begin, len);
+        }
+        for i in (0..other.size).filter(|&i| other.pinned[i]) {
+            self.pin(at + i, 1).unwrap();
+        }
+        self
+    }
+
+    /// Turn the bits [begin, begin+n) into holes, which stay allocated for
+    /// good, and are no longer pinned. See `BitAllocator::is_hole`.
+    pub fn mark_hole(&mut self, begin: usize, n: usize) {
+        assert!(begin + n <= self.size);
+        self.unpin(begin, n);
+        self.set_range(begin, begin + n, true);
+        for hole in &mut self.holes[begin..begin + n] {
+            if !*hole { *hole = true; self.hole_count += 1; }
+        }
+    }
+
+    /// Retire the faulty bits [begin, begin+n) for good, whether they are
+    /// allocated or not: they become holes that are reported as bad. See
+    /// `BitAllocator::is_bad`.
+    pub fn mark_bad(&mut self, begin: usize, n: usize) {
+        self.mark_hole(begin, n);
+        for bad in &mut self.bad[begin..begin + n] {
+            if !*bad { *bad = true; self.bad_count += 1; }
+        }
+    }
+
+    /// Make the bits [begin, begin+n) available for allocation, e.g. when
+    /// memory is hotplugged. The bitmap grows if they reach past its end; any
+    /// bits between the old end and begin become holes. Within the bitmap,
+    /// only holes can be added.
+    ///
+    /// Fails, without changing anything, if n is zero, if the range reaches
+    /// past the capacity, or if it covers bits of the bitmap that are not
+    /// holes or are bad.
+    pub fn hot_add(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        let (end, old) = (begin + n, self.size);
+        if n == 0 {
+            return Err(AllocError::InvalidSize);
+        }
+        if end > self.capacity() {
+            return Err(AllocError::OutOfBounds);
+        }
+        if (begin..min(end, old)).any(|i| !self.holes[i] || self.bad[i]) {
+            return Err(AllocError::Occupied);
+        }
+        if end > old {
+            self.resize(end)?;
+        }
+        if begin > old {
+            self.mark_hole(old, begin - old);
+        }
+        if begin < old {
+            self.clear_holes(begin, min(end, old));
+        }
+        self.dealloc(begin, n);
+        Ok(())
+    }
+
+    /// Take the bits [begin, begin+n) offline, e.g. before memory is
+    /// unplugged, by turning them into holes.
+    ///
+    /// Fails, without changing anything, unless every bit of the range is
+    /// free or already a hole.
+    pub fn try_offline(&mut self, begin: usize, n: usize) -> Result<(), OfflineError> {
+        crate::check_offline(self, begin, n)?;
+        self.mark_hole(begin, n);
+        Ok(())
+    }
+
+    /// Pin the allocated bits [begin, begin+n), e.g. for a long-lived DMA
+    /// mapping: until unpinned, dealloc leaves them allocated and
+    /// dealloc_checked and try_dealloc fail. See `BitAllocator::is_pinned`.
+    ///
+    /// Fails, without changing anything, if the range is empty or out of
+    /// bounds, or if some bit of it is free or a hole.
+    pub fn pin(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        crate::check_pin(self, begin, n)?;
+        for pinned in &mut self.pinned[begin..begin + n] {
+            if !*pinned { *pinned = true; self.pinned_count += 1; }
+        }
+        Ok(())
+    }
+
+    /// Unpin the bits [begin, begin+n), so that they can be freed again.
+    /// Bits that are not pinned are left alone.
+    ///
+    /// Panics if the range reaches past the end of the bitmap.
+    pub fn unpin(&mut self, begin: usize, n: usize) {
+        assert!(begin + n <= self.size);
+        for pinned in &mut self.pinned[begin..begin + n] {
+            if *pinned { *pinned = false; self.pinned_count -= 1; }
+        }
+    }
+
+    // Turn the holes among the bits [begin, end) back into allocated bits,
+    // forgetting which were bad.
+    fn clear_holes(&mut self, begin: usize, end: usize) {
+        for hole in &mut self.holes[begin..end] {
+            if *hole { *hole = false; self.hole_count -= 1; }
+        }
+        for bad in &mut self.bad[begin..end] {
+            if *bad { *bad = false; self.bad_count -= 1; }
+        }
+    }
+
+    /// One element per bit, true where the bit is allocated.
+    pub fn as_bools(&self) -> &[bool] { &self.bitmap[..self.size] }
+
+    /// Pack the bitmap into out, in the layout taken by
+    /// `bitalloc::LinearBitMap::from_words`, returning how many words were
+    /// written. Bits of the last word past size are clear.
+    ///
+    /// Panics if out is too short.
+    pub fn copy_words_into(&self, out: &mut [u64]) -> usize {
+        let words = &mut out[..self.size.div_ceil(64)];
+        words.iter_mut().for_each(|w| *w = 0);
+        for (i, _) in self.as_bools().iter().enumerate().filter(|&(_, &b)| b) {
+            words[i / 64] |= 1 << (i % 64);
         }
+        words.len()
+    }
+
+    /// Allocate through the policy rather than the first-fit scan below.
+    fn alloc_placed(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        let begin = self.policy.place(self, n, align, offset)?;
+        self.mark(begin, n);
+        self.policy.allocated(begin, n);
+        Some(begin)
+    }
+
+    /// Allocate one bit. Fast-path.
+    fn alloc_1(&mut self) -> Option<usize> {
+        let i = self.bitmap[..self.size].iter().position(|&b| !b)?;
+        self.bitmap[i] = true;
+        self.used += 1;
+        Some(i)
     }
 
+    /// Set bits [begin, end) to b, keeping the used counter in sync. Holes
+    /// and pinned bits are left allocated.
+    fn set_range(&mut self, begin: usize, end: usize, b: bool) {
+        for i in begin..min(end, self.size) {
+            let bit = &mut self.bitmap[i];
+            if *bit != b && !self.holes[i] && !self.pinned[i] {
+                *bit = b;
+                if b { self.used += 1; } else { self.used -= 1; }
+            }
+        }
+    }
+}
+
+impl<P: FitPolicy + Clone> LinearBitMap<P> {
+    /// Save the whole allocator state, including that of the policy.
+    pub fn snapshot(&self) -> Self { self.clone() }
+
+    /// Roll back to the state saved by snapshot.
+    pub fn restore(&mut self, snapshot: &Self) { self.clone_from(snapshot) }
+}
+
+impl<P: FitPolicy + fmt::Debug> fmt::Debug for LinearBitMap<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        crate::debug_summary(self, "LinearBitMap", &self.policy, f)
+    }
+}
+
+impl<P: FitPolicy> fmt::Display for LinearBitMap<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.visualize().fmt(f)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<P: FitPolicy> serde::Serialize for LinearBitMap<P> {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut words = [0; MAX_LEN / 64];
+        let n = self.copy_words_into(&mut words);
+        crate::serde_impl::serialize(self.size, &words[..n], s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, P: FitPolicy + Default> serde::Deserialize<'de> for LinearBitMap<P> {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        crate::serde_impl::deserialize(d, |size| Self::with_policy(size, P::default()))
+    }
+}
+
+impl<P: FitPolicy + Default> BitAllocatorInit for LinearBitMap<P> {
+    fn new(size: usize) -> Self {
+        Self::with_policy(size, P::default())
+    }
+}
+
+impl<P: FitPolicy> BitAllocator for LinearBitMap<P> {
     fn alloc(&mut self, n: usize) -> Option<usize> {
         assert!(0 < n && n <= self.size);
+        if !P::FIRST_FIT {
+            return self.alloc_placed(n, 1, 0);
+        }
         // fast path
         if n == 1 {
             return self.alloc_1();
@@ -47,7 +361,7 @@ impl BitAllocator for LinearBitMap {
             let mut end = begin + 1;
             while end < self.size && !bm[end] { end += 1; }
             if end - begin >= n {
-                for i in begin..begin+n { bm[i] = true; }
+                self.set_range(begin, begin + n, true);
                 return Some(begin);
             }
             begin = end;
@@ -57,19 +371,21 @@ impl BitAllocator for LinearBitMap {
 
     fn alloc_aligned(&mut self, n: usize, align: usize) -> Option<usize> {
         assert!(0 < n && n <= self.size);
-        assert!(align >= 1 && align & (align-1) == 0); // alignment must be a power of 2
-        let align_mask = align - 1;
+        assert!(align >= 1);
+        if !P::FIRST_FIT {
+            return self.alloc_placed(n, align, 0);
+        }
         let bm = &mut self.bitmap;
         let mut begin = 0;
         while begin < self.size {
             while begin < self.size && bm[begin] { begin += 1; }
-            begin = (begin + align - 1) & !align_mask; // x >= begin s.t. x is aligned
+            begin += (align - begin % align) % align; // x >= begin s.t. x is aligned
             if begin >= self.size { break; }
             if bm[begin] { continue; } // advance to allocated position
             let mut end = begin + 1;
             while end < self.size && !bm[end] { end += 1; }
             if end - begin >= n {
-                for i in begin..begin+n { bm[i] = true; }
+                self.set_range(begin, begin + n, true);
                 return Some(begin);
             }
             begin = end;
@@ -77,10 +393,59 @@ impl BitAllocator for LinearBitMap {
         None
     }
 
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        if offset == 0 {
+            return self.alloc_aligned(n, align);
+        }
+        assert!(0 < n && n <= self.size);
+        assert!(offset < align);
+        self.alloc_placed(n, align, offset)
+    }
+
     fn dealloc(&mut self, begin: usize, n: usize) {
-        let bm = &mut self.bitmap;
-        for i in begin..begin+n {
-            bm[i] = false;
+        self.set_range(begin, begin + n, false);
+    }
+
+    fn set_all(&mut self, allocated: bool) {
+        for i in 0..self.size {
+            self.bitmap[i] = allocated || self.holes[i] || self.pinned[i];
         }
+        self.used = if allocated { self.size } else { self.hole_count + self.pinned_count };
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.set_range(begin, begin + n, true);
     }
+
+    fn size(&self) -> usize { self.size }
+
+    fn capacity(&self) -> usize { MAX_LEN }
+
+    fn free_count(&self) -> usize { self.size - self.used }
+
+    fn is_allocated(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.bitmap[index]
+    }
+
+    fn is_hole(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.holes[index]
+    }
+
+    fn hole_count(&self) -> usize { self.hole_count }
+
+    fn is_bad(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.bad[index]
+    }
+
+    fn bad_count(&self) -> usize { self.bad_count }
+
+    fn is_pinned(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.pinned[index]
+    }
+
+    fn pinned_count(&self) -> usize { self.pinned_count }
 }
@@ -0,0 +1,118 @@
+//! Planning the moves that defragment a bitmap.
+
+use core::cmp::min;
+use crate::{BitAllocator, Extent};
+
+/// Copying len bits' worth of data from the bits at from to the free bits at
+/// to, after which the former are freed and the latter allocated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Move {
+    /// First bit of the allocated range to move.
+    pub from: usize,
+    /// First bit of the free range to move it to.
+    pub to: usize,
+    /// Number of bits.
+    pub len: usize,
+}
+
+/// The moves that make a free run of a given number of bits, in an order
+/// in which they can be carried out one after the other.
+///
+/// The run is made in the window of that many bits that holds the fewest
+/// allocated bits and neither holes nor pinned bits, the lowest one on a
+/// tie, so as few bits as possible are moved. They go to the lowest free
+/// bits outside the window, and an allocated run is split where it does not
+/// fit into a single free run. If no such window exists, or there are not
+/// enough free bits, the plan is empty and target is `None`.
+///
+/// Created by [`BitAllocator::plan_compaction`].
+pub struct CompactionPlan<'a, T: ?Sized> {
+    bm: &'a T,
+    planner: Planner,
+}
+
+impl<'a, T: BitAllocator + ?Sized> CompactionPlan<'a, T> {
+    pub(crate) fn new(bm: &'a T, goal: usize) -> Self {
+        CompactionPlan { bm, planner: Planner::new(bm, goal) }
+    }
+
+    /// The free run the plan makes, if it can be made.
+    pub fn target(&self) -> Option<Extent> { self.planner.target() }
+}
+
+impl<'a, T: BitAllocator + ?Sized> Iterator for CompactionPlan<'a, T> {
+    type Item = Move;
+
+    fn next(&mut self) -> Option<Move> { self.planner.next_move(self.bm) }
+}
+
+/// The state of a [`CompactionPlan`], apart from the bitmap, so the moves can
+/// also be carried out as they are planned.
+///
+/// Carrying out a move only changes bits that both cursors are past.
+pub(crate) struct Planner {
+    begin: usize, // The window, empty if there is none.
+    end: usize,
+    src: usize, // Where to look for the next bits to move.
+    dst: usize, // Where to look for the next free bits to move them to.
+}
+
+impl Planner {
+    pub(crate) fn new<T: BitAllocator + ?Sized>(bm: &T, goal: usize) -> Self {
+        assert!(goal > 0);
+        let begin = window(bm, goal);
+        let (begin, end) = begin.map_or((0, 0), |b| (b, b + goal));
+        Planner { begin, end, src: begin, dst: 0 }
+    }
+
+    pub(crate) fn target(&self) -> Option<Extent> {
+        if self.begin < self.end { Some(Extent { begin: self.begin, len: self.end - self.begin }) } else { None }
+    }
+
+    pub(crate) fn next_move<T: BitAllocator + ?Sized>(&mut self, bm: &T) -> Option<Move> {
+        let from = bm.find_next(self.src, true);
+        if from >= self.end { return None; }
+        let mut to = bm.find_next(self.dst, false);
+        if self.begin <= to && to < self.end { to = bm.find_next(self.end, false); }
+        if to >= bm.size() { return None; }
+        let mut to_end = bm.find_next(to, true);
+        if to < self.begin { to_end = min(to_end, self.begin); }
+        let len = min(min(bm.find_next(from, false), self.end) - from, to_end - to);
+        self.src = from + len;
+        self.dst = to + len;
+        Some(Move { from, to, len })
+    }
+}
+
+/// Length of the longest free run that moving allocated bits can make: every
+/// free bit, unless holes or pinned bits are in the way.
+pub(crate) fn largest_goal<T: BitAllocator + ?Sized>(bm: &T) -> usize {
+    let (mut longest, mut len) = (0, 0);
+    for i in 0..bm.size() {
+        len = if bm.is_hole(i) || bm.is_pinned(i) { 0 } else { len + 1 };
+        longest = core::cmp::max(longest, len);
+    }
+    min(longest, bm.free_count())
+}
+
+/// Lowest begin of the goal bits to free by moving the fewest allocated bits
+/// elsewhere, if there is room for them.
+fn window<T: BitAllocator + ?Sized>(bm: &T, goal: usize) -> Option<usize> {
+    let size = bm.size();
+    if goal > size || goal > bm.free_count() { return None; }
+    let fixed = |i| bm.is_hole(i) || bm.is_pinned(i);
+    let (mut used, mut fixed_count) = (0, 0);
+    let mut best: Option<(usize, usize)> = None; // (used, begin)
+    for i in 0..size {
+        used += bm.is_allocated(i) as usize;
+        fixed_count += fixed(i) as usize;
+        if i >= goal {
+            used -= bm.is_allocated(i - goal) as usize;
+            fixed_count -= fixed(i - goal) as usize;
+        }
+        if i + 1 >= goal && fixed_count == 0 && best.map_or(true, |(u, _)| used < u) {
+            best = Some((used, i + 1 - goal));
+        }
+    }
+    best.map(|(_, begin)| begin)
+}
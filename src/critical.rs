@@ -0,0 +1,66 @@
+//! A bitmap allocator guarded by critical sections, enabled by the
+//! `critical-section` feature.
+
+use core::cell::RefCell;
+use critical_section::Mutex;
+use crate::{AllocError, BitAllocator};
+use crate::bitalloc::LinearBitMap;
+
+/// A `bitalloc::LinearBitMap` that is only touched inside
+/// `critical_section::with`, so it can live in a `static` and be used both
+/// from threads and from interrupt handlers, e.g. on single-core
+/// microcontrollers where a critical section masks interrupts.
+///
+/// Every method runs one operation in its own critical section. Use with to
+/// run several operations in one.
+pub struct CriticalSectionBitMap {
+    inner: Mutex<RefCell<LinearBitMap>>,
+}
+
+impl CriticalSectionBitMap {
+    /// Create a bitmap with size bits. By default, all bits are unallocated.
+    pub const fn new(size: usize) -> Self {
+        CriticalSectionBitMap { inner: Mutex::new(RefCell::new(LinearBitMap::new(size))) }
+    }
+
+    /// Run f on the bitmap in a critical section.
+    ///
+    /// Panics if f calls back into this bitmap.
+    pub fn with<R, F: FnOnce(&mut LinearBitMap) -> R>(&self, f: F) -> R {
+        critical_section::with(|cs| f(&mut self.inner.borrow_ref_mut(cs)))
+    }
+
+    /// Unwrap the bitmap.
+    pub fn into_inner(self) -> LinearBitMap { self.inner.into_inner().into_inner() }
+
+    /// Like `BitAllocator::alloc`.
+    pub fn alloc(&self, n: usize) -> Option<usize> { self.with(|bm| bm.alloc(n)) }
+
+    /// Like `BitAllocator::alloc_aligned`.
+    pub fn alloc_aligned(&self, n: usize, alignment: usize) -> Option<usize> {
+        self.with(|bm| bm.alloc_aligned(n, alignment))
+    }
+
+    /// Like `BitAllocator::try_alloc`.
+    pub fn try_alloc(&self, n: usize) -> Result<usize, AllocError> { self.with(|bm| bm.try_alloc(n)) }
+
+    /// Like `BitAllocator::dealloc`.
+    pub fn dealloc(&self, begin: usize, n: usize) { self.with(|bm| bm.dealloc(begin, n)) }
+
+    /// Like `BitAllocator::dealloc_checked`.
+    pub fn dealloc_checked(&self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.with(|bm| bm.dealloc_checked(begin, n))
+    }
+
+    /// Like `BitAllocator::mark`.
+    pub fn mark(&self, begin: usize, n: usize) { self.with(|bm| bm.mark(begin, n)) }
+
+    /// Like `BitAllocator::size`.
+    pub fn size(&self) -> usize { self.with(|bm| bm.size()) }
+
+    /// Like `BitAllocator::is_allocated`.
+    pub fn is_allocated(&self, index: usize) -> bool { self.with(|bm| bm.is_allocated(index)) }
+
+    /// Like `BitAllocator::free_count`, as of the critical section.
+    pub fn free_count(&self) -> usize { self.with(|bm| bm.free_count()) }
+}
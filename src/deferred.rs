@@ -0,0 +1,104 @@
+//! A lock-free queue of frees to apply later, for interrupt handlers.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::{AllocError, BitAllocator};
+
+struct Slot {
+    // pos while free for the push at pos, pos + 1 once that push is written.
+    seq: AtomicUsize,
+    begin: AtomicUsize,
+    n: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: Slot = Slot { seq: AtomicUsize::new(0), begin: AtomicUsize::new(0), n: AtomicUsize::new(0) };
+
+/// A bounded queue of up to N pending frees, each `(begin, n)`, that any
+/// context can push to without a lock, e.g. an interrupt handler that must
+/// not take the lock of the allocator.
+///
+/// The frees are applied by drain_deferred_frees, from a context allowed to
+/// use the allocator. Neither pushing nor draining ever waits for another
+/// core: a push that was interrupted halfway is simply left for the next
+/// drain. N must be a power of two.
+pub struct FreeQueue<const N: usize = 64> {
+    slots: [Slot; N],
+    enqueue: AtomicUsize, // Position of the next push.
+    dequeue: AtomicUsize, // Position of the next free to apply.
+}
+
+impl<const N: usize> Default for FreeQueue<N> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<const N: usize> FreeQueue<N> {
+    /// Create an empty queue.
+    pub const fn new() -> Self {
+        assert!(N.is_power_of_two());
+        let mut slots = [EMPTY; N];
+        let mut i = 0;
+        while i < N {
+            slots[i].seq = AtomicUsize::new(i);
+            i += 1;
+        }
+        FreeQueue { slots, enqueue: AtomicUsize::new(0), dequeue: AtomicUsize::new(0) }
+    }
+
+    /// Queue the free of the n bits at begin.
+    ///
+    /// Fails with `AllocError::OutOfSpace` if N frees are already pending.
+    pub fn push(&self, begin: usize, n: usize) -> Result<(), AllocError> {
+        let mut pos = self.enqueue.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % N];
+            let lag = slot.seq.load(Ordering::Acquire).wrapping_sub(pos) as isize;
+            if lag < 0 { return Err(AllocError::OutOfSpace); }
+            if lag > 0 {
+                pos = self.enqueue.load(Ordering::Relaxed);
+                continue;
+            }
+            match self.enqueue.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => {
+                    slot.begin.store(begin, Ordering::Relaxed);
+                    slot.n.store(n, Ordering::Relaxed);
+                    slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                    return Ok(());
+                }
+                Err(now) => pos = now,
+            }
+        }
+    }
+
+    /// Take the oldest pending free whose push has completed.
+    pub fn pop(&self) -> Option<(usize, usize)> {
+        let mut pos = self.dequeue.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % N];
+            let lag = slot.seq.load(Ordering::Acquire).wrapping_sub(pos.wrapping_add(1)) as isize;
+            if lag < 0 { return None; }
+            if lag > 0 {
+                pos = self.dequeue.load(Ordering::Relaxed);
+                continue;
+            }
+            match self.dequeue.compare_exchange_weak(pos, pos.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => {
+                    let free = (slot.begin.load(Ordering::Relaxed), slot.n.load(Ordering::Relaxed));
+                    slot.seq.store(pos.wrapping_add(N), Ordering::Release);
+                    return Some(free);
+                }
+                Err(now) => pos = now,
+            }
+        }
+    }
+
+    /// Apply the pending frees to bm, oldest first, and return how many
+    /// there were.
+    pub fn drain_deferred_frees<A: BitAllocator + ?Sized>(&self, bm: &mut A) -> usize {
+        let mut count = 0;
+        while let Some((begin, n)) = self.pop() {
+            bm.dealloc(begin, n);
+            count += 1;
+        }
+        count
+    }
+}
@@ -0,0 +1,131 @@
+//! Frees that only take effect once a grace period has passed.
+
+use crate::{BitAllocator, MAX_BITS};
+
+/// Wraps an allocator so that bits can be freed with a delay, e.g. frames
+/// whose old mappings may still be cached in the TLBs of other cores.
+///
+/// Bits freed with dealloc_deferred stay allocated in the wrapped allocator,
+/// so they are not handed out again, until advance_epoch is called, which
+/// the user does once the grace period is over, e.g. after a TLB shootdown.
+/// Only then are they freed. Plain dealloc frees at once, as usual.
+pub struct EpochAllocator<A> {
+    inner: A,
+    pending: [bool; MAX_BITS],
+    pending_count: usize,
+    epoch: u64,
+}
+
+impl<A: BitAllocator> EpochAllocator<A> {
+    /// Wrap inner, with no frees pending.
+    ///
+    /// Panics if the size of inner exceeds the capacity.
+    pub fn new(inner: A) -> Self {
+        assert!(inner.size() <= MAX_BITS);
+        EpochAllocator { inner, pending: [false; MAX_BITS], pending_count: 0, epoch: 0 }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator. Pending frees are dropped, leaving their bits
+    /// allocated.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// Number of times advance_epoch was called.
+    pub fn epoch(&self) -> u64 { self.epoch }
+
+    /// Whether the index-th bit waits for the end of the epoch to be freed.
+    ///
+    /// Panics if index is not smaller than the size.
+    pub fn is_pending(&self, index: usize) -> bool {
+        assert!(index < self.inner.size());
+        self.pending[index]
+    }
+
+    /// Number of bits waiting for the end of the epoch to be freed.
+    pub fn pending_count(&self) -> usize { self.pending_count }
+
+    /// Free the bits [begin, begin+n) at the next advance_epoch, keeping
+    /// them allocated until then.
+    ///
+    /// Panics if the range reaches past the end of the bitmap.
+    pub fn dealloc_deferred(&mut self, begin: usize, n: usize) {
+        assert!(begin + n <= self.inner.size());
+        for i in begin..begin + n {
+            if !self.pending[i] && self.inner.is_allocated(i) {
+                self.pending[i] = true;
+                self.pending_count += 1;
+            }
+        }
+    }
+
+    /// End the epoch: free every bit passed to dealloc_deferred since the
+    /// last call, and return how many there were.
+    pub fn advance_epoch(&mut self) -> usize {
+        let freed = self.pending_count;
+        let mut i = 0;
+        while self.pending_count > 0 {
+            if !self.pending[i] {
+                i += 1;
+                continue;
+            }
+            let begin = i;
+            while i < self.inner.size() && self.pending[i] { i += 1; }
+            self.unpend(begin, i);
+            self.inner.dealloc(begin, i - begin);
+        }
+        self.epoch += 1;
+        freed
+    }
+
+    // Forget the pending frees of the bits [begin, end).
+    fn unpend(&mut self, begin: usize, end: usize) {
+        for p in &mut self.pending[begin..end] {
+            if *p {
+                *p = false;
+                self.pending_count -= 1;
+            }
+        }
+    }
+}
+
+impl<A: BitAllocator> BitAllocator for EpochAllocator<A> {
+    fn alloc(&mut self, n: usize) -> Option<usize> { self.inner.alloc(n) }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        self.inner.alloc_aligned(n, alignment)
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        self.inner.alloc_aligned_offset(n, align, offset)
+    }
+
+    /// Free the bits at once, cancelling any pending free of them.
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.inner.dealloc(begin, n);
+        self.unpend(begin, begin + n);
+    }
+
+    /// Mark the bits allocated, cancelling any pending free of them.
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.inner.mark(begin, n);
+        self.unpend(begin, begin + n);
+    }
+
+    /// Free the ranges at once, cancelling any pending free of them.
+    fn dealloc_batch(&mut self, ranges: &[(usize, usize)]) {
+        self.inner.dealloc_batch(ranges);
+        for &(begin, n) in ranges { self.unpend(begin, begin + n); }
+    }
+
+    /// Set every bit, cancelling all pending frees.
+    fn set_all(&mut self, allocated: bool) {
+        self.inner.set_all(allocated);
+        self.unpend(0, self.inner.size());
+    }
+
+    // Bits with a pending free are still allocated, so alloc_at and reserve
+    // fail on them and need not cancel anything.
+    forward_bit_allocator!(alloc_at, reserve);
+}
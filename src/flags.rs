@@ -0,0 +1,165 @@
+//! Per-bit dirty and accessed flags kept alongside an allocator.
+
+use crate::{AllocError, BitAllocator, MAX_BITS, ReserveConflict};
+
+/// Which flag of a [`FlagTracker`] an operation is about.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Flag {
+    /// Set when the memory behind a bit has been written, e.g. for
+    /// writeback.
+    Dirty,
+    /// Set when the memory behind a bit has been used, e.g. for page
+    /// replacement.
+    Accessed,
+}
+
+/// Wraps an allocator with a dirty and an accessed flag for every bit, in
+/// the same index space.
+///
+/// Both flags of a bit are cleared whenever it is allocated, so a fresh
+/// allocation never inherits the flags of a previous owner; in between, only
+/// the user sets and clears them. Allocate through the tracker rather than
+/// through the wrapped allocator to keep the flags in step.
+pub struct FlagTracker<A> {
+    inner: A,
+    dirty: [bool; MAX_BITS],
+    accessed: [bool; MAX_BITS],
+}
+
+impl<A: BitAllocator> FlagTracker<A> {
+    /// Wrap inner, with every flag clear.
+    ///
+    /// Panics if the size of inner exceeds the capacity.
+    pub fn new(inner: A) -> Self {
+        assert!(inner.size() <= MAX_BITS);
+        FlagTracker { inner, dirty: [false; MAX_BITS], accessed: [false; MAX_BITS] }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator, forgetting all flags.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// Whether flag is set for the index-th bit.
+    ///
+    /// Panics if index is not smaller than the size.
+    pub fn get(&self, flag: Flag, index: usize) -> bool {
+        assert!(index < self.inner.size());
+        self.flags(flag)[index]
+    }
+
+    /// Set flag for the bits [begin, begin+n).
+    ///
+    /// Panics if the range reaches past the end of the bitmap.
+    pub fn set(&mut self, flag: Flag, begin: usize, n: usize) {
+        self.set_range(flag, begin, n, true);
+    }
+
+    /// Clear flag for the bits [begin, begin+n).
+    ///
+    /// Panics if the range reaches past the end of the bitmap.
+    pub fn unset(&mut self, flag: Flag, begin: usize, n: usize) {
+        self.set_range(flag, begin, n, false);
+    }
+
+    /// Clear flag for the index-th bit, returning whether it was set, as a
+    /// clock sweep does with accessed flags.
+    ///
+    /// Panics if index is not smaller than the size.
+    pub fn take(&mut self, flag: Flag, index: usize) -> bool {
+        let was = self.get(flag, index);
+        self.set_range(flag, index, 1, false);
+        was
+    }
+
+    /// Iterate over the maximal runs of bits for which flag is set, in
+    /// increasing order, as `(begin, len)` pairs.
+    pub fn iter_ranges(&self, flag: Flag) -> FlagRuns<'_> {
+        FlagRuns { flags: &self.flags(flag)[..self.inner.size()], pos: 0 }
+    }
+
+    fn flags(&self, flag: Flag) -> &[bool; MAX_BITS] {
+        match flag {
+            Flag::Dirty => &self.dirty,
+            Flag::Accessed => &self.accessed,
+        }
+    }
+
+    fn set_range(&mut self, flag: Flag, begin: usize, n: usize, b: bool) {
+        assert!(begin + n <= self.inner.size());
+        let flags = match flag {
+            Flag::Dirty => &mut self.dirty,
+            Flag::Accessed => &mut self.accessed,
+        };
+        for f in &mut flags[begin..begin + n] { *f = b; }
+    }
+
+    // Clear both flags of the freshly allocated bits [begin, begin+n).
+    fn allocated(&mut self, begin: Option<usize>, n: usize) -> Option<usize> {
+        let begin = begin?;
+        self.set_range(Flag::Dirty, begin, n, false);
+        self.set_range(Flag::Accessed, begin, n, false);
+        Some(begin)
+    }
+}
+
+impl<A: BitAllocator> BitAllocator for FlagTracker<A> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        let begin = self.inner.alloc(n);
+        self.allocated(begin, n)
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        let begin = self.inner.alloc_aligned(n, alignment);
+        self.allocated(begin, n)
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        let begin = self.inner.alloc_aligned_offset(n, align, offset);
+        self.allocated(begin, n)
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.inner.mark(begin, n);
+        self.allocated(Some(begin), n);
+    }
+
+    fn set_all(&mut self, allocated: bool) {
+        self.inner.set_all(allocated);
+        if allocated { self.allocated(Some(0), self.inner.size()); }
+    }
+
+    fn alloc_at(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.inner.alloc_at(begin, n)?;
+        self.allocated(Some(begin), n);
+        Ok(())
+    }
+
+    fn reserve(&mut self, begin: usize, n: usize) -> Result<(), ReserveConflict> {
+        self.inner.reserve(begin, n)?;
+        self.allocated(Some(begin), n);
+        Ok(())
+    }
+
+    forward_bit_allocator!(dealloc, dealloc_batch);
+}
+
+/// Iterator over maximal runs of bits with a flag set.
+///
+/// Created by [`FlagTracker::iter_ranges`].
+pub struct FlagRuns<'a> {
+    flags: &'a [bool],
+    pos: usize,
+}
+
+impl<'a> Iterator for FlagRuns<'a> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        let begin = self.pos + self.flags[self.pos..].iter().position(|&f| f)?;
+        let len = self.flags[begin..].iter().position(|&f| !f).unwrap_or(self.flags.len() - begin);
+        self.pos = begin + len;
+        Some((begin, len))
+    }
+}
@@ -0,0 +1,91 @@
+//! Handle-based allocation that catches stale and duplicate frees.
+
+use core::marker::PhantomData;
+use crate::{AllocError, BitAllocator, BitIndex, MAX_BITS};
+
+/// An opaque reference to bits allocated through a [`HandleAllocator`].
+///
+/// A handle stays valid until it is passed to
+/// [`HandleAllocator::dealloc_handle`]; after that it, and every copy of it,
+/// is stale, even if the same bits are allocated again.
+///
+/// Indices are stored as I, so handles into small bitmaps can be made
+/// smaller by choosing a narrow index type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AllocHandle<I = usize> {
+    index: I,
+    generation: u32,
+    len: I,
+}
+
+impl<I: BitIndex> AllocHandle<I> {
+    /// Index of the first allocated bit.
+    pub fn index(&self) -> usize { self.index.to_usize() }
+
+    /// How many times the first bit had been freed through a handle when
+    /// this handle was created.
+    pub fn generation(&self) -> u32 { self.generation }
+}
+
+/// Wraps an allocator so that allocations are made and freed through
+/// [`AllocHandle`]s.
+///
+/// Every bit carries a generation counter that is bumped when an allocation
+/// beginning at it is freed, so a handle whose generation no longer matches
+/// was already freed.
+pub struct HandleAllocator<A, I = usize> {
+    inner: A,
+    generations: [u32; MAX_BITS],
+    index: PhantomData<I>,
+}
+
+impl<A: BitAllocator> HandleAllocator<A> {
+    /// Wrap inner, which must not have been allocated from yet.
+    pub fn new(inner: A) -> Self {
+        Self::with_index_type(inner)
+    }
+}
+
+impl<A: BitAllocator, I: BitIndex> HandleAllocator<A, I> {
+    /// Like new, but the handles store indices as I.
+    ///
+    /// Panics if the size of inner exceeds what I can hold.
+    pub fn with_index_type(inner: A) -> Self {
+        assert!(inner.size() <= MAX_BITS && inner.size() <= I::MAX);
+        HandleAllocator { inner, generations: [0; MAX_BITS], index: PhantomData }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator, forgetting all generations.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// Allocate n consecutive bits, like `BitAllocator::alloc`.
+    pub fn alloc(&mut self, n: usize) -> Option<AllocHandle<I>> {
+        let index = self.inner.alloc(n)?;
+        Some(AllocHandle {
+            index: I::from_usize(index),
+            generation: self.generations[index],
+            len: I::from_usize(n),
+        })
+    }
+
+    /// Whether handle has not been freed yet.
+    pub fn is_live(&self, handle: AllocHandle<I>) -> bool {
+        let index = handle.index();
+        index < self.inner.size() && self.generations[index] == handle.generation
+    }
+
+    /// Free the bits behind handle. Fails, without changing anything, if the
+    /// handle is stale.
+    pub fn dealloc_handle(&mut self, handle: AllocHandle<I>) -> Result<(), AllocError> {
+        if !self.is_live(handle) {
+            return Err(AllocError::StaleHandle);
+        }
+        let generation = &mut self.generations[handle.index()];
+        *generation = generation.wrapping_add(1);
+        self.inner.dealloc(handle.index(), handle.len.to_usize());
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! The peak number of allocated bits, for sizing memory.
+
+use crate::{AllocError, BitAllocator, ReserveConflict};
+
+/// Wraps an allocator to track the largest number of bits allocated at the
+/// same time, e.g. to find out how much memory an embedded product really
+/// needs from a test run under load.
+///
+/// Holes are not counted, like in used_count.
+pub struct HighWater<A> {
+    inner: A,
+    peak: usize,
+}
+
+impl<A: BitAllocator> HighWater<A> {
+    /// Wrap inner. The bits already allocated count towards the high-water
+    /// mark.
+    pub fn new(inner: A) -> Self {
+        let peak = inner.used_count();
+        HighWater { inner, peak }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// Largest number of bits that were allocated at once since the wrapper
+    /// was created or the mark reset.
+    pub fn high_water_mark(&self) -> usize { self.peak }
+
+    /// Start tracking anew from the number of bits allocated now.
+    pub fn reset_high_water_mark(&mut self) {
+        self.peak = self.inner.used_count();
+    }
+
+    fn update(&mut self) {
+        self.peak = core::cmp::max(self.peak, self.inner.used_count());
+    }
+
+    fn tracked<F: FnOnce(&mut A) -> Option<usize>>(&mut self, f: F) -> Option<usize> {
+        let begin = f(&mut self.inner);
+        self.update();
+        begin
+    }
+}
+
+impl<A: BitAllocator> BitAllocator for HighWater<A> {
+    fn alloc(&mut self, n: usize) -> Option<usize> { self.tracked(|a| a.alloc(n)) }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        self.tracked(|a| a.alloc_aligned(n, alignment))
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        self.tracked(|a| a.alloc_aligned_offset(n, align, offset))
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.inner.mark(begin, n);
+        self.update();
+    }
+
+    fn set_all(&mut self, allocated: bool) {
+        self.inner.set_all(allocated);
+        self.update();
+    }
+
+    fn alloc_at(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        let result = self.inner.alloc_at(begin, n);
+        self.update();
+        result
+    }
+
+    fn reserve(&mut self, begin: usize, n: usize) -> Result<(), ReserveConflict> {
+        let result = self.inner.reserve(begin, n);
+        self.update();
+        result
+    }
+
+    forward_bit_allocator!(dealloc, dealloc_batch);
+}
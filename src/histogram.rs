@@ -0,0 +1,64 @@
+//! A histogram of requested allocation sizes, enabled by the `histogram`
+//! feature.
+
+use crate::{BitAllocator, MAX_BITS};
+
+/// Buckets of a [`SizeHistogram`]: enough for requests of up to MAX_BITS
+/// bits.
+pub const BUCKETS: usize = (usize::BITS - MAX_BITS.leading_zeros()) as usize;
+
+/// Wraps an allocator to count the allocation requests made through it by
+/// size, e.g. to pick the policy that suits the sizes a kernel really asks
+/// for.
+///
+/// Bucket k counts the requests for n bits with `2^k <= n < 2^(k+1)`,
+/// whether they succeeded or not; the last bucket also counts larger ones.
+pub struct SizeHistogram<A> {
+    inner: A,
+    counts: [u64; BUCKETS],
+}
+
+impl<A: BitAllocator> SizeHistogram<A> {
+    /// Wrap inner, with every count zero.
+    pub fn new(inner: A) -> Self {
+        SizeHistogram { inner, counts: [0; BUCKETS] }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator, dropping the counts.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// Number of requests in every bucket.
+    pub fn histogram(&self) -> &[u64; BUCKETS] { &self.counts }
+
+    /// Set every count to zero.
+    pub fn reset_histogram(&mut self) {
+        self.counts = [0; BUCKETS];
+    }
+
+    fn count(&mut self, n: usize) {
+        let bucket = (usize::BITS - 1).saturating_sub(n.leading_zeros()) as usize;
+        self.counts[core::cmp::min(bucket, BUCKETS - 1)] += 1;
+    }
+}
+
+impl<A: BitAllocator> BitAllocator for SizeHistogram<A> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        self.count(n);
+        self.inner.alloc(n)
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        self.count(n);
+        self.inner.alloc_aligned(n, alignment)
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        self.count(n);
+        self.inner.alloc_aligned_offset(n, align, offset)
+    }
+
+    forward_bit_allocator!(dealloc, mark, dealloc_batch, set_all, alloc_at, reserve);
+}
@@ -0,0 +1,205 @@
+//! A bitmap paired with a short list of its largest free extents.
+
+use core::cmp::max;
+use crate::{AllocError, BitAllocator, Extent, ReserveConflict};
+
+/// Free extents remembered by a [`HybridBitMap`].
+const EXTENTS: usize = 8;
+
+/// Wraps an allocator, which stays the ground truth, with a list of its few
+/// largest free extents, so that large requests are answered without a
+/// scan.
+///
+/// The list comes with a bound on the length of every free run not in it. A
+/// request of more bits than the bound can only fit in a listed extent, so it
+/// takes the lowest listed extent that fits, which is where a first-fit scan
+/// would have put it. Smaller requests go to the wrapped allocator. The
+/// bound grows when runs change length unseen, e.g. when the wrapped
+/// allocator carves a request out of an unlisted run; the list is then
+/// rebuilt by a scan when a request within the bound is longer than the
+/// shortest listed extent, as a rebuilt list could then answer it.
+///
+/// With a first-fit allocator inside, results are those of first fit.
+pub struct HybridBitMap<A> {
+    inner: A,
+    extents: [Extent; EXTENTS],
+    len: usize,   // Extents in use, in no particular order.
+    bound: usize, // No unlisted free run is longer.
+    stale: bool,  // Whether bound may be loose.
+}
+
+impl<A: BitAllocator> HybridBitMap<A> {
+    /// Wrap inner, scanning it for its largest free extents.
+    pub fn new(inner: A) -> Self {
+        let mut bm = HybridBitMap {
+            inner,
+            extents: [Extent::default(); EXTENTS],
+            len: 0,
+            bound: 0,
+            stale: false,
+        };
+        bm.rebuild();
+        bm
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator, forgetting the extents.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// The listed free extents, in no particular order.
+    pub fn extents(&self) -> &[Extent] { &self.extents[..self.len] }
+
+    /// Rescan the wrapped allocator for its largest free extents.
+    pub fn rebuild(&mut self) {
+        self.len = 0;
+        self.bound = 0;
+        let mut pos = 0;
+        while pos < self.inner.size() {
+            let begin = self.inner.find_next(pos, false);
+            pos = self.inner.find_next(begin, true);
+            self.insert(Extent { begin, len: pos - begin });
+        }
+        self.stale = false;
+    }
+
+    // List e if it is among the longest, updating the bound with whatever
+    // is left out.
+    fn insert(&mut self, e: Extent) {
+        if e.len == 0 { return; }
+        if self.len < EXTENTS {
+            self.extents[self.len] = e;
+            self.len += 1;
+            return;
+        }
+        let shortest = (0..self.len).min_by_key(|&i| self.extents[i].len).unwrap();
+        let out = if e.len > self.extents[shortest].len {
+            core::mem::replace(&mut self.extents[shortest], e)
+        } else {
+            e
+        };
+        self.bound = max(self.bound, out.len);
+    }
+
+    fn remove(&mut self, i: usize) -> Extent {
+        let e = self.extents[i];
+        self.len -= 1;
+        self.extents[i] = self.extents[self.len];
+        e
+    }
+
+    // The bits [begin, end) were allocated: cut them out of the listed
+    // extents. Unlisted runs they were taken from can only have shrunk.
+    fn allocated(&mut self, begin: usize, end: usize) {
+        let mut i = 0;
+        let mut hit = false;
+        while i < self.len {
+            let e = self.extents[i];
+            if e.begin < end && begin < e.begin + e.len {
+                self.remove(i);
+                self.insert(Extent { begin: e.begin, len: begin.saturating_sub(e.begin) });
+                let tail = max(end, e.begin);
+                self.insert(Extent { begin: tail, len: (e.begin + e.len).saturating_sub(tail) });
+                hit = true;
+            } else {
+                i += 1;
+            }
+        }
+        if !hit { self.stale = true; }
+    }
+
+    // Take n bits from the lowest listed extent that holds them.
+    fn alloc_listed(&mut self, n: usize) -> Option<usize> {
+        let i = (0..self.len)
+            .filter(|&i| self.extents[i].len >= n)
+            .min_by_key(|&i| self.extents[i].begin)?;
+        let begin = self.extents[i].begin;
+        self.inner.mark(begin, n);
+        self.extents[i].begin += n;
+        self.extents[i].len -= n;
+        if self.extents[i].len == 0 { self.remove(i); }
+        Some(begin)
+    }
+}
+
+impl<A: BitAllocator> BitAllocator for HybridBitMap<A> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.inner.size());
+        // The wrapped allocator is right for any request within the bound,
+        // so a loose bound costs the extra scan by it only for requests
+        // that would otherwise come from the list.
+        let shortest = self.extents().iter().map(|e| e.len).min().unwrap_or(0);
+        if self.stale && n <= self.bound && n > shortest {
+            self.rebuild();
+        }
+        if n > self.bound {
+            return self.alloc_listed(n);
+        }
+        let begin = self.inner.alloc(n)?;
+        self.allocated(begin, begin + n);
+        Some(begin)
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        let begin = self.inner.alloc_aligned(n, alignment)?;
+        self.allocated(begin, begin + n);
+        Some(begin)
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        let begin = self.inner.alloc_aligned_offset(n, align, offset)?;
+        self.allocated(begin, begin + n);
+        Some(begin)
+    }
+
+    /// Free the bits, then list the free runs they are part of.
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.inner.dealloc(begin, n);
+        let start = self.inner.find_prev(begin, true).map_or(0, |i| i + 1);
+        let mut pos = start;
+        while pos < begin + n {
+            let run_begin = self.inner.find_next(pos, false);
+            if run_begin >= begin + n { break; }
+            let run_end = self.inner.find_next(run_begin, true);
+            let mut i = 0;
+            while i < self.len {
+                let e = self.extents[i];
+                if e.begin >= run_begin && e.begin < run_end { self.remove(i); } else { i += 1; }
+            }
+            self.insert(Extent { begin: run_begin, len: run_end - run_begin });
+            pos = run_end;
+        }
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.inner.mark(begin, n);
+        self.allocated(begin, begin + n);
+    }
+
+    /// Free the ranges in one call to the wrapped allocator, then rescan it
+    /// for its largest free extents.
+    fn dealloc_batch(&mut self, ranges: &[(usize, usize)]) {
+        self.inner.dealloc_batch(ranges);
+        self.rebuild();
+    }
+
+    fn set_all(&mut self, allocated: bool) {
+        self.inner.set_all(allocated);
+        self.rebuild();
+    }
+
+    fn alloc_at(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.inner.alloc_at(begin, n)?;
+        self.allocated(begin, begin + n);
+        Ok(())
+    }
+
+    fn reserve(&mut self, begin: usize, n: usize) -> Result<(), ReserveConflict> {
+        self.inner.reserve(begin, n)?;
+        self.allocated(begin, begin + n);
+        Ok(())
+    }
+
+    forward_bit_allocator!();
+}
@@ -0,0 +1,92 @@
+//! An allocator of single indices, such as PIDs, file descriptors or IRQ
+//! vectors.
+
+use crate::{BitAllocator, MAX_BITS};
+use crate::bitalloc::LinearBitMap;
+
+/// Freed ids remembered by an [`IdAlloc`].
+const RECYCLED: usize = 16;
+
+/// An allocator of ids in [0, size), each a single bit, in O(1) for the
+/// common cases.
+///
+/// Next to the bitmap, which stays the ground truth, it keeps a stack of
+/// recently freed ids and a high watermark below which every id handed out
+/// so far lies. An id is taken from the stack if it holds one, from the
+/// watermark otherwise, and only once both are used up by a scan of the
+/// bitmap, which finds the ids that spilled over when the stack was full.
+///
+/// Recently freed ids are therefore reused first, most recent first, and
+/// fresh ids are handed out in increasing order.
+pub struct IdAlloc {
+    map: LinearBitMap,
+    recycled: [usize; RECYCLED],
+    top: usize,       // Ids on the stack, from recycled[0].
+    watermark: usize, // Every id from here on has never been handed out.
+}
+
+impl IdAlloc {
+    /// Create an allocator of size ids, all free.
+    ///
+    /// Panics if size exceeds MAX_BITS.
+    pub const fn new(size: usize) -> Self {
+        assert!(size <= MAX_BITS);
+        IdAlloc { map: LinearBitMap::new(size), recycled: [0; RECYCLED], top: 0, watermark: 0 }
+    }
+
+    /// The bitmap of ids in use.
+    pub fn map(&self) -> &LinearBitMap { &self.map }
+
+    /// Number of ids.
+    pub fn size(&self) -> usize { self.map.size() }
+
+    /// Number of ids in use.
+    pub fn used_count(&self) -> usize { self.map.used_count() }
+
+    /// Whether id is in use.
+    ///
+    /// Panics if id is not smaller than the size.
+    pub fn is_allocated(&self, id: usize) -> bool { self.map.is_allocated(id) }
+
+    /// Allocate an id, or return `None` if all are in use.
+    pub fn alloc(&mut self) -> Option<usize> {
+        while self.top > 0 {
+            self.top -= 1;
+            let id = self.recycled[self.top];
+            // Skip ids taken by a scan or reserve since they were freed.
+            if !self.map.is_allocated(id) {
+                self.map.mark(id, 1);
+                return Some(id);
+            }
+        }
+        while self.watermark < self.map.size() {
+            let id = self.watermark;
+            self.watermark += 1;
+            if !self.map.is_allocated(id) {
+                self.map.mark(id, 1);
+                return Some(id);
+            }
+        }
+        if self.map.size() == 0 { return None; }
+        self.map.alloc(1)
+    }
+
+    /// Free id, to be handed out again before any other.
+    ///
+    /// Panics if id is not smaller than the size.
+    pub fn dealloc(&mut self, id: usize) {
+        self.map.dealloc(id, 1);
+        if id >= self.watermark { return; }
+        if self.top < RECYCLED {
+            self.recycled[self.top] = id;
+            self.top += 1;
+        }
+    }
+
+    /// Take id out of circulation, e.g. PID 0, whether or not it was in use.
+    ///
+    /// Panics if id is not smaller than the size.
+    pub fn reserve(&mut self, id: usize) {
+        self.map.mark(id, 1);
+    }
+}
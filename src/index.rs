@@ -0,0 +1,45 @@
+//! Integer types usable as bit indices in auxiliary structures.
+//!
+//! The allocators index bits with `usize` in their interface. Indices they
+//! store, such as the cursors of [`NextFit`](crate::NextFit), the first-free
+//! hint of `bitalloc::LinearBitMap` and the bits of an
+//! [`AllocHandle`](crate::AllocHandle), can be made a narrower type instead
+//! when the bitmap is small enough. The same
+//! types serve as per-bit counters, as in [`RefCountAlloc`](crate::RefCountAlloc).
+
+use core::convert::TryFrom;
+use core::fmt::Debug;
+use core::hash::Hash;
+
+/// An unsigned integer type holding bit indices and run lengths.
+pub trait BitIndex: Copy + Eq + Ord + Hash + Debug {
+    /// Largest value of the type, as a usize.
+    const MAX: usize;
+
+    /// Zero, for const initializers.
+    const ZERO: Self;
+
+    /// Convert from usize. Panics if i exceeds MAX.
+    fn from_usize(i: usize) -> Self;
+
+    /// Convert to usize.
+    fn to_usize(self) -> usize;
+}
+
+macro_rules! impl_bit_index {
+    ($($t:ty)*) => {$(
+        impl BitIndex for $t {
+            const MAX: usize = <$t>::MAX as usize;
+
+            const ZERO: Self = 0;
+
+            fn from_usize(i: usize) -> Self {
+                <$t>::try_from(i).expect("index does not fit the index type")
+            }
+
+            fn to_usize(self) -> usize { self as usize }
+        }
+    )*};
+}
+
+impl_bit_index!(u8 u16 u32 usize);
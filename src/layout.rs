@@ -0,0 +1,84 @@
+//! How bitmaps are laid out as bytes outside the allocator.
+
+/// Order of the bytes of a word.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Endian {
+    /// Least significant byte first.
+    Little,
+    /// Most significant byte first.
+    Big,
+}
+
+impl Endian {
+    /// The byte order of the target.
+    #[cfg(target_endian = "little")]
+    pub const NATIVE: Endian = Endian::Little;
+    /// The byte order of the target.
+    #[cfg(target_endian = "big")]
+    pub const NATIVE: Endian = Endian::Big;
+}
+
+/// Which bit of a word holds the first bit of the bitmap it covers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BitOrder {
+    /// The least significant bit.
+    LsbFirst,
+    /// The most significant bit.
+    MsbFirst,
+}
+
+/// A byte layout of a bitmap, for importing and exporting it.
+///
+/// The bitmap is split into words of `word_bytes` bytes, each holding the
+/// next `8 * word_bytes` bits in bit_order and stored in endian order. A
+/// bitmap whose size is not a multiple of the word size is padded with clear
+/// bits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// Bytes per word. Must not be zero.
+    pub word_bytes: usize,
+    /// Order of the bytes of a word.
+    pub endian: Endian,
+    /// Order of the bits of a word.
+    pub bit_order: BitOrder,
+}
+
+impl Layout {
+    /// The bytes of the words of `bitalloc::LinearBitMap::as_words`, stored
+    /// little-endian regardless of the host.
+    pub const LE64: Layout = Layout::new(8, Endian::Little, BitOrder::LsbFirst);
+
+    /// Bytes with the first bit in the least significant bit, as in ext2/ext4
+    /// block and inode bitmaps.
+    pub const EXT2: Layout = Layout::new(1, Endian::Little, BitOrder::LsbFirst);
+
+    /// The NTFS `$Bitmap` cluster bitmap: bit i of byte j stands for cluster
+    /// `8 * j + i`, a set bit meaning the cluster is in use, and the data is
+    /// padded to a multiple of 8 bytes.
+    pub const NTFS: Layout = Layout::new(8, Endian::Little, BitOrder::LsbFirst);
+
+    /// Describe a layout.
+    pub const fn new(word_bytes: usize, endian: Endian, bit_order: BitOrder) -> Self {
+        Layout { word_bytes, endian, bit_order }
+    }
+
+    /// Number of bytes taken by a bitmap of size bits.
+    pub fn byte_len(&self, size: usize) -> usize {
+        size.div_ceil(8 * self.word_bytes) * self.word_bytes
+    }
+
+    /// Index of the byte holding the i-th bit, and the mask selecting it.
+    pub(crate) fn locate(&self, i: usize) -> (usize, u8) {
+        let word_bits = 8 * self.word_bytes;
+        let (word, bit) = (i / word_bits, i % word_bits);
+        let k = match self.bit_order {
+            BitOrder::LsbFirst => bit,
+            BitOrder::MsbFirst => word_bits - 1 - bit,
+        };
+        let byte = match self.endian {
+            Endian::Little => k / 8,
+            Endian::Big => self.word_bytes - 1 - k / 8,
+        };
+        (word * self.word_bytes + byte, 1 << (k % 8))
+    }
+}
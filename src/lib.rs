@@ -1,23 +1,993 @@
 #![no_std]
 
-trait BitAllocator {
-    /// Create a bitmap with size bits. By default, all bits are unallocated.
-    fn new(size: usize) -> Self;
+//! Brute-force bitmap allocators.
+//!
+//! Every allocator in this crate manages a fixed number of bits, each standing
+//! for one allocation unit (typically a physical frame), and implements
+//! [`BitAllocator`]. Code that only needs to allocate and free ranges of bits
+//! should be written against the trait so that the implementations are
+//! interchangeable.
+//!
+//! With the `serde` feature, both `LinearBitMap`s implement `Serialize` and
+//! `Deserialize`. The `locked` feature adds `LockedBitMap`, a bitmap behind a
+//! `lock_api` lock that can be shared as a `static`, and the
+//! `critical-section` feature `CriticalSectionBitMap`, one that can also be
+//! shared with interrupt handlers. The `owners` feature adds `OwnerTracker`,
+//! which records who allocated every range, and the `audit` feature
+//! `AuditLog`, which keeps the last operations on an allocator for debugging.
+//! The `histogram` feature adds `SizeHistogram`, which counts requests by size.
+//! The `simd` feature makes `SliceBitMap` skip allocated or free stretches 16
+//! bytes at a time on x86_64 and aarch64.
+
+use core::fmt;
+
+/// The most bits any allocator in this crate can manage.
+pub const MAX_BITS: usize = 0x600000 / 4096;
+
+/// The id of whoever allocated a range, such as a subsystem or a process.
+pub type Tag = u32;
+
+/// An allocator over a fixed-size bitmap.
+///
+/// Bits are indexed from `0` to `size - 1`, where `size` is the value passed
+/// to [`BitAllocatorInit::new`]. A set bit is allocated, a clear bit is free.
+///
+/// Implementations must agree on which index is returned for a given sequence
+/// of operations: under the default [`FirstFit`] policy, the lowest index at
+/// which the request fits. This is what makes `bitalloc::LinearBitMap` and
+/// `bytealloc::LinearBitMap` interchangeable.
+///
+/// The trait is object safe, so an implementation can be picked at run time
+/// and used through `&mut dyn BitAllocator`. Only the few methods returning
+/// borrowing wrappers, such as iter_allocated_ranges, need a concrete type.
+pub trait BitAllocator {
     /// Allocate n consecutive bits. Returns the index of the first.
+    ///
+    /// Returns `None` if no free run of n bits exists.
+    /// Panics if n is zero or larger than the size of the bitmap.
     fn alloc(&mut self, n: usize) -> Option<usize>;
     /// Parameters:
-    /// * align: must be a power of two.
+    /// * align: must not be zero. It need not be a power of two.
     ///
-    /// Similar to alloc, but the returned index is aligned to align.
+    /// Similar to alloc, but the returned index is a multiple of align.
     fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize>;
+    /// Parameters:
+    /// * align: must not be zero.
+    /// * offset: must be smaller than align.
+    ///
+    /// Similar to alloc, but the returned index i satisfies `i % align == offset`.
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.size());
+        assert!(offset < align);
+        let begin = policy::first_fit(self, 0, self.size(), n, align, offset)?;
+        self.mark(begin, n);
+        Some(begin)
+    }
     /// Deallocate n consecutive bits, starting from the begin-th bit.
     ///
     /// Note we do not require bytes to be allocated first before they are deallocated.
+    /// Holes in the range stay allocated.
     fn dealloc(&mut self, begin: usize, n: usize);
+    /// Deallocate several ranges, each given as `(begin, n)`, in one call.
+    ///
+    /// The ranges may be given in any order and may overlap.
+    fn dealloc_batch(&mut self, ranges: &[(usize, usize)]) {
+        for &(begin, n) in ranges {
+            self.dealloc(begin, n);
+        }
+    }
+    /// Mark n consecutive bits as allocated, starting from the begin-th bit.
+    ///
+    /// Like dealloc, this does not look at the current state of the bits.
+    fn mark(&mut self, begin: usize, n: usize);
+    /// Mark every bit as allocated or every bit as free.
+    fn set_all(&mut self, allocated: bool) {
+        let size = self.size();
+        if size == 0 { return; }
+        if allocated { self.mark(0, size); } else { self.dealloc(0, size); }
+    }
+    /// Mark every bit as free.
+    fn clear(&mut self) { self.set_all(false) }
+    /// Mark every bit as allocated.
+    fn fill(&mut self) { self.set_all(true) }
+    /// Number of bits managed by this bitmap.
+    fn size(&self) -> usize;
+    /// Largest size the backing storage of this bitmap can hold.
+    fn capacity(&self) -> usize { self.size() }
+    /// Whether the index-th bit is allocated.
+    ///
+    /// Panics if index is not smaller than the size of the bitmap.
+    fn is_allocated(&self, index: usize) -> bool;
+    /// Number of free bits. Implementations keep this up to date on every
+    /// operation, so it is O(1).
+    fn free_count(&self) -> usize;
+    /// Whether the index-th bit is a hole: an allocated bit that stands for
+    /// nothing, such as a frame missing between two memory regions. Holes can
+    /// never be freed and are counted neither as free nor as used.
+    ///
+    /// Only allocators that support holes have any; for the others this is
+    /// always false.
+    fn is_hole(&self, _index: usize) -> bool { false }
+    /// Number of holes.
+    fn hole_count(&self) -> usize { 0 }
+    /// Whether the index-th bit is bad: a hole standing for a faulty block,
+    /// such as a page with ECC errors or a worn-out flash block, that must
+    /// never be handed out again.
+    ///
+    /// Only allocators that support holes have any; for the others this is
+    /// always false.
+    fn is_bad(&self, _index: usize) -> bool { false }
+    /// Number of bad bits. They are included in hole_count.
+    fn bad_count(&self) -> usize { 0 }
+    /// Whether the index-th bit is pinned: an allocated bit that dealloc
+    /// leaves alone until it is unpinned.
+    ///
+    /// Only allocators that support pinning have any; for the others this
+    /// is always false.
+    fn is_pinned(&self, _index: usize) -> bool { false }
+    /// Number of pinned bits.
+    fn pinned_count(&self) -> usize { 0 }
+    /// Number of allocated bits, not counting holes.
+    fn used_count(&self) -> usize { self.size() - self.free_count() - self.hole_count() }
+    /// Number of bits still available for allocation; the same as free_count.
+    fn remaining(&self) -> usize { self.free_count() }
+    /// Occupancy and fragmentation of the bitmap, computed by a scan.
+    fn stats(&self) -> AllocStats {
+        let (free_runs, largest_free_run) = free_run_stats(self);
+        let free = self.free_count();
+        AllocStats {
+            size: self.size(),
+            free,
+            used: self.used_count(),
+            free_runs,
+            largest_free_run,
+            mean_free_run: free.checked_div(free_runs).unwrap_or(0),
+            fragmentation: (largest_free_run * 100).checked_div(free).map_or(0, |p| 100 - p) as u8,
+        }
+    }
+    /// How well the free bits serve requests of target_size bits, e.g. to
+    /// decide whether reclaim or compaction is needed.
+    ///
+    /// Panics if target_size is zero.
+    fn fragmentation(&self, target_size: usize) -> FragInfo {
+        assert!(target_size > 0);
+        let satisfiable = policy::free_runs(self, 0).map(|(_, len)| len / target_size).sum::<usize>();
+        let free = self.free_count();
+        let unusable = free - satisfiable * target_size;
+        FragInfo { satisfiable, unusable, unusable_percent: (unusable * 100).checked_div(free).unwrap_or(0) as u8 }
+    }
+    /// Index of the first bit at or after from whose state is allocated.
+    /// Returns the size of the bitmap if there is no such bit.
+    fn find_next(&self, from: usize, allocated: bool) -> usize {
+        (from..self.size()).find(|&i| self.is_allocated(i) == allocated).unwrap_or(self.size())
+    }
+    /// Index of the last bit before the before-th bit whose state is
+    /// allocated, or `None` if there is no such bit.
+    fn find_prev(&self, before: usize, allocated: bool) -> Option<usize> {
+        (0..core::cmp::min(before, self.size())).rev().find(|&i| self.is_allocated(i) == allocated)
+    }
+
+    /// Iterate over the maximal runs of allocated bits, in increasing order,
+    /// as `(begin, len)` pairs.
+    fn iter_allocated_ranges(&self) -> Runs<'_, Self> where Self: Sized {
+        Runs { bm: self, pos: 0, allocated: true }
+    }
+
+    /// Call report with `(begin, len, owner)` for every maximal run of
+    /// allocated bits, not counting holes, in increasing order, e.g. to find
+    /// frames leaked by a test or at shutdown.
+    ///
+    /// The owner is only known to allocators that record one, such as
+    /// `OwnerTracker`; a run there is also split where the owner changes.
+    fn outstanding_report(&self, report: &mut dyn FnMut(usize, usize, Option<Tag>)) {
+        outstanding(self, |_| None, report)
+    }
+
+    /// Whether alloc_aligned(n, align) would succeed now, without allocating
+    /// anything. Requests that alloc_aligned would reject as invalid are
+    /// never possible.
+    fn can_alloc(&self, n: usize, align: usize) -> bool {
+        n > 0 && align > 0 && n <= self.size() && policy::first_fit(self, 0, self.size(), n, align, 0).is_some()
+    }
+
+    /// Fill out with the largest free runs, as `(begin, len)` pairs, longest
+    /// first and lowest first among equally long ones, e.g. to find
+    /// candidates for huge pages. Returns how many there were, at most the
+    /// length of out.
+    fn largest_free_runs(&self, out: &mut [(usize, usize)]) -> usize {
+        let mut count = 0;
+        for run in policy::free_runs(self, 0) {
+            let at = out[..count].iter().position(|&(_, len)| len < run.1).unwrap_or(count);
+            if at == out.len() { continue; }
+            count = core::cmp::min(count + 1, out.len());
+            out[at..count].rotate_right(1);
+            out[at] = run;
+        }
+        count
+    }
+
+    /// The moves that would make a free run of goal bits, for the caller to
+    /// carry out: the allocator knows the layout, the caller how to copy the
+    /// data. See [`CompactionPlan`].
+    ///
+    /// Panics if goal is zero.
+    fn plan_compaction(&self, goal: usize) -> CompactionPlan<'_, Self> where Self: Sized {
+        CompactionPlan::new(self, goal)
+    }
+
+    /// Gather the free bits into a single run, as far as holes and pinned
+    /// bits allow, by carrying out the moves of plan_compaction for the
+    /// longest run that can be made.
+    ///
+    /// Before each move, relocate is called with `(from, to, len)` to copy
+    /// the data, and the bits are only moved if it returns true. Compaction
+    /// stops at the first move that fails. Returns the number of bits moved.
+    fn compact<F>(&mut self, mut relocate: F) -> usize
+        where Self: Sized, F: FnMut(usize, usize, usize) -> bool {
+        let goal = compact::largest_goal(self);
+        if goal == 0 { return 0; }
+        let mut planner = compact::Planner::new(self, goal);
+        let mut moved = 0;
+        while let Some(Move { from, to, len }) = planner.next_move(self) {
+            if !relocate(from, to, len) { break; }
+            self.mark(to, len);
+            self.dealloc(from, len);
+            moved += len;
+        }
+        moved
+    }
+
+    /// Iterate over the maximal runs of bad bits, in increasing order, as
+    /// `(begin, len)` pairs.
+    fn iter_bad_ranges(&self) -> BadRuns<'_, Self> where Self: Sized {
+        BadRuns { bm: self, pos: 0 }
+    }
+
+    /// Write the run-length encoding of the bitmap to the beginning of out,
+    /// returning its length. The encoding is compact for bitmaps made of a
+    /// few long runs, and can be read back with `BitAllocatorInit::decode_rle`.
+    ///
+    /// Panics if out is shorter than rle_len.
+    fn encode_rle(&self, out: &mut [u8]) -> usize {
+        let mut len = 0;
+        rle::encode(self, |b| { out[len] = b; len += 1; });
+        len
+    }
+    /// Length of the output of encode_rle.
+    fn rle_len(&self) -> usize {
+        let mut len = 0;
+        rle::encode(self, |_| len += 1);
+        len
+    }
+
+    /// Write the bitmap to the beginning of out in the given layout,
+    /// returning the number of bytes written, `layout.byte_len(size)`.
+    ///
+    /// Panics if out is too short.
+    fn export_bytes(&self, layout: &Layout, out: &mut [u8]) -> usize {
+        let len = layout.byte_len(self.size());
+        out[..len].fill(0);
+        for i in (0..self.size()).filter(|&i| self.is_allocated(i)) {
+            let (byte, mask) = layout.locate(i);
+            out[byte] |= mask;
+        }
+        len
+    }
+
+    /// Render the bitmap as text; see [`Visualize`].
+    fn visualize(&self) -> Visualize<'_, Self> where Self: Sized {
+        Visualize::new(self)
+    }
+
+    /// Like alloc, but the bits are freed again when the returned guard is
+    /// dropped, unless it is leaked first.
+    fn alloc_guarded(&mut self, n: usize) -> Option<Allocation<'_, Self>> where Self: Sized {
+        let begin = self.alloc(n)?;
+        Some(Allocation { bm: self, extent: Extent { begin, len: n } })
+    }
+
+    /// Like alloc, but the search starts at the hint-th bit and wraps around
+    /// to the beginning of the bitmap.
+    ///
+    /// Callers that know where space was recently freed can use this to avoid
+    /// rescanning the allocated prefix of the bitmap.
+    fn alloc_from(&mut self, hint: usize, n: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.size());
+        let begin = policy::wrapping_fit(self, hint, n, 1, 0)?;
+        self.mark(begin, n);
+        Some(begin)
+    }
+
+    /// Like alloc, but only considers the bits [lo, hi), for example to keep
+    /// an allocation within a DMA zone or a memory node.
+    ///
+    /// Panics if the range does not lie within the bitmap.
+    fn alloc_in_range(&mut self, n: usize, lo: usize, hi: usize) -> Option<usize> {
+        assert!(0 < n && lo <= hi && hi <= self.size());
+        let begin = policy::first_fit(self, lo, hi, n, 1, 0)?;
+        self.mark(begin, n);
+        Some(begin)
+    }
+
+    /// Like alloc, but searches from the end of the bitmap downward and
+    /// returns the highest index at which n bits fit.
+    ///
+    /// Allocating one kind of object from the top and another from the bottom
+    /// keeps the two apart in a single bitmap.
+    fn alloc_from_top(&mut self, n: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.size());
+        let begin = policy::last_fit(self, 0, self.size(), n, 1, 0)?;
+        self.mark(begin, n);
+        Some(begin)
+    }
+
+    /// Like alloc, but reports why the allocation failed instead of returning
+    /// `None`, and rejects invalid sizes instead of panicking.
+    fn try_alloc(&mut self, n: usize) -> Result<usize, AllocError> {
+        check_len(self.size(), n)?;
+        self.alloc(n).ok_or_else(|| exhaustion(self, n))
+    }
+    /// Like alloc_aligned, but reports why the allocation failed instead of
+    /// returning `None`, and rejects invalid arguments instead of panicking.
+    fn try_alloc_aligned(&mut self, n: usize, alignment: usize) -> Result<usize, AllocError> {
+        check_len(self.size(), n)?;
+        if alignment == 0 {
+            return Err(AllocError::InvalidAlignment);
+        }
+        self.alloc_aligned(n, alignment).ok_or_else(|| exhaustion(self, n))
+    }
+    /// Allocate `2^order` bits at a multiple of `2^order`, like Linux's
+    /// `alloc_pages`; with 4KiB frames, order 9 is a 2MiB huge frame.
+    ///
+    /// Panics if `2^order` is larger than the size of the bitmap.
+    fn alloc_order(&mut self, order: u32) -> Option<usize> {
+        let n = 1 << order;
+        self.alloc_aligned(n, n)
+    }
+    /// Free the `2^order` bits at begin, as allocated by alloc_order.
+    fn dealloc_order(&mut self, begin: usize, order: u32) {
+        self.dealloc(begin, 1 << order);
+    }
+    /// Allocate n bits that need not be consecutive.
+    ///
+    /// The lowest free bits are taken, and out is filled with the extents
+    /// they form, in increasing order. Returns the number of extents used.
+    /// Nothing is allocated if there are fewer than n free bits, or if they
+    /// form more extents than out can hold (`AllocError::Fragmented`).
+    fn alloc_scatter(&mut self, n: usize, out: &mut [Extent]) -> Result<usize, AllocError> {
+        check_len(self.size(), n)?;
+        if self.free_count() < n {
+            return Err(AllocError::OutOfSpace);
+        }
+        let mut remaining = n;
+        let mut used = 0;
+        for (begin, len) in policy::free_runs(&*self, 0) {
+            if remaining == 0 { break; }
+            let slot = out.get_mut(used).ok_or(AllocError::Fragmented)?;
+            *slot = Extent { begin, len: core::cmp::min(len, remaining) };
+            remaining -= slot.len;
+            used += 1;
+        }
+        for e in &out[..used] {
+            self.mark(e.begin, e.len);
+        }
+        Ok(used)
+    }
+    /// Allocate requests.len() independent runs, the i-th of requests[i] bits,
+    /// storing the index of the i-th in out[i].
+    ///
+    /// Either all requests are satisfied or none is: on failure, runs already
+    /// allocated are freed again and the error of the failed request is returned.
+    /// Only the state of the placement policy, such as a next-fit cursor, may
+    /// have changed then.
+    /// Panics if out is shorter than requests.
+    fn alloc_batch(&mut self, requests: &[usize], out: &mut [usize]) -> Result<(), AllocError> {
+        assert!(out.len() >= requests.len());
+        for (i, &n) in requests.iter().enumerate() {
+            match self.try_alloc(n) {
+                Ok(begin) => out[i] = begin,
+                Err(e) => {
+                    for (&begin, &n) in out[..i].iter().zip(requests) {
+                        self.dealloc(begin, n);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Where the bits described by req would be allocated, without
+    /// allocating them. Claim them afterwards with commit.
+    ///
+    /// Together, the two let a caller vet a candidate range before taking
+    /// it, and look elsewhere, e.g. with a narrower req, if it is rejected.
+    fn find(&self, req: &AllocRequest) -> Result<usize, AllocError> {
+        req.find(self)
+    }
+    /// Claim bits returned by find. Fails without changing anything if any
+    /// of them has been allocated in the meantime.
+    fn commit(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.alloc_at(begin, n)
+    }
+    /// Allocate exactly the bits [begin, begin+n).
+    ///
+    /// Fails without changing anything if any bit in the range is already allocated.
+    fn alloc_at(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        check_range(self.size(), begin, n)?;
+        if (begin..begin + n).any(|i| self.is_allocated(i)) {
+            return Err(AllocError::Occupied);
+        }
+        self.mark(begin, n);
+        Ok(())
+    }
+    /// Mark the bits [begin, begin+n) as used, e.g. for firmware or MMIO
+    /// regions at boot.
+    ///
+    /// Like alloc_at, but a conflict reports the first bit of the range that
+    /// is already allocated, so the caller can log what collided.
+    fn reserve(&mut self, begin: usize, n: usize) -> Result<(), ReserveConflict> {
+        check_range(self.size(), begin, n).map_err(|_| ReserveConflict::OutOfBounds)?;
+        let first = self.find_next(begin, true);
+        if first < begin + n {
+            return Err(ReserveConflict::Allocated(first));
+        }
+        self.mark(begin, n);
+        Ok(())
+    }
+    /// Grow the allocation of old_n bits at begin to new_n bits in place.
+    ///
+    /// Fails without changing anything unless the bits following the
+    /// allocation are free.
+    fn try_grow(&mut self, begin: usize, old_n: usize, new_n: usize) -> Result<(), AllocError> {
+        if new_n < old_n {
+            return Err(AllocError::InvalidSize);
+        }
+        check_range(self.size(), begin, new_n)?;
+        if new_n == old_n {
+            return Ok(());
+        }
+        self.alloc_at(begin + old_n, new_n - old_n)
+    }
+    /// Shrink the allocation of old_n bits at begin to its first new_n bits,
+    /// freeing the tail [begin+new_n, begin+old_n).
+    fn shrink(&mut self, begin: usize, old_n: usize, new_n: usize) -> Result<(), AllocError> {
+        if new_n > old_n {
+            return Err(AllocError::InvalidSize);
+        }
+        check_range(self.size(), begin, old_n)?;
+        if new_n < old_n {
+            self.dealloc(begin + new_n, old_n - new_n);
+        }
+        Ok(())
+    }
+    /// Like try_dealloc, but also fails, without changing anything, if any
+    /// bit in the range is already free. This catches double frees.
+    fn dealloc_checked(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        check_range(self.size(), begin, n)?;
+        check_holes(self, begin, n)?;
+        check_pins(self, begin, n)?;
+        if self.find_next(begin, false) < begin + n {
+            return Err(AllocError::DoubleFree);
+        }
+        self.dealloc(begin, n);
+        Ok(())
+    }
+    /// Like dealloc, but rejects ranges that do not lie within the bitmap or
+    /// that contain holes or pinned bits.
+    fn try_dealloc(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        check_range(self.size(), begin, n)?;
+        check_holes(self, begin, n)?;
+        check_pins(self, begin, n)?;
+        self.dealloc(begin, n);
+        Ok(())
+    }
+}
+
+/// Allocators that can be created from nothing but their size.
+///
+/// Kept apart from [`BitAllocator`], which would otherwise not be object safe.
+pub trait BitAllocatorInit: BitAllocator + Sized {
+    /// Create a bitmap with size bits. By default, all bits are unallocated.
+    ///
+    /// Panics if size exceeds the capacity of the implementation.
+    fn new(size: usize) -> Self;
+    /// Create a bitmap with size bits, all of them allocated. Usable memory
+    /// can then be freed as it is discovered.
+    fn new_filled(size: usize) -> Self {
+        let mut bm = Self::new(size);
+        bm.fill();
+        bm
+    }
+    /// Create a bitmap with size bits in which the given ranges, each as
+    /// `(begin, n)`, are allocated and all other bits are free.
+    ///
+    /// Panics if a range reaches past the end of the bitmap.
+    fn with_reserved(size: usize, reserved: &[(usize, usize)]) -> Self {
+        let mut bm = Self::new(size);
+        for &(begin, n) in reserved {
+            assert!(begin + n <= size);
+            bm.mark(begin, n);
+        }
+        bm
+    }
+    /// Create a bitmap from the output of encode_rle.
+    ///
+    /// Returns `None` if data is not such output.
+    fn decode_rle(data: &[u8]) -> Option<Self> {
+        rle::decode(data)
+    }
+    /// Create a bitmap with size bits from bytes in the given layout, as
+    /// written by export_bytes. Padding bits are ignored.
+    ///
+    /// Panics if bytes is shorter than `layout.byte_len(size)`.
+    fn import_bytes(size: usize, layout: &Layout, bytes: &[u8]) -> Self {
+        assert!(bytes.len() >= layout.byte_len(size));
+        let mut bm = Self::new(size);
+        for i in 0..size {
+            let (byte, mask) = layout.locate(i);
+            if bytes[byte] & mask != 0 { bm.mark(i, 1); }
+        }
+        bm
+    }
+}
+
+/// A run of consecutive bits.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Extent {
+    /// Index of the first bit.
+    pub begin: usize,
+    /// Number of bits.
+    pub len: usize,
+}
+
+/// A snapshot of how full and how fragmented a bitmap is, returned by
+/// [`BitAllocator::stats`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct AllocStats {
+    /// Number of bits.
+    pub size: usize,
+    /// Number of free bits.
+    pub free: usize,
+    /// Number of allocated bits, not counting holes.
+    pub used: usize,
+    /// Number of maximal runs of free bits.
+    pub free_runs: usize,
+    /// Length of the longest free run, the largest request that can succeed.
+    pub largest_free_run: usize,
+    /// Mean length of the free runs, rounded down.
+    pub mean_free_run: usize,
+    /// External fragmentation: the percentage of free bits outside the
+    /// longest free run, from 0 when all free bits are in one run to almost
+    /// 100 when they are scattered.
+    pub fragmentation: u8,
+}
+
+/// How fragmented a bitmap is for requests of a given size, returned by
+/// [`BitAllocator::fragmentation`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FragInfo {
+    /// Number of requests of the size that could be satisfied one after the
+    /// other from the free bits as they are.
+    pub satisfiable: usize,
+    /// Number of free bits left over once they are, too few in their run to
+    /// serve another request of the size.
+    pub unusable: usize,
+    /// unusable as a percentage of the free bits, rounded down; 0 if there
+    /// are none.
+    pub unusable_percent: u8,
+}
+
+/// Iterator over maximal runs of bits in the same state.
+///
+/// Created by [`BitAllocator::iter_allocated_ranges`].
+pub struct Runs<'a, T: ?Sized> {
+    bm: &'a T,
+    pos: usize,
+    allocated: bool,
+}
+
+impl<'a, T: BitAllocator + ?Sized> Iterator for Runs<'a, T> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        let begin = self.bm.find_next(self.pos, self.allocated);
+        if begin >= self.bm.size() { return None; }
+        let end = self.bm.find_next(begin, !self.allocated);
+        self.pos = end;
+        Some((begin, end - begin))
+    }
+}
+
+/// Iterator over maximal runs of bad bits.
+///
+/// Created by [`BitAllocator::iter_bad_ranges`].
+pub struct BadRuns<'a, T: ?Sized> {
+    bm: &'a T,
+    pos: usize,
+}
+
+impl<'a, T: BitAllocator + ?Sized> Iterator for BadRuns<'a, T> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        let size = self.bm.size();
+        let begin = (self.pos..size).find(|&i| self.bm.is_bad(i))?;
+        let end = (begin..size).find(|&i| !self.bm.is_bad(i)).unwrap_or(size);
+        self.pos = end;
+        Some((begin, end - begin))
+    }
+}
+
+/// An allocated run of bits that is deallocated on drop.
+///
+/// Created by [`BitAllocator::alloc_guarded`].
+pub struct Allocation<'a, T: BitAllocator + ?Sized> {
+    bm: &'a mut T,
+    extent: Extent,
+}
+
+impl<'a, T: BitAllocator + ?Sized> Allocation<'a, T> {
+    /// Index of the first allocated bit.
+    pub fn begin(&self) -> usize { self.extent.begin }
+
+    /// The allocated bits.
+    pub fn extent(&self) -> Extent { self.extent }
+
+    /// Keep the bits allocated and give up the guard. The caller is then
+    /// responsible for deallocating the returned extent.
+    pub fn leak(self) -> Extent {
+        let extent = self.extent;
+        core::mem::forget(self);
+        extent
+    }
+}
+
+impl<'a, T: BitAllocator + ?Sized> Drop for Allocation<'a, T> {
+    fn drop(&mut self) {
+        self.bm.dealloc(self.extent.begin, self.extent.len);
+    }
+}
+
+/// Why an allocator operation failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AllocError {
+    /// Fewer bits are free than were requested.
+    OutOfSpace,
+    /// Enough bits are free, but no free run satisfies the request.
+    Fragmented,
+    /// The alignment is zero.
+    InvalidAlignment,
+    /// The request covers zero bits.
+    InvalidSize,
+    /// The request reaches past the end of the bitmap.
+    OutOfBounds,
+    /// Some bit of the requested range is already allocated.
+    Occupied,
+    /// Some bit of the range to free, pin or take a reference to is not
+    /// allocated.
+    DoubleFree,
+    /// The handle was already freed.
+    StaleHandle,
+    /// Some bit of the range to free is a hole.
+    Hole,
+    /// A reference count would exceed what its counter type can hold.
+    CountOverflow,
+    /// Some bit of the range to free is pinned.
+    Pinned,
+    /// The allocation would take its tag over its quota.
+    QuotaExceeded,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AllocError::OutOfSpace => "not enough free bits",
+            AllocError::Fragmented => "no free run is large enough",
+            AllocError::InvalidAlignment => "alignment is zero",
+            AllocError::InvalidSize => "zero-sized request",
+            AllocError::OutOfBounds => "range exceeds the bitmap",
+            AllocError::Occupied => "range is already allocated",
+            AllocError::DoubleFree => "range is not allocated",
+            AllocError::StaleHandle => "handle was already freed",
+            AllocError::Hole => "range contains a hole",
+            AllocError::CountOverflow => "reference count overflow",
+            AllocError::Pinned => "range contains a pinned bit",
+            AllocError::QuotaExceeded => "quota exceeded",
+        })
+    }
+}
+
+/// Why [`BitAllocator::reserve`] failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReserveConflict {
+    /// The range is empty or reaches past the end of the bitmap.
+    OutOfBounds,
+    /// The bit at this index is already allocated, and is the first such bit
+    /// of the range.
+    Allocated(usize),
+}
+
+impl fmt::Display for ReserveConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReserveConflict::OutOfBounds => f.write_str("range exceeds the bitmap"),
+            ReserveConflict::Allocated(i) => write!(f, "bit {} is already allocated", i),
+        }
+    }
+}
+
+/// Why taking a range offline failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OfflineError {
+    /// The range is empty or reaches past the end of the bitmap.
+    OutOfBounds,
+    /// count bits of the range are allocated, the lowest being first and the
+    /// highest last.
+    Busy { first: usize, last: usize, count: usize },
+}
+
+impl fmt::Display for OfflineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OfflineError::OutOfBounds => f.write_str("range exceeds the bitmap"),
+            OfflineError::Busy { first, last, count } =>
+                write!(f, "{} bits between {} and {} are allocated", count, first, last),
+        }
+    }
+}
+
+/// Check that the bits [begin, begin+n) of bm can be taken offline: they
+/// must all be free or holes.
+fn check_offline<T: BitAllocator + ?Sized>(bm: &T, begin: usize, n: usize) -> Result<(), OfflineError> {
+    check_range(bm.size(), begin, n).map_err(|_| OfflineError::OutOfBounds)?;
+    let mut busy = (begin..begin + n).filter(|&i| bm.is_allocated(i) && !bm.is_hole(i));
+    match busy.next() {
+        None => Ok(()),
+        Some(first) => {
+            let (last, count) = busy.fold((first, 1), |(_, count), i| (i, count + 1));
+            Err(OfflineError::Busy { first, last, count })
+        }
+    }
+}
+
+/// Validate the length of a request against a bitmap of size bits.
+fn check_len(size: usize, n: usize) -> Result<(), AllocError> {
+    if n == 0 {
+        Err(AllocError::InvalidSize)
+    } else if n > size {
+        Err(AllocError::OutOfBounds)
+    } else {
+        Ok(())
+    }
+}
+
+/// Validate that [begin, begin+n) is a non-empty range within the bitmap.
+fn check_range(size: usize, begin: usize, n: usize) -> Result<(), AllocError> {
+    check_len(size, n)?;
+    match begin.checked_add(n) {
+        Some(end) if end <= size => Ok(()),
+        _ => Err(AllocError::OutOfBounds),
+    }
+}
+
+/// Fails if any bit of the range [begin, begin+n), which must lie within bm, is a hole.
+fn check_holes<T: BitAllocator + ?Sized>(bm: &T, begin: usize, n: usize) -> Result<(), AllocError> {
+    if bm.hole_count() > 0 && (begin..begin + n).any(|i| bm.is_hole(i)) {
+        return Err(AllocError::Hole);
+    }
+    Ok(())
+}
+
+/// Fails if any bit of the range [begin, begin+n), which must lie within bm, is pinned.
+fn check_pins<T: BitAllocator + ?Sized>(bm: &T, begin: usize, n: usize) -> Result<(), AllocError> {
+    if bm.pinned_count() > 0 && (begin..begin + n).any(|i| bm.is_pinned(i)) {
+        return Err(AllocError::Pinned);
+    }
+    Ok(())
+}
+
+/// Check that the bits [begin, begin+n) of bm can be pinned: they must all be
+/// allocated, and none of them a hole.
+fn check_pin<T: BitAllocator + ?Sized>(bm: &T, begin: usize, n: usize) -> Result<(), AllocError> {
+    check_range(bm.size(), begin, n)?;
+    check_holes(bm, begin, n)?;
+    if bm.find_next(begin, false) < begin + n {
+        return Err(AllocError::DoubleFree);
+    }
+    Ok(())
+}
+
+/// Number of free runs of bm and length of the longest one.
+fn free_run_stats<T: BitAllocator + ?Sized>(bm: &T) -> (usize, usize) {
+    policy::free_runs(bm, 0).fold((0, 0), |(count, longest), (_, len)| {
+        (count + 1, core::cmp::max(longest, len))
+    })
+}
+
+/// Call report with every run of allocated bits of bm that are not holes and
+/// have the same owner.
+fn outstanding<T, F>(bm: &T, owner: F, report: &mut dyn FnMut(usize, usize, Option<Tag>))
+    where T: BitAllocator + ?Sized, F: Fn(usize) -> Option<Tag> {
+    for (begin, len) in (Runs { bm, pos: 0, allocated: true }) {
+        let mut i = begin;
+        while i < begin + len {
+            if bm.is_hole(i) {
+                i += 1;
+                continue;
+            }
+            let (start, tag) = (i, owner(i));
+            while i < begin + len && !bm.is_hole(i) && owner(i) == tag { i += 1; }
+            report(start, i - start, tag);
+        }
+    }
+}
+
+/// Debug output for the allocators of this crate: a summary of occupancy
+/// rather than every bit.
+fn debug_summary<T, P>(bm: &T, name: &str, policy: &P, f: &mut fmt::Formatter) -> fmt::Result
+    where T: BitAllocator + ?Sized, P: fmt::Debug {
+    let (free_runs, largest_free_run) = free_run_stats(bm);
+    f.debug_struct(name)
+        .field("size", &bm.size())
+        .field("used", &bm.used_count())
+        .field("free", &bm.free_count())
+        .field("free_runs", &free_runs)
+        .field("largest_free_run", &largest_free_run)
+        .field("policy", policy)
+        .finish()
+}
+
+/// Classify a failed allocation of n bits.
+fn exhaustion<T: BitAllocator + ?Sized>(bm: &T, n: usize) -> AllocError {
+    if bm.free_count() < n { AllocError::OutOfSpace } else { AllocError::Fragmented }
+}
+
+/// Implement the BitAllocator methods of a wrapper that only look at the
+/// bitmap by forwarding them to its inner field, along with the listed
+/// mutating ones that need no bookkeeping in the wrapper.
+macro_rules! forward_bit_allocator {
+    (@dealloc) => {
+        fn dealloc(&mut self, begin: usize, n: usize) { self.inner.dealloc(begin, n) }
+    };
+    (@mark) => {
+        fn mark(&mut self, begin: usize, n: usize) { self.inner.mark(begin, n) }
+    };
+    (@dealloc_batch) => {
+        fn dealloc_batch(&mut self, ranges: &[(usize, usize)]) { self.inner.dealloc_batch(ranges) }
+    };
+    (@set_all) => {
+        fn set_all(&mut self, allocated: bool) { self.inner.set_all(allocated) }
+    };
+    (@alloc_at) => {
+        fn alloc_at(&mut self, begin: usize, n: usize) -> Result<(), $crate::AllocError> {
+            self.inner.alloc_at(begin, n)
+        }
+    };
+    (@reserve) => {
+        fn reserve(&mut self, begin: usize, n: usize) -> Result<(), $crate::ReserveConflict> {
+            self.inner.reserve(begin, n)
+        }
+    };
+    ($($op:ident),*) => {
+        fn size(&self) -> usize { self.inner.size() }
+
+        fn capacity(&self) -> usize { self.inner.capacity() }
+
+        fn is_allocated(&self, index: usize) -> bool { self.inner.is_allocated(index) }
+
+        fn free_count(&self) -> usize { self.inner.free_count() }
+
+        fn is_hole(&self, index: usize) -> bool { self.inner.is_hole(index) }
+
+        fn hole_count(&self) -> usize { self.inner.hole_count() }
+
+        fn is_bad(&self, index: usize) -> bool { self.inner.is_bad(index) }
+
+        fn bad_count(&self) -> usize { self.inner.bad_count() }
+
+        fn is_pinned(&self, index: usize) -> bool { self.inner.is_pinned(index) }
+
+        fn pinned_count(&self) -> usize { self.inner.pinned_count() }
+
+        fn find_next(&self, from: usize, allocated: bool) -> usize {
+            self.inner.find_next(from, allocated)
+        }
+
+        fn find_prev(&self, before: usize, allocated: bool) -> Option<usize> {
+            self.inner.find_prev(before, allocated)
+        }
+
+        $(forward_bit_allocator!(@$op);)*
+    };
 }
 
+pub mod atomic;
+#[cfg(feature = "audit")]
+pub mod audit;
 pub mod bitalloc;
+pub mod boot;
+#[cfg(feature = "critical-section")]
+pub mod critical;
 pub mod bytealloc;
+pub mod compact;
+pub mod deferred;
+pub mod epoch;
+pub mod flags;
+pub mod handle;
+pub mod highwater;
+#[cfg(feature = "histogram")]
+pub mod histogram;
+pub mod hybrid;
+pub mod id;
+pub mod index;
+pub mod layout;
+pub mod linux;
+#[cfg(feature = "locked")]
+pub mod locked;
+pub mod observer;
+#[cfg(feature = "owners")]
+pub mod owner;
+pub mod policy;
+pub mod quota;
+pub mod reclaim;
+pub mod refcount;
+pub mod request;
+mod rle;
+pub mod runcache;
+mod scan;
+pub mod segalloc;
+#[cfg(feature = "serde")]
+mod serde_impl;
+pub mod sharded;
+pub mod slab;
+pub mod slice;
+pub mod sparse;
+pub mod statealloc;
+pub mod sub;
+pub mod tlsf;
+pub mod visualize;
+pub mod watermark;
+pub mod zone;
+
+pub use atomic::AtomicBitMap;
+#[cfg(feature = "audit")]
+pub use audit::{AuditLog, OpKind, Operation};
+#[cfg(feature = "critical-section")]
+pub use critical::CriticalSectionBitMap;
+pub use compact::{CompactionPlan, Move};
+pub use deferred::FreeQueue;
+pub use epoch::EpochAllocator;
+pub use policy::{FitPolicy, FirstFit, NextFit, BestFit, WorstFit, Bidirectional};
+pub use quota::QuotaAllocator;
+pub use reclaim::{ReclaimAllocator, Shrinker};
+pub use refcount::RefCountAlloc;
+pub use request::AllocRequest;
+pub use runcache::RunCache;
+pub use flags::{Flag, FlagTracker};
+pub use handle::{AllocHandle, HandleAllocator};
+pub use highwater::HighWater;
+#[cfg(feature = "histogram")]
+pub use histogram::SizeHistogram;
+pub use hybrid::HybridBitMap;
+pub use id::IdAlloc;
+pub use index::BitIndex;
+pub use layout::{BitOrder, Endian, Layout};
+#[cfg(feature = "locked")]
+pub use locked::{LockedBitMap, RawSpinlock};
+pub use observer::{AllocObserver, Observed};
+#[cfg(feature = "owners")]
+pub use owner::OwnerTracker;
+pub use segalloc::SegTreeBitMap;
+pub use sharded::ShardedBitMap;
+pub use slab::SlabPool;
+pub use slice::SliceBitMap;
+pub use sparse::SparseBitMap;
+pub use statealloc::{SlotState, StateBitMap};
+pub use sub::SubAllocator;
+pub use tlsf::TlsfBitMap;
+pub use visualize::Visualize;
+pub use watermark::{MemoryEvent, WatermarkAllocator};
+pub use zone::ZoneManager;
 
 #[cfg(test)]
 mod tests {
@@ -60,6 +1030,24 @@ mod tests {
         inner_test_dealloc(B::LinearBitMap::new(10));
     }
 
+    fn inner_test_past_size<T: BitAllocator>(mut bm: T) {
+        // Bits past the size are ignored, even whole words of them.
+        bm.dealloc(60, 10);
+        bm.mark(8, 60);
+        bm.mark(200, 1);
+        assert_eq!((8, 8), (bm.free_count(), bm.find_next(0, true)));
+        bm.dealloc(5, 100);
+        assert_eq!((10, Some(0)), (bm.free_count(), bm.alloc(10)));
+    }
+
+    #[test]
+    fn test_past_size() {
+        inner_test_past_size(b::LinearBitMap::new(10));
+        inner_test_past_size(B::LinearBitMap::new(10));
+        inner_test_past_size(SegTreeBitMap::new(10));
+        inner_test_past_size(TlsfBitMap::new(10));
+    }
+
     fn inner_test_alloc_aligned<T: BitAllocator>(mut bm: T) {
         assert_eq!(Some(0), bm.alloc(5));
         assert_eq!(Some(6), bm.alloc_aligned(3, 2));
@@ -81,7 +1069,2250 @@ mod tests {
         inner_test_alloc_aligned(B::LinearBitMap::new(10));
     }
 
+    fn inner_test_alloc_order<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(0), bm.alloc_order(0));
+        assert_eq!(Some(4), bm.alloc_order(2));
+        assert_eq!(Some(2), bm.alloc_order(1));
+        assert_eq!(None, bm.alloc_order(3));
+        bm.dealloc_order(4, 2);
+        assert_eq!(Some(4), bm.alloc_order(2));
+        assert_eq!(Some(8), bm.alloc_order(1));
+        assert_eq!(Some(1), bm.alloc_order(0));
+        assert_eq!(0, bm.free_count());
+    }
+
+    #[test]
+    fn test_alloc_order() {
+        inner_test_alloc_order(b::LinearBitMap::new(10));
+        inner_test_alloc_order(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_try_alloc<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Err(AllocError::InvalidSize), bm.try_alloc(0));
+        assert_eq!(Err(AllocError::OutOfBounds), bm.try_alloc(11));
+        assert_eq!(Err(AllocError::InvalidAlignment), bm.try_alloc_aligned(2, 0));
+        assert_eq!(Ok(0), bm.try_alloc(4));
+        assert_eq!(Ok(4), bm.try_alloc(2));
+        assert_eq!(Ok(6), bm.try_alloc(4));
+        assert_eq!(Ok(()), bm.try_dealloc(3, 2));
+        assert_eq!(Err(AllocError::Fragmented), bm.try_alloc_aligned(2, 4));
+        assert_eq!(Err(AllocError::OutOfSpace), bm.try_alloc(3));
+        assert_eq!(Err(AllocError::OutOfBounds), bm.try_dealloc(9, 2));
+        assert_eq!(Ok(3), bm.try_alloc(2));
+    }
+
+    #[test]
+    fn test_try_alloc() {
+        inner_test_try_alloc(b::LinearBitMap::new(10));
+        inner_test_try_alloc(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_alloc_at<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Ok(()), bm.alloc_at(3, 4));
+        assert_eq!(Err(AllocError::Occupied), bm.alloc_at(6, 2));
+        assert_eq!(Err(AllocError::OutOfBounds), bm.alloc_at(8, 3));
+        assert!(!bm.is_allocated(7));
+        assert_eq!(Some(0), bm.alloc(3));
+        assert_eq!(Some(7), bm.alloc(3));
+    }
+
+    #[test]
+    fn test_alloc_at() {
+        inner_test_alloc_at(b::LinearBitMap::new(10));
+        inner_test_alloc_at(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_counts<T: BitAllocator>(mut bm: T) {
+        assert_eq!((10, 0), (bm.free_count(), bm.used_count()));
+        assert_eq!(Some(0), bm.alloc(4));
+        assert_eq!(Some(4), bm.alloc(1));
+        bm.mark(3, 4);
+        assert_eq!((3, 7), (bm.free_count(), bm.used_count()));
+        bm.dealloc(6, 4);
+        bm.dealloc(6, 4);
+        assert_eq!((4, 6), (bm.free_count(), bm.used_count()));
+        assert_eq!(Some(8), bm.alloc_aligned(2, 4));
+        assert_eq!((2, 8), (bm.free_count(), bm.used_count()));
+    }
+
+    #[test]
+    fn test_counts() {
+        inner_test_counts(b::LinearBitMap::new(10));
+        inner_test_counts(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_allocated_ranges<T: BitAllocator>(mut bm: T) {
+        assert_eq!(None, bm.iter_allocated_ranges().next());
+        bm.mark(0, 2);
+        bm.mark(4, 3);
+        bm.mark(9, 1);
+        let mut it = bm.iter_allocated_ranges();
+        assert_eq!(Some((0, 2)), it.next());
+        assert_eq!(Some((4, 3)), it.next());
+        assert_eq!(Some((9, 1)), it.next());
+        assert_eq!(None, it.next());
+        bm.dealloc(0, 10);
+        bm.mark(0, 10);
+        assert_eq!(Some((0, 10)), bm.iter_allocated_ranges().next());
+    }
+
+    #[test]
+    fn test_allocated_ranges() {
+        inner_test_allocated_ranges(b::LinearBitMap::new(10));
+        inner_test_allocated_ranges(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_alloc_from<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(6), bm.alloc_from(6, 2));
+        assert_eq!(Some(8), bm.alloc_from(6, 2));
+        assert_eq!(Some(0), bm.alloc_from(6, 2));
+        assert_eq!(Some(2), bm.alloc_from(20, 3));
+        bm.dealloc(8, 2);
+        // Only [9, 10) lies past the hint, so the search wraps and finds [8, 10).
+        assert_eq!(Some(8), bm.alloc_from(9, 2));
+        bm.dealloc(4, 3);
+        assert_eq!(Some(4), bm.alloc_from(5, 3));
+        assert_eq!(None, bm.alloc_from(5, 1));
+    }
+
+    #[test]
+    fn test_alloc_from() {
+        inner_test_alloc_from(b::LinearBitMap::new(10));
+        inner_test_alloc_from(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_next_fit<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(0), bm.alloc(3));
+        assert_eq!(Some(3), bm.alloc(3));
+        bm.dealloc(0, 3);
+        assert_eq!(Some(6), bm.alloc(2));
+        assert_eq!(Some(0), bm.alloc(3));
+        bm.dealloc(3, 3);
+        assert_eq!(Some(4), bm.alloc_aligned(2, 2));
+        assert_eq!(Some(8), bm.alloc_aligned(2, 2));
+        assert_eq!(Some(3), bm.alloc(1));
+        assert_eq!(None, bm.alloc(1));
+    }
+
+    #[test]
+    fn test_next_fit() {
+        inner_test_next_fit(b::LinearBitMap::with_policy(10, NextFit::default()));
+        inner_test_next_fit(B::LinearBitMap::with_policy(10, NextFit::default()));
+    }
+
+    fn inner_test_best_fit<T: BitAllocator>(mut bm: T) {
+        bm.mark(0, 1);
+        bm.mark(5, 1);
+        bm.mark(8, 1);
+        // Free runs: [1, 5), [6, 8), [9, 10)
+        assert_eq!(Some(9), bm.alloc(1));
+        assert_eq!(Some(6), bm.alloc(2));
+        assert_eq!(Some(2), bm.alloc_aligned(2, 2));
+        assert_eq!(Some(1), bm.alloc(1));
+        assert_eq!(Some(4), bm.alloc(1));
+        assert_eq!(None, bm.alloc(1));
+    }
+
+    #[test]
+    fn test_best_fit() {
+        inner_test_best_fit(b::LinearBitMap::<BestFit>::new(10));
+        inner_test_best_fit(B::LinearBitMap::with_policy(10, BestFit));
+    }
+
+    fn inner_test_worst_fit<T: BitAllocator>(mut bm: T) {
+        bm.mark(3, 1);
+        bm.mark(6, 1);
+        // Free runs: [0, 3), [4, 6), [7, 10)
+        assert_eq!(Some(0), bm.alloc(1));
+        assert_eq!(Some(7), bm.alloc(1));
+        assert_eq!(Some(1), bm.alloc(1));
+        assert_eq!(Some(4), bm.alloc_aligned(2, 4));
+        assert_eq!(Some(8), bm.alloc(2));
+        assert_eq!(None, bm.alloc(2));
+    }
+
+    #[test]
+    fn test_worst_fit() {
+        inner_test_worst_fit(b::LinearBitMap::with_policy(10, WorstFit));
+        inner_test_worst_fit(B::LinearBitMap::with_policy(10, WorstFit));
+    }
+
+    fn inner_test_alloc_from_top<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(7), bm.alloc_from_top(3));
+        assert_eq!(Some(0), bm.alloc(2));
+        assert_eq!(Some(5), bm.alloc_from_top(2));
+        bm.dealloc(7, 1);
+        assert_eq!(Some(2), bm.alloc_from_top(3));
+        assert_eq!(Some(7), bm.alloc_from_top(1));
+        assert_eq!(None, bm.alloc_from_top(1));
+    }
+
+    #[test]
+    fn test_alloc_from_top() {
+        inner_test_alloc_from_top(b::LinearBitMap::new(10));
+        inner_test_alloc_from_top(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_bidirectional<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(0), bm.alloc(1));
+        assert_eq!(Some(7), bm.alloc(3));
+        assert_eq!(Some(4), bm.alloc(3));
+        assert_eq!(Some(1), bm.alloc(2));
+        bm.dealloc(7, 3);
+        assert_eq!(Some(7), bm.alloc(3));
+        assert_eq!(Some(3), bm.alloc(1));
+        assert_eq!(None, bm.alloc(1));
+    }
+
+    #[test]
+    fn test_bidirectional() {
+        inner_test_bidirectional(b::LinearBitMap::with_policy(10, Bidirectional::new(2)));
+        inner_test_bidirectional(B::LinearBitMap::with_policy(10, Bidirectional::new(2)));
+    }
+
+    fn inner_test_alloc_aligned_offset<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(1), bm.alloc_aligned_offset(2, 4, 1));
+        assert_eq!(Some(3), bm.alloc_aligned_offset(1, 2, 1));
+        assert_eq!(Some(0), bm.alloc_aligned_offset(1, 8, 0));
+        assert_eq!(Some(6), bm.alloc_aligned_offset(3, 4, 2));
+        assert_eq!(Some(5), bm.alloc_aligned_offset(1, 4, 1));
+        assert_eq!(None, bm.alloc_aligned_offset(2, 1, 0));
+        assert_eq!(Some(4), bm.alloc_aligned_offset(1, 2, 0));
+    }
+
+    #[test]
+    fn test_alloc_aligned_offset() {
+        inner_test_alloc_aligned_offset(b::LinearBitMap::new(10));
+        inner_test_alloc_aligned_offset(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_alloc_in_range<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(4), bm.alloc_in_range(2, 4, 8));
+        assert_eq!(Some(6), bm.alloc_in_range(2, 4, 8));
+        assert_eq!(None, bm.alloc_in_range(1, 4, 8));
+        assert_eq!(None, bm.alloc_in_range(3, 2, 5));
+        assert_eq!(Some(2), bm.alloc_in_range(2, 2, 5));
+        assert_eq!(Some(0), bm.alloc(2));
+        assert_eq!(Some(8), bm.alloc_in_range(2, 0, 10));
+    }
+
+    #[test]
+    fn test_alloc_in_range() {
+        inner_test_alloc_in_range(b::LinearBitMap::new(10));
+        inner_test_alloc_in_range(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_alloc_request<T: BitAllocator>(mut bm: T) {
+        let req = AllocRequest::new().size(3).not_crossing(4);
+        assert_eq!(Ok(0), req.allocate(&mut bm));
+        assert_eq!(Ok(4), req.allocate(&mut bm));
+        assert_eq!(Ok(8), AllocRequest::new().size(2).align(2).from_hint(7).allocate(&mut bm));
+        assert_eq!(Err(AllocError::Fragmented), AllocRequest::new().size(2).allocate(&mut bm));
+        assert_eq!(Ok(7), AllocRequest::new().from_hint(7).allocate(&mut bm));
+        assert_eq!(Err(AllocError::InvalidSize), AllocRequest::new().size(5).not_crossing(4).allocate(&mut bm));
+        assert_eq!(Err(AllocError::OutOfBounds), AllocRequest::new().within(8..11).allocate(&mut bm));
+        assert_eq!(Err(AllocError::InvalidAlignment), AllocRequest::new().align(0).allocate(&mut bm));
+        assert_eq!(Ok(3), AllocRequest::new().within(2..10).allocate(&mut bm));
+    }
+
+    #[test]
+    fn test_alloc_request() {
+        inner_test_alloc_request(b::LinearBitMap::new(10));
+        inner_test_alloc_request(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_alloc_aligned_npot<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(0), bm.alloc_aligned(2, 3));
+        assert_eq!(Some(3), bm.alloc_aligned(2, 3));
+        assert_eq!(Some(5), bm.alloc_aligned(4, 5));
+        assert_eq!(None, bm.alloc_aligned_offset(1, 3, 1));
+        assert_eq!(Some(2), bm.alloc_aligned_offset(1, 3, 2));
+        assert_eq!(Some(9), bm.alloc_aligned(1, 3));
+    }
+
+    #[test]
+    fn test_alloc_aligned_npot() {
+        inner_test_alloc_aligned_npot(b::LinearBitMap::new(10));
+        inner_test_alloc_aligned_npot(B::LinearBitMap::new(10));
+        inner_test_alloc_aligned_npot(b::LinearBitMap::with_policy(10, NextFit::default()));
+    }
+
+    fn inner_test_try_grow<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(0), bm.alloc(2));
+        assert_eq!(Some(2), bm.alloc(2));
+        bm.mark(7, 1);
+        assert_eq!(Ok(()), bm.try_grow(2, 2, 4));
+        assert_eq!(Err(AllocError::Occupied), bm.try_grow(2, 4, 6));
+        assert_eq!(Ok(()), bm.try_grow(2, 4, 5));
+        assert_eq!(Err(AllocError::InvalidSize), bm.try_grow(2, 5, 4));
+        assert_eq!(Err(AllocError::OutOfBounds), bm.try_grow(7, 1, 4));
+        assert_eq!(Ok(()), bm.try_grow(7, 1, 3));
+        assert_eq!(0, bm.free_count());
+    }
+
+    #[test]
+    fn test_try_grow() {
+        inner_test_try_grow(b::LinearBitMap::new(10));
+        inner_test_try_grow(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_shrink<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(0), bm.alloc(6));
+        assert_eq!(Ok(()), bm.shrink(0, 6, 4));
+        assert_eq!((6, 4), (bm.free_count(), bm.used_count()));
+        assert_eq!(Ok(()), bm.shrink(0, 4, 4));
+        assert_eq!(Err(AllocError::InvalidSize), bm.shrink(0, 4, 5));
+        assert_eq!(Err(AllocError::OutOfBounds), bm.shrink(8, 4, 1));
+        assert_eq!(Some(4), bm.alloc(6));
+        assert_eq!(Ok(()), bm.shrink(4, 6, 0));
+        assert_eq!(Some((0, 4)), bm.iter_allocated_ranges().next());
+        assert_eq!(6, bm.free_count());
+    }
+
+    #[test]
+    fn test_shrink() {
+        inner_test_shrink(b::LinearBitMap::new(10));
+        inner_test_shrink(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_alloc_scatter<T: BitAllocator>(mut bm: T) {
+        let mut out = [Extent::default(); 2];
+        bm.mark(2, 2);
+        bm.mark(5, 3);
+        // Free: [0, 2), [4, 5), [8, 10)
+        assert_eq!(Err(AllocError::Fragmented), bm.alloc_scatter(4, &mut out));
+        assert_eq!(5, bm.free_count());
+        assert_eq!(Ok(2), bm.alloc_scatter(3, &mut out));
+        assert_eq!([Extent { begin: 0, len: 2 }, Extent { begin: 4, len: 1 }], out);
+        assert_eq!(Err(AllocError::OutOfSpace), bm.alloc_scatter(3, &mut out));
+        assert_eq!(Ok(1), bm.alloc_scatter(2, &mut out));
+        assert_eq!(Extent { begin: 8, len: 2 }, out[0]);
+        assert_eq!(0, bm.free_count());
+    }
+
+    #[test]
+    fn test_alloc_scatter() {
+        inner_test_alloc_scatter(b::LinearBitMap::new(10));
+        inner_test_alloc_scatter(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_dealloc_batch<T: BitAllocator>(mut bm: T) {
+        bm.mark(0, 200);
+        bm.dealloc_batch(&[(150, 20), (10, 5), (60, 70), (12, 8), (199, 1)]);
+        assert_eq!(20 + 10 + 70 + 1, bm.free_count());
+        let mut it = bm.iter_allocated_ranges();
+        assert_eq!(Some((0, 10)), it.next());
+        assert_eq!(Some((20, 40)), it.next());
+        assert_eq!(Some((130, 20)), it.next());
+        assert_eq!(Some((170, 29)), it.next());
+        assert_eq!(None, it.next());
+    }
+
+    #[test]
+    fn test_dealloc_batch() {
+        inner_test_dealloc_batch(b::LinearBitMap::new(200));
+        inner_test_dealloc_batch(B::LinearBitMap::new(200));
+    }
+
+    fn inner_test_alloc_batch<T: BitAllocator>(mut bm: T) {
+        let mut out = [0; 3];
+        assert_eq!(Ok(()), bm.alloc_batch(&[2, 1, 3], &mut out));
+        assert_eq!([0, 2, 3], out);
+        assert_eq!(Err(AllocError::OutOfSpace), bm.alloc_batch(&[1, 4], &mut out));
+        assert_eq!(Err(AllocError::InvalidSize), bm.alloc_batch(&[1, 0], &mut out));
+        assert_eq!(4, bm.free_count());
+        assert_eq!(Some(6), bm.alloc(4));
+    }
+
+    #[test]
+    fn test_alloc_batch() {
+        inner_test_alloc_batch(b::LinearBitMap::new(10));
+        inner_test_alloc_batch(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_dealloc_checked<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(0), bm.alloc(4));
+        assert_eq!(Ok(()), bm.dealloc_checked(1, 2));
+        assert_eq!(Err(AllocError::DoubleFree), bm.dealloc_checked(0, 2));
+        assert_eq!(Err(AllocError::DoubleFree), bm.dealloc_checked(3, 2));
+        assert_eq!(Err(AllocError::OutOfBounds), bm.dealloc_checked(3, 8));
+        assert_eq!(2, bm.used_count());
+        assert_eq!(Ok(()), bm.dealloc_checked(3, 1));
+        assert_eq!(Ok(()), bm.dealloc_checked(0, 1));
+        assert_eq!(0, bm.used_count());
+    }
+
+    #[test]
+    fn test_dealloc_checked() {
+        inner_test_dealloc_checked(b::LinearBitMap::new(10));
+        inner_test_dealloc_checked(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_reserve<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Ok(()), bm.reserve(2, 3));
+        assert_eq!(Ok(()), bm.alloc_at(6, 2));
+        assert_eq!(Err(ReserveConflict::Allocated(6)), bm.reserve(5, 3));
+        assert_eq!(Err(ReserveConflict::Allocated(2)), bm.reserve(0, 8));
+        assert_eq!(Err(ReserveConflict::Allocated(7)), bm.reserve(7, 3));
+        assert_eq!(Err(ReserveConflict::OutOfBounds), bm.reserve(8, 3));
+        assert_eq!(Err(ReserveConflict::OutOfBounds), bm.reserve(8, 0));
+        assert_eq!(Ok(()), bm.reserve(8, 2));
+        assert_eq!(3, bm.free_count());
+    }
+
+    #[test]
+    fn test_reserve() {
+        inner_test_reserve(b::LinearBitMap::new(10));
+        inner_test_reserve(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_set_all<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(0), bm.alloc(3));
+        bm.fill();
+        assert_eq!(0, bm.free_count());
+        assert_eq!(Some((0, 130)), bm.iter_allocated_ranges().next());
+        assert_eq!(None, bm.alloc(1));
+        bm.clear();
+        assert_eq!(130, bm.free_count());
+        assert_eq!(None, bm.iter_allocated_ranges().next());
+        assert_eq!(Some(0), bm.alloc(130));
+        bm.set_all(false);
+        assert_eq!(Some(0), bm.alloc(1));
+    }
+
+    #[test]
+    fn test_set_all() {
+        inner_test_set_all(b::LinearBitMap::new(130));
+        inner_test_set_all(B::LinearBitMap::new(130));
+    }
+
+    fn inner_test_introspection<T: BitAllocatorInit>(mut bm: T) {
+        let capacity = bm.capacity();
+        assert_eq!(Some(0), bm.alloc(4));
+        assert_eq!((10, 6), (bm.size(), bm.remaining()));
+        let mut bm = T::new(capacity);
+        assert_eq!((capacity, capacity), (bm.size(), bm.remaining()));
+        bm.mark(capacity - 2, 2);
+        assert_eq!(Some(capacity - 3), bm.alloc_from_top(1));
+        assert_eq!(Some((capacity - 3, 3)), bm.iter_allocated_ranges().next());
+        assert_eq!(Some(0), bm.alloc(capacity - 3));
+        assert_eq!((0, capacity), (bm.remaining(), bm.used_count()));
+    }
+
+    #[test]
+    fn test_introspection() {
+        inner_test_introspection(b::LinearBitMap::new(10));
+        inner_test_introspection(B::LinearBitMap::new(10));
+        // A narrow hint caps the capacity.
+        inner_test_introspection(b::LinearBitMap::<FirstFit, u8>::new(10));
+        let region = boot::MemoryRegion { base: 0, len: 1000 * 0x1000, usable: true };
+        let (bm, frames) = boot::from_memory_map::<b::LinearBitMap<FirstFit, u8>, _>(core::iter::once(region), 0x1000);
+        assert_eq!((255, 0..255), (bm.capacity(), frames));
+    }
+
+    fn inner_test_alloc_guarded<T: BitAllocator>(mut bm: T) {
+        {
+            let a = bm.alloc_guarded(4).unwrap();
+            assert_eq!(0, a.begin());
+            assert_eq!(Extent { begin: 0, len: 4 }, a.extent());
+        }
+        assert_eq!(10, bm.free_count());
+        let leaked = bm.alloc_guarded(3).unwrap().leak();
+        assert_eq!(Extent { begin: 0, len: 3 }, leaked);
+        assert_eq!(7, bm.free_count());
+        assert!(bm.alloc_guarded(8).is_none());
+        assert_eq!(Some(3), bm.alloc_guarded(7).map(|a| a.begin()));
+        assert_eq!(7, bm.free_count());
+    }
+
+    #[test]
+    fn test_alloc_guarded() {
+        inner_test_alloc_guarded(b::LinearBitMap::new(10));
+        inner_test_alloc_guarded(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_handles<T: BitAllocator>(bm: T) {
+        let mut hm = HandleAllocator::new(bm);
+        let h = hm.alloc(4).unwrap();
+        assert_eq!((0, 0), (h.index(), h.generation()));
+        assert!(hm.is_live(h));
+        assert_eq!(Ok(()), hm.dealloc_handle(h));
+        assert!(!hm.is_live(h));
+        assert_eq!(Err(AllocError::StaleHandle), hm.dealloc_handle(h));
+        let h2 = hm.alloc(2).unwrap();
+        assert_eq!((0, 1), (h2.index(), h2.generation()));
+        assert_eq!(Err(AllocError::StaleHandle), hm.dealloc_handle(h));
+        assert_eq!(8, hm.inner().free_count());
+        assert_eq!(Ok(()), hm.dealloc_handle(h2));
+        assert_eq!(10, hm.into_inner().free_count());
+    }
+
+    #[test]
+    fn test_handles() {
+        inner_test_handles(b::LinearBitMap::new(10));
+        inner_test_handles(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_clone<T: BitAllocator + Clone>(mut bm: T) {
+        bm.mark(2, 3);
+        let mut copy = bm.clone();
+        assert_eq!(Some(5), copy.alloc(4));
+        assert_eq!((7, 3), (bm.free_count(), copy.free_count()));
+        assert_eq!(Some(0), bm.alloc(2));
+        copy.clone_from(&bm);
+        assert_eq!(Some((0, 5)), copy.iter_allocated_ranges().next());
+    }
+
+    #[test]
+    fn test_clone() {
+        inner_test_clone(b::LinearBitMap::new(10));
+        inner_test_clone(B::LinearBitMap::new(10));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut bm = b::LinearBitMap::with_policy(10, NextFit::default());
+        let mut byte = B::LinearBitMap::with_policy(10, NextFit::default());
+        assert_eq!((Some(0), Some(0)), (bm.alloc(2), byte.alloc(2)));
+        let (saved, byte_saved) = (bm.snapshot(), byte.snapshot());
+        assert_eq!((Some(2), Some(2)), (bm.alloc(6), byte.alloc(6)));
+        bm.restore(&saved);
+        byte.restore(&byte_saved);
+        assert_eq!((8, 8), (bm.free_count(), byte.free_count()));
+        assert_eq!((Some(2), Some(2)), (bm.alloc(1), byte.alloc(1)));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde() {
+        extern crate std;
+        use std::string::ToString;
+        let mut bm = b::LinearBitMap::new(70);
+        let mut byte = B::LinearBitMap::new(70);
+        bm.mark(3, 65);
+        byte.mark(3, 65);
+        let json = serde_json::to_string(&bm).unwrap();
+        assert_eq!(r#"{"size":70,"words":[18446744073709551608,15]}"#, json);
+        assert_eq!(json, serde_json::to_string(&byte).unwrap());
+        // Each reads back as the other, from a map or a sequence.
+        let byte: B::LinearBitMap = serde_json::from_str(&json).unwrap();
+        let bm: b::LinearBitMap = serde_json::from_str("[70, [18446744073709551608, 15]]").unwrap();
+        assert_eq!((5, 5), (bm.free_count(), byte.free_count()));
+        assert_eq!((Some((3, 65)), Some((3, 65))), (bm.iter_allocated_ranges().next(), byte.iter_allocated_ranges().next()));
+        let err = |json: &str| serde_json::from_str::<b::LinearBitMap>(json).unwrap_err().to_string();
+        assert!(err(r#"{"size":2000,"words":[]}"#).contains("a supported size"));
+        assert!(err(r#"{"size":70,"words":[1]}"#).contains("one word per 64 bits"));
+        assert!(err(r#"{"size":70,"words":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0]}"#).contains("at most 24 words"));
+        assert!(err(r#"{"size":70}"#).contains("missing field"));
+        let narrow = serde_json::from_str::<b::LinearBitMap<FirstFit, u8>>(r#"{"size":300,"words":[0,0,0,0,0]}"#);
+        assert!(narrow.unwrap_err().to_string().contains("within the capacity"));
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut bm = b::LinearBitMap::new(10);
+        let mut byte = B::LinearBitMap::new(10);
+        bm.mark(2, 4);
+        byte.mark(2, 4);
+        assert_eq!(Err(AllocError::Occupied), bm.resize(5));
+        assert_eq!(Err(AllocError::Occupied), byte.resize(5));
+        assert_eq!(Err(AllocError::OutOfBounds), bm.resize(MAX_BITS + 1));
+        assert_eq!(Err(AllocError::OutOfBounds), byte.resize(MAX_BITS + 1));
+        assert_eq!((Ok(()), Ok(())), (bm.resize(6), byte.resize(6)));
+        assert_eq!((6, 6), (bm.size(), byte.size()));
+        assert_eq!((None, None), (bm.alloc(3), byte.alloc(3)));
+        assert_eq!((Ok(()), Ok(())), (bm.resize(100), byte.resize(100)));
+        assert_eq!((96, 96), (bm.free_count(), byte.free_count()));
+        assert_eq!((Some(6), Some(6)), (bm.alloc(90), byte.alloc(90)));
+    }
+
+    #[test]
+    fn test_split_off_merge() {
+        let mut bm = b::LinearBitMap::new(10);
+        let mut byte = B::LinearBitMap::new(10);
+        bm.mark(2, 6);
+        byte.mark(2, 6);
+        let (mut hi, mut byte_hi) = (bm.split_off(4), byte.split_off(4));
+        assert_eq!((4, 2), (bm.size(), bm.free_count()));
+        assert_eq!((4, 2), (byte.size(), byte.free_count()));
+        assert_eq!((6, 2), (hi.size(), hi.free_count()));
+        assert_eq!((6, 2), (byte_hi.size(), byte_hi.free_count()));
+        assert_eq!((Some(4), Some(4)), (hi.alloc(1), byte_hi.alloc(1)));
+        let (bm, byte) = (bm.merge(hi), byte.merge(byte_hi));
+        assert_eq!((10, 10), (bm.size(), byte.size()));
+        assert_eq!(Some((2, 7)), bm.iter_allocated_ranges().next());
+        assert_eq!(Some((2, 7)), byte.iter_allocated_ranges().next());
+        assert_eq!((3, 3), (bm.free_count(), byte.free_count()));
+    }
+
+    fn inner_test_sub_allocator<T: BitAllocator>(mut bm: T) {
+        bm.mark(0, 3);
+        {
+            let mut sub = SubAllocator::new(&mut bm, 2, 6);
+            assert_eq!((2, 6, 5), (sub.base(), sub.size(), sub.free_count()));
+            assert_eq!(Some(1), sub.alloc(2));
+            assert_eq!(Some(4), sub.alloc_aligned(2, 2));
+            assert_eq!(Some(3), sub.alloc(1));
+            assert_eq!(None, sub.alloc(1));
+            sub.dealloc(0, 2);
+            assert_eq!(2, sub.free_count());
+            assert_eq!(Some((2, 4)), sub.iter_allocated_ranges().next());
+        }
+        assert!(bm.is_allocated(1) && !bm.is_allocated(2));
+        assert_eq!(Some((4, 4)), bm.iter_allocated_ranges().nth(1));
+        assert_eq!(4, bm.free_count());
+    }
+
+    #[test]
+    fn test_sub_allocator() {
+        inner_test_sub_allocator(b::LinearBitMap::new(10));
+        inner_test_sub_allocator(B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_dyn(bm: &mut dyn BitAllocator) {
+        assert_eq!(Some(0), bm.alloc(3));
+        assert_eq!(Some(4), bm.alloc_aligned(2, 4));
+        assert_eq!(Ok(()), bm.dealloc_checked(0, 3));
+        assert_eq!((4, 6), (bm.find_next(0, true), bm.find_next(4, false)));
+        assert_eq!(8, bm.free_count());
+    }
+
+    #[test]
+    fn test_dyn() {
+        inner_test_dyn(&mut b::LinearBitMap::new(10));
+        inner_test_dyn(&mut B::LinearBitMap::new(10));
+    }
+
+    fn inner_test_narrow_handles<T: BitAllocator>(bm: T) {
+        let mut hm = HandleAllocator::<_, u16>::with_index_type(bm);
+        let h = hm.alloc(4).unwrap();
+        assert_eq!(Some(4), hm.alloc(6).map(|h| h.index()));
+        assert_eq!(Ok(()), hm.dealloc_handle(h));
+        assert_eq!(Err(AllocError::StaleHandle), hm.dealloc_handle(h));
+        assert_eq!(4, hm.inner().free_count());
+        assert!(core::mem::size_of::<AllocHandle<u16>>() < core::mem::size_of::<AllocHandle>());
+    }
+
+    #[test]
+    fn test_narrow_handles() {
+        inner_test_narrow_handles(b::LinearBitMap::new(10));
+        inner_test_narrow_handles(B::LinearBitMap::new(10));
+        assert_eq!(60000, u16::from_usize(60000));
+        assert_eq!(usize::MAX, usize::MAX.to_usize());
+    }
+
+    #[test]
+    fn test_narrow_cursors() {
+        extern crate std;
+        inner_test_next_fit(b::LinearBitMap::<_, u8>::with_index_type(10, NextFit::<u8>::with_index_type()));
+        inner_test_next_fit(B::LinearBitMap::with_policy(10, NextFit::<u16>::with_index_type()));
+        inner_test_bidirectional(b::LinearBitMap::with_policy(10, Bidirectional::<u8>::with_index_type(2)));
+        inner_test_first_free_hint(b::LinearBitMap::<_, u16>::with_index_type(300, FirstFit));
+        assert!(core::mem::size_of::<NextFit<u16>>() < core::mem::size_of::<NextFit>());
+        // The hint must be able to hold the size.
+        assert!(std::panic::catch_unwind(|| b::LinearBitMap::<_, u8>::with_index_type(256, FirstFit)).is_err());
+        let mut bm = b::LinearBitMap::<_, u8>::with_index_type(200, FirstFit);
+        assert_eq!(Err(AllocError::OutOfBounds), bm.resize(300));
+    }
+
+    fn inner_test_find_commit<T: BitAllocator>(mut bm: T) {
+        bm.mark(0, 1);
+        let req = AllocRequest::new().size(3).align(2);
+        assert_eq!(Ok(2), bm.find(&req));
+        assert_eq!(9, bm.free_count());
+        let retry = req.clone().within(3..10);
+        assert_eq!(Ok(4), bm.find(&retry));
+        assert_eq!(Ok(()), bm.commit(4, 3));
+        assert_eq!(Err(AllocError::Occupied), bm.commit(4, 3));
+        assert_eq!(Err(AllocError::Fragmented), bm.find(&retry));
+        assert_eq!(Ok(2), bm.find(&req.size(2)));
+    }
+
+    #[test]
+    fn test_find_commit() {
+        inner_test_find_commit(b::LinearBitMap::new(10));
+        inner_test_find_commit(B::LinearBitMap::new(10));
+    }
+
+    #[test]
+    fn test_const_new() {
+        static BM: b::LinearBitMap = b::LinearBitMap::new(10);
+        const BYTE: B::LinearBitMap<NextFit> = B::LinearBitMap::with_policy(10, NextFit::new());
+        const BIDI: b::LinearBitMap<Bidirectional> =
+            b::LinearBitMap::with_policy(10, Bidirectional::new(2));
+        assert_eq!((10, 10), (BM.size(), BM.free_count()));
+        let (mut byte, mut bidi) = (BYTE, BIDI);
+        assert_eq!((Some(0), Some(7)), (byte.alloc(3), bidi.alloc(3)));
+    }
+
+    fn inner_test_init<T: BitAllocatorInit>() {
+        let mut bm = T::new_filled(10);
+        assert_eq!(0, bm.free_count());
+        assert_eq!(None, bm.alloc(1));
+        bm.dealloc(6, 4);
+        assert_eq!(Some(6), bm.alloc(2));
+        let mut bm = T::with_reserved(10, &[(0, 2), (5, 1), (1, 2)]);
+        assert_eq!(6, bm.free_count());
+        assert_eq!(Some(6), bm.alloc(3));
+        assert_eq!(Some((0, 3)), bm.iter_allocated_ranges().next());
+    }
+
+    #[test]
+    fn test_init() {
+        inner_test_init::<b::LinearBitMap>();
+        inner_test_init::<B::LinearBitMap>();
+    }
+
+    #[test]
+    fn test_from_words() {
+        let mut bm = b::LinearBitMap::from_words(70, &[0xf0f0, !0, !0]);
+        assert_eq!((70, 56), (bm.size(), bm.free_count()));
+        assert_eq!(Some((4, 4)), bm.iter_allocated_ranges().next());
+        assert_eq!(Some((64, 6)), bm.iter_allocated_ranges().last());
+        assert_eq!(Some(0), bm.alloc(4));
+        assert_eq!(Some(8), bm.alloc_aligned(4, 8));
+        assert_eq!(Some(16), bm.alloc(48));
+        assert_eq!(0, bm.free_count());
+        let byte = B::LinearBitMap::from_bools(&[true, false, false, true]);
+        assert_eq!((4, 2), (byte.size(), byte.free_count()));
+        assert_eq!(Some((3, 1)), byte.iter_allocated_ranges().nth(1));
+    }
+
+    #[test]
+    fn test_export_words() {
+        let mut bm = b::LinearBitMap::new(70);
+        let mut byte = B::LinearBitMap::new(70);
+        for &(begin, n) in &[(1, 2), (60, 8)] {
+            bm.mark(begin, n);
+            byte.mark(begin, n);
+        }
+        let expected = [0xf000_0000_0000_0006, 0xf];
+        assert_eq!(&expected[..], bm.as_words());
+        let (mut out, mut byte_out) = ([!0; 3], [!0; 3]);
+        assert_eq!(2, bm.copy_words_into(&mut out));
+        assert_eq!(2, byte.copy_words_into(&mut byte_out));
+        assert_eq!([expected[0], expected[1], !0], out);
+        assert_eq!(out, byte_out);
+        assert_eq!(&[false, true, true, false], &byte.as_bools()[..4]);
+        assert_eq!(0, b::LinearBitMap::new(0).as_words().len());
+    }
+
+    #[test]
+    fn test_debug() {
+        extern crate std;
+        let expected = "LinearBitMap { size: 10, used: 4, free: 6, free_runs: 2, \
+                        largest_free_run: 5, policy: FirstFit }";
+        let mut bm = b::LinearBitMap::new(10);
+        bm.mark(1, 4);
+        assert_eq!(expected, std::format!("{:?}", bm));
+        let mut byte = B::LinearBitMap::new(10);
+        byte.mark(1, 4);
+        assert_eq!(expected, std::format!("{:?}", byte));
+    }
+
+    fn inner_test_visualize<T: BitAllocator>(mut bm: T) {
+        extern crate std;
+        bm.mark(1, 3);
+        bm.mark(9, 1);
+        assert_eq!("0 .###\n4 ....\n8 .#", std::format!("{}", bm.visualize().columns(4)));
+        assert_eq!("0 ++.#", std::format!("{}", bm.visualize().fit_width(4)));
+        assert_eq!("0 .###.....#", std::format!("{}", bm.visualize()));
+    }
+
+    #[test]
+    fn test_visualize() {
+        extern crate std;
+        inner_test_visualize(b::LinearBitMap::new(10));
+        inner_test_visualize(B::LinearBitMap::new(10));
+        let mut bm = b::LinearBitMap::new(3);
+        bm.mark(0, 1);
+        assert_eq!("0 #..", std::format!("{}", bm));
+        assert_eq!("0 #..", std::format!("{}", B::LinearBitMap::from_bools(&[true, false, false])));
+    }
+
+    fn inner_test_rle<T: BitAllocatorInit>() {
+        let mut bm = T::new(1000);
+        bm.mark(0, 3);
+        bm.mark(200, 800);
+        let mut out = [0; 16];
+        assert_eq!(8, bm.rle_len());
+        assert_eq!(8, bm.encode_rle(&mut out));
+        assert_eq!([0xe8, 0x07, 0, 3, 197, 1, 0xa0, 0x06], out[..8]);
+        let decoded = T::decode_rle(&out[..8]).unwrap();
+        assert_eq!((1000, 197), (decoded.size(), decoded.free_count()));
+        assert_eq!(Some((200, 800)), decoded.iter_allocated_ranges().nth(1));
+        assert_eq!(1, T::new(0).encode_rle(&mut out));
+        assert_eq!(Some(0), T::decode_rle(&out[..1]).map(|bm| bm.size()));
+        assert!(T::decode_rle(&[10, 4]).is_none());
+        assert!(T::decode_rle(&[10, 4, 7]).is_none());
+        assert!(T::decode_rle(&[10, 4, 6, 0]).is_none());
+        assert!(T::decode_rle(&[10, 4, 0, 6]).is_none());
+        assert!(T::decode_rle(&[10, 0, 0, 10]).is_none());
+        assert_eq!(Some(0), T::decode_rle(&[10, 0, 10]).map(|bm| bm.free_count()));
+        // A size of 10 spelled out to the last bits of a usize, the last
+        // byte with a bit past them.
+        let k = (usize::BITS / 7) as usize;
+        let mut long = [0x80; 16];
+        long[0] = 0x8a;
+        (long[k], long[k + 1]) = (0, 10);
+        assert_eq!(Some(10), T::decode_rle(&long[..k + 2]).map(|bm| bm.size()));
+        long[k] = 1 << (usize::BITS as usize - 7 * k);
+        assert!(T::decode_rle(&long[..k + 2]).is_none());
+    }
+
+    #[test]
+    fn test_rle() {
+        inner_test_rle::<b::LinearBitMap>();
+        inner_test_rle::<B::LinearBitMap>();
+        // The size must fit the capacity of the bitmap decoded into.
+        let mut out = [0; 8];
+        let len = b::LinearBitMap::<FirstFit>::new(300).encode_rle(&mut out);
+        assert!(b::LinearBitMap::<FirstFit, u8>::decode_rle(&out[..len]).is_none());
+    }
+
+    fn inner_test_layout<T: BitAllocatorInit>() {
+        let mut bm = T::new(20);
+        bm.mark(0, 1);
+        bm.mark(9, 1);
+        let mut out = [0xff; 5];
+        let layouts = [
+            (Layout::new(2, Endian::Little, BitOrder::LsbFirst), [0x01, 0x02, 0x00, 0x00]),
+            (Layout::new(2, Endian::Big, BitOrder::LsbFirst), [0x02, 0x01, 0x00, 0x00]),
+            (Layout::new(2, Endian::Little, BitOrder::MsbFirst), [0x40, 0x80, 0x00, 0x00]),
+            (Layout::new(2, Endian::Big, BitOrder::MsbFirst), [0x80, 0x40, 0x00, 0x00]),
+        ];
+        for (layout, expected) in layouts.iter() {
+            assert_eq!(4, bm.export_bytes(layout, &mut out));
+            assert_eq!(expected, &out[..4]);
+            let back = T::import_bytes(20, layout, &out);
+            assert_eq!(Some((9, 1)), back.iter_allocated_ranges().nth(1));
+            assert_eq!(18, back.free_count());
+        }
+        assert_eq!(8, bm.export_bytes(&Layout::LE64, &mut [0; 8]));
+    }
+
+    #[test]
+    fn test_layout() {
+        inner_test_layout::<b::LinearBitMap>();
+        inner_test_layout::<B::LinearBitMap>();
+        let mut bm = b::LinearBitMap::new(70);
+        bm.mark(3, 64);
+        let mut out = [0; 16];
+        bm.export_bytes(&Layout::LE64, &mut out);
+        assert_eq!(bm.as_words()[0].to_le_bytes(), out[..8]);
+        assert_eq!(bm.as_words()[1].to_le_bytes(), out[8..]);
+    }
+
+    #[test]
+    fn test_ext2() {
+        // 20 blocks; ext4 sets the padding bits of the last byte.
+        let mut disk = [0b0000_0011, 0x00, 0xf0];
+        {
+            let mut bm = SliceBitMap::ext2(&mut disk, 20);
+            assert_eq!((20, 18), (bm.size(), bm.free_count()));
+            assert_eq!(Some(2), bm.alloc(7));
+            assert_eq!(Some(16), bm.alloc_aligned(4, 8));
+            bm.dealloc(0, 1);
+            assert_eq!(None, bm.alloc(8));
+        }
+        assert_eq!([0b1111_1110, 0b0000_0001, 0xff], disk);
+        let back: b::LinearBitMap = BitAllocatorInit::import_bytes(20, &Layout::EXT2, &disk);
+        assert_eq!(Some((1, 8)), back.iter_allocated_ranges().next());
+    }
+
+    #[test]
+    fn test_ntfs() {
+        let mut bm = B::LinearBitMap::new(70);
+        bm.mark(0, 16);
+        bm.mark(66, 2);
+        let mut bitmap = [0xaa; 24];
+        assert_eq!(16, bm.export_bytes(&Layout::NTFS, &mut bitmap));
+        assert_eq!([0xff, 0xff, 0, 0, 0, 0, 0, 0, 0b1100, 0, 0, 0, 0, 0, 0, 0], bitmap[..16]);
+        {
+            let mut volume = SliceBitMap::ntfs(&mut bitmap[..16], 70);
+            assert_eq!(52, volume.free_count());
+            assert_eq!(Some(68), volume.alloc_from_top(2));
+        }
+        assert_eq!(0b11_1100, bitmap[8]);
+        let back: B::LinearBitMap = BitAllocatorInit::import_bytes(70, &Layout::NTFS, &bitmap);
+        assert_eq!(Some((66, 4)), back.iter_allocated_ranges().nth(1));
+    }
+
+    #[test]
+    fn test_linux() {
+        use crate::linux::*;
+        let size = 2 * BITS_PER_LONG + 10;
+        let mut map = [0usize; 3];
+        assert_eq!(size, find_first_bit(&map, size));
+        assert_eq!(0, find_first_zero_bit(&map, size));
+        bitmap_set(&mut map, 3, BITS_PER_LONG);
+        assert_eq!([!0 << 3, 0b111, 0], map);
+        assert_eq!(3, find_first_bit(&map, size));
+        assert_eq!(BITS_PER_LONG + 3, find_next_zero_bit(&map, size, 5));
+        assert_eq!(size, find_next_bit(&map, size, BITS_PER_LONG + 3));
+        bitmap_clear(&mut map, 4, 2);
+        assert_eq!(4, find_next_zero_bit(&map, size, 3));
+        assert_eq!(6, find_next_bit(&map, size, 4));
+        bitmap_set(&mut map, 0, size);
+        assert_eq!(size, find_first_zero_bit(&map, size));
+        assert_eq!(size, find_next_bit(&map, size, size));
+        map[2] = !0;
+        assert_eq!(size, find_next_zero_bit(&map, size, 0));
+    }
+
+    #[test]
+    fn test_slice_words() {
+        let mut words = [0x0f, 0, !0];
+        {
+            let mut bm = SliceBitMap::from_words(&mut words, 140);
+            assert_eq!((140, 124), (bm.size(), bm.free_count()));
+            assert_eq!(Some(4), bm.alloc(64));
+            assert_eq!(Some(68), bm.alloc_aligned(2, 4));
+            bm.dealloc(0, 2);
+            assert_eq!(None, bm.alloc(71));
+        }
+        assert_eq!([!0 << 2, 0b11_1111, !0], words);
+        let bm = b::LinearBitMap::from_words(140, &words);
+        assert_eq!(Some((2, 68)), bm.iter_allocated_ranges().next());
+    }
+
+    #[test]
+    fn test_init_at() {
+        extern crate std;
+        use core::mem::MaybeUninit;
+        use std::boxed::Box;
+        // Garbage left in the memory must not show through.
+        let slot = Box::leak(Box::new(MaybeUninit::<b::LinearBitMap>::uninit()));
+        unsafe { slot.as_mut_ptr().write_bytes(0xa5, 1) };
+        let bm = unsafe { b::LinearBitMap::init_at(slot.as_mut_ptr() as *mut u8, 100) };
+        assert_eq!((100, 100, 0), (bm.size(), bm.free_count(), bm.hole_count()));
+        assert_eq!(b::LinearBitMap::new(100).as_words(), bm.as_words());
+        assert_eq!((Some(0), Some(1)), (bm.alloc(1), bm.alloc(99)));
+        assert_eq!(None, bm.alloc(1));
+        let slot = Box::leak(Box::new(MaybeUninit::<B::LinearBitMap>::uninit()));
+        unsafe { slot.as_mut_ptr().write_bytes(0xff, 1) };
+        let byte = unsafe { B::LinearBitMap::init_at(slot.as_mut_ptr() as *mut u8, 7) };
+        assert_eq!((7, 0, 0), (byte.free_count(), byte.hole_count(), byte.pinned_count()));
+        assert_eq!(Some(0), byte.alloc(7));
+        assert_eq!(None, byte.alloc(1));
+    }
+
+    fn inner_test_self_hosted<T: BitAllocatorInit + 'static>() {
+        extern crate std;
+        use std::boxed::Box;
+        let region = Box::leak(Box::new([0u64; 800]));
+        let meta = core::mem::size_of::<T>().div_ceil(64);
+        let bm = unsafe { boot::self_hosted::<T>(region.as_mut_ptr() as *mut u8, 6400, 64) };
+        assert_eq!((100, 100 - meta), (bm.size(), bm.free_count()));
+        assert_eq!(Some((0, meta)), bm.iter_allocated_ranges().next());
+        assert_eq!(Some(meta), bm.alloc(1));
+    }
+
+    #[test]
+    fn test_self_hosted() {
+        inner_test_self_hosted::<b::LinearBitMap>();
+        inner_test_self_hosted::<B::LinearBitMap>();
+    }
+
+    fn inner_test_memory_map<T: BitAllocatorInit>() {
+        use crate::boot::{from_memory_map, MemoryRegion};
+        let map = [
+            MemoryRegion { base: 0x0, len: 0x1000, usable: false },
+            MemoryRegion { base: 0x1800, len: 0x3800, usable: true },
+            MemoryRegion { base: 0x3000, len: 0x10, usable: false },
+            MemoryRegion { base: 0x8000, len: 0x2000, usable: true },
+            MemoryRegion { base: usize::MAX - 0xfff, len: 0x1000, usable: false },
+        ];
+        let (bm, frames) = from_memory_map::<T, _>(map.iter().copied(), 0x1000);
+        assert_eq!(2..10, frames);
+        assert_eq!(8, bm.size());
+        assert_eq!(4, bm.free_count());
+        // Frame 3 is reserved, frames 5 to 7 are a hole.
+        assert_eq!(Some((1, 1)), bm.iter_allocated_ranges().next());
+        assert_eq!(Some((3, 3)), bm.iter_allocated_ranges().nth(1));
+        let (empty, frames) = from_memory_map::<T, _>(map[..1].iter().copied(), 0x1000);
+        assert_eq!((0, 0..0), (empty.size(), frames));
+    }
+
+    #[test]
+    fn test_memory_map() {
+        inner_test_memory_map::<b::LinearBitMap>();
+        inner_test_memory_map::<B::LinearBitMap>();
+    }
+
+    fn inner_test_device_tree<T: BitAllocatorInit>() {
+        let memory = [(0x8000_0100, 0x10000)];
+        let reserved = [(0x8000_4000, 0x1800), (0x7000_0000, 0x1000)];
+        let (bm, blocks) = boot::from_device_tree::<T>(&memory, &reserved, 0x1000);
+        assert_eq!(0x80001..0x80010, blocks);
+        assert_eq!(15 - 2, bm.free_count());
+        assert_eq!(Some((3, 2)), bm.iter_allocated_ranges().next());
+    }
+
+    #[test]
+    fn test_device_tree() {
+        inner_test_device_tree::<b::LinearBitMap>();
+        inner_test_device_tree::<B::LinearBitMap>();
+    }
+
+    fn inner_test_holes<T: BitAllocator>(mut bm: T) {
+        // Bits 4 and 5 are holes.
+        assert_eq!((8, 0, 2), (bm.free_count(), bm.used_count(), bm.hole_count()));
+        assert!(bm.is_hole(4) && !bm.is_hole(3));
+        assert_eq!(Some(0), bm.alloc(4));
+        assert_eq!(Some(6), bm.alloc(2));
+        assert_eq!(Err(AllocError::Hole), bm.dealloc_checked(3, 2));
+        assert_eq!(Err(AllocError::Hole), bm.try_dealloc(5, 1));
+        bm.dealloc(0, 10);
+        assert_eq!((8, 0), (bm.free_count(), bm.used_count()));
+        bm.dealloc_batch(&[(4, 2)]);
+        bm.fill();
+        bm.clear();
+        assert_eq!(Some((4, 2)), bm.iter_allocated_ranges().next());
+        assert_eq!(None, bm.alloc(5));
+    }
+
+    #[test]
+    fn test_holes() {
+        let mut bm = b::LinearBitMap::new(10);
+        bm.mark_hole(4, 2);
+        inner_test_holes(bm.snapshot());
+        let mut hi = bm.split_off(5);
+        assert_eq!((1, 1), (bm.hole_count(), hi.hole_count()));
+        assert!(hi.is_hole(0));
+        assert_eq!(Some(1), hi.alloc(1));
+        let bm = bm.merge(b::LinearBitMap::new(5));
+        assert_eq!((1, 9), (bm.hole_count(), bm.free_count()));
+        let mut byte = B::LinearBitMap::new(10);
+        byte.mark_hole(4, 2);
+        inner_test_holes(byte.snapshot());
+        let hi = byte.split_off(5);
+        let byte = byte.merge(hi);
+        assert_eq!((2, 8), (byte.hole_count(), byte.free_count()));
+        inner_test_holes(byte);
+    }
+
+    #[test]
+    fn test_hot_add() {
+        let mut bm = b::LinearBitMap::new(10);
+        let mut byte = B::LinearBitMap::new(10);
+        bm.fill();
+        byte.fill();
+        assert_eq!((Ok(()), Ok(())), (bm.hot_add(14, 6), byte.hot_add(14, 6)));
+        assert_eq!((20, 20), (bm.size(), byte.size()));
+        assert_eq!((4, 4), (bm.hole_count(), byte.hole_count()));
+        assert_eq!((6, 6), (bm.free_count(), byte.free_count()));
+        assert_eq!((Some(14), Some(14)), (bm.alloc_aligned(2, 7), byte.alloc_aligned(2, 7)));
+        assert_eq!(Err(AllocError::Occupied), bm.hot_add(9, 2));
+        assert_eq!(Err(AllocError::Occupied), byte.hot_add(9, 2));
+        assert_eq!((Ok(()), Ok(())), (bm.hot_add(11, 2), byte.hot_add(11, 2)));
+        assert!(bm.is_hole(10) && !bm.is_hole(11) && bm.is_hole(13));
+        assert!(byte.is_hole(10) && !byte.is_hole(11) && byte.is_hole(13));
+        assert_eq!((6, 6), (bm.free_count(), byte.free_count()));
+        assert_eq!(Err(AllocError::OutOfBounds), bm.hot_add(MAX_BITS, 1));
+        assert_eq!(Err(AllocError::InvalidSize), byte.hot_add(30, 0));
+    }
+
+    #[test]
+    fn test_try_offline() {
+        let mut bm = b::LinearBitMap::new(10);
+        let mut byte = B::LinearBitMap::new(10);
+        bm.mark(3, 2);
+        bm.mark(7, 1);
+        byte.mark(3, 2);
+        byte.mark(7, 1);
+        let busy = Err(OfflineError::Busy { first: 3, last: 7, count: 3 });
+        assert_eq!((busy, busy), (bm.try_offline(2, 8), byte.try_offline(2, 8)));
+        let oob = Err(OfflineError::OutOfBounds);
+        assert_eq!((oob, oob), (bm.try_offline(9, 2), byte.try_offline(9, 0)));
+        assert_eq!((Ok(()), Ok(())), (bm.try_offline(0, 3), byte.try_offline(0, 3)));
+        assert_eq!((Ok(()), Ok(())), (bm.try_offline(1, 2), byte.try_offline(1, 2)));
+        assert_eq!((3, 3), (bm.hole_count(), byte.hole_count()));
+        assert_eq!((4, 4), (bm.free_count(), byte.free_count()));
+        assert_eq!(Err(AllocError::Hole), bm.dealloc_checked(0, 1));
+        assert_eq!(Err(AllocError::Hole), byte.dealloc_checked(0, 1));
+    }
+
+    fn inner_test_bad<T: BitAllocator>(bm: &mut T) {
+        extern crate std;
+        use std::vec::Vec;
+        // Bits 2..4 were allocated and 6..9 free when they went bad.
+        assert_eq!(std::vec![(2, 2), (6, 3)], bm.iter_bad_ranges().collect::<Vec<_>>());
+        assert_eq!((5, 5, 2), (bm.bad_count(), bm.hole_count(), bm.used_count()));
+        assert_eq!(5, bm.free_count());
+        bm.dealloc(0, 4);
+        assert!(bm.is_allocated(2) && bm.is_bad(3) && !bm.is_bad(4));
+        assert_eq!(Some(0), bm.alloc(2));
+        assert_eq!(Some(4), bm.alloc(2));
+        assert_eq!(Some(9), bm.alloc(3));
+        assert_eq!(None, bm.alloc(1));
+    }
+
+    #[test]
+    fn test_mark_bad() {
+        let mut bm = b::LinearBitMap::new(12);
+        let mut byte = B::LinearBitMap::new(12);
+        assert_eq!((0, 0), (bm.bad_count(), byte.bad_count()));
+        bm.mark(0, 4);
+        byte.mark(0, 4);
+        bm.mark_bad(2, 2);
+        byte.mark_bad(2, 2);
+        bm.mark_bad(6, 3);
+        byte.mark_bad(6, 3);
+        bm.mark_bad(7, 1);
+        byte.mark_bad(7, 1);
+        assert_eq!(Err(AllocError::Occupied), bm.hot_add(6, 3));
+        assert_eq!(Err(AllocError::Occupied), byte.hot_add(6, 3));
+        inner_test_bad(&mut bm);
+        inner_test_bad(&mut byte);
+        let other = bm.split_off(5);
+        assert_eq!((2, 3), (bm.bad_count(), other.bad_count()));
+        assert_eq!(5, bm.merge(other).bad_count());
+    }
+
+    #[test]
+    fn test_state_bitmap() {
+        use SlotState::*;
+        let mut bm = StateBitMap::new(10);
+        assert_eq!(Ok(()), bm.reserve_range(0, 2));
+        assert_eq!(Ok(()), bm.mark_bad(5, 1));
+        assert_eq!(Some(2), bm.alloc(3));
+        assert_eq!(Some(6), bm.alloc(2));
+        assert_eq!([Reserved, Allocated, Bad], [bm.state(1), bm.state(4), bm.state(5)]);
+        assert_eq!((2, 5, 1, 7), (bm.count(Free), bm.count(Allocated), bm.hole_count(), bm.used_count()));
+        assert_eq!(Err(AllocError::Occupied), bm.reserve_range(1, 2));
+        assert_eq!(Err(AllocError::Hole), bm.transition(4, 2, Free));
+        assert_eq!(Err(AllocError::DoubleFree), bm.transition(7, 2, Free));
+        assert_eq!(Err(AllocError::OutOfBounds), bm.transition(9, 2, Bad));
+        assert_eq!(Allocated, bm.state(4));
+        bm.dealloc(0, 10);
+        assert_eq!((9, Bad), (bm.free_count(), bm.state(5)));
+        assert_eq!(Some(0), bm.alloc(5));
+        assert_eq!(Some(6), bm.alloc(4));
+    }
+
+    #[test]
+    fn test_ref_count() {
+        let mut rc = RefCountAlloc::new(b::LinearBitMap::new(8));
+        assert_eq!(Some(0), rc.alloc(3));
+        assert_eq!(Ok(()), rc.incref(1, 2));
+        assert_eq!((1, 2), (rc.count(0), rc.count(2)));
+        assert_eq!(Err(AllocError::DoubleFree), rc.incref(2, 2));
+        assert_eq!(Ok(1), rc.decref(0, 3));
+        assert_eq!((0, 1, 6), (rc.count(0), rc.count(1), rc.inner().free_count()));
+        assert_eq!(Some(0), rc.alloc(1));
+        assert_eq!(Ok(3), rc.decref(0, 3));
+        assert_eq!(Err(AllocError::DoubleFree), rc.decref(1, 1));
+        assert_eq!(8, rc.inner().free_count());
+
+        let mut small: RefCountAlloc<_, u8> = RefCountAlloc::with_count_type(B::LinearBitMap::new(4));
+        let begin = small.alloc(1).unwrap();
+        for _ in 1..255 { small.incref(begin, 1).unwrap(); }
+        assert_eq!(Err(AllocError::CountOverflow), small.incref(begin, 1));
+        assert_eq!(Err(AllocError::OutOfBounds), small.decref(3, 2));
+    }
+
+    fn inner_test_pin<T: BitAllocator>(bm: &mut T) {
+        // Bits 0..6 are allocated, and 2..4 pinned.
+        assert_eq!(2, bm.pinned_count());
+        assert_eq!(Err(AllocError::Pinned), bm.try_dealloc(0, 4));
+        assert_eq!(Err(AllocError::Pinned), bm.dealloc_checked(3, 1));
+        bm.dealloc(0, 6);
+        assert!(bm.is_pinned(2) && bm.is_allocated(3) && !bm.is_allocated(4));
+        assert_eq!(8, bm.free_count());
+        bm.clear();
+        assert_eq!(Some(4), bm.alloc(3));
+    }
+
+    #[test]
+    fn test_pin() {
+        let mut bm = b::LinearBitMap::new(10);
+        let mut byte = B::LinearBitMap::new(10);
+        bm.mark(0, 6);
+        byte.mark(0, 6);
+        assert_eq!(Err(AllocError::DoubleFree), bm.pin(5, 2));
+        assert_eq!(Err(AllocError::DoubleFree), byte.pin(5, 2));
+        assert_eq!((Ok(()), Ok(())), (bm.pin(2, 2), byte.pin(2, 2)));
+        inner_test_pin(&mut bm);
+        inner_test_pin(&mut byte);
+        let other = bm.split_off(3);
+        assert_eq!((1, 1), (bm.pinned_count(), other.pinned_count()));
+        let mut bm = bm.merge(other);
+        bm.unpin(0, 10);
+        byte.unpin(3, 1);
+        assert_eq!((0, 1), (bm.pinned_count(), byte.pinned_count()));
+        bm.dealloc(0, 10);
+        byte.dealloc(0, 10);
+        assert_eq!((10, 9), (bm.free_count(), byte.free_count()));
+    }
+
+    #[test]
+    fn test_flag_tracker() {
+        extern crate std;
+        use std::vec::Vec;
+        let mut bm = FlagTracker::new(b::LinearBitMap::new(16));
+        assert_eq!(Some(0), bm.alloc(8));
+        bm.set(Flag::Dirty, 1, 3);
+        bm.set(Flag::Dirty, 6, 4);
+        bm.set(Flag::Accessed, 2, 1);
+        assert_eq!(std::vec![(1, 3), (6, 4)], bm.iter_ranges(Flag::Dirty).collect::<Vec<_>>());
+        assert!(bm.take(Flag::Accessed, 2));
+        assert!(!bm.get(Flag::Accessed, 2));
+        bm.unset(Flag::Dirty, 2, 1);
+        assert_eq!(Some((3, 1)), bm.iter_ranges(Flag::Dirty).nth(1));
+        bm.set(Flag::Accessed, 12, 4);
+        bm.dealloc(0, 4);
+        assert_eq!(Some(0), bm.alloc(2));
+        assert_eq!(Some(8), bm.alloc_aligned(8, 8));
+        assert_eq!(std::vec![(3, 1), (6, 2)], bm.iter_ranges(Flag::Dirty).collect::<Vec<_>>());
+        assert_eq!(None, bm.iter_ranges(Flag::Accessed).next());
+        // Bulk operations go straight to the inner allocator, but still
+        // clear the flags of what they allocate.
+        bm.set(Flag::Dirty, 0, 16);
+        bm.dealloc_batch(&[(0, 2), (8, 8)]);
+        assert_eq!(Ok(()), bm.alloc_at(9, 2));
+        assert_eq!((true, false), (bm.get(Flag::Dirty, 8), bm.get(Flag::Dirty, 9)));
+        assert_eq!(Err(ReserveConflict::Allocated(4)), bm.reserve(2, 4));
+        assert!(bm.get(Flag::Dirty, 2));
+        bm.set_all(true);
+        assert_eq!(None, bm.iter_ranges(Flag::Dirty).next());
+        assert_eq!(0, bm.into_inner().free_count());
+    }
+
+    #[test]
+    fn test_seg_tree() {
+        inner_test_alloc(SegTreeBitMap::new(10));
+        inner_test_alloc_aligned(SegTreeBitMap::new(10));
+        inner_test_alloc_order(SegTreeBitMap::new(10));
+        let mut bm = SegTreeBitMap::new(MAX_BITS);
+        assert_eq!(Some(0), bm.alloc(MAX_BITS));
+        bm.dealloc(1000, 4);
+        bm.dealloc(1100, 30);
+        assert_eq!(Some(1104), bm.alloc_aligned(8, 8));
+        assert_eq!((1000, 1004), (bm.find_next(0, false), bm.find_next(1000, true)));
+        assert_eq!(Some(1000), bm.alloc(4));
+        assert_eq!(None, bm.alloc(23));
+        assert_eq!(MAX_BITS, bm.find_next(1200, false));
+    }
+
+    #[test]
+    fn test_seg_bit_equivalent() {
+        const N: usize = 300;
+        let mut bm_bit = b::LinearBitMap::new(N);
+        let mut bm_seg = SegTreeBitMap::new(N);
+
+        let randint = |b: usize, e: usize| { b + (rand::random::<usize>() % (e - b + 1)) };
+        for _ in 0..20000 {
+            match randint(0, 2) {
+                0 => {
+                    let n = randint(1, 40);
+                    assert_eq!(bm_bit.alloc(n), bm_seg.alloc(n));
+                }
+                1 => {
+                    let b = randint(0, N - 1);
+                    let n = randint(1, core::cmp::min(40, N - b));
+                    bm_bit.dealloc(b, n);
+                    bm_seg.dealloc(b, n);
+                }
+                _ => {
+                    let n = randint(1, 40);
+                    let a = randint(1, 40);
+                    assert_eq!(bm_bit.alloc_aligned(n, a), bm_seg.alloc_aligned(n, a));
+                }
+            }
+            assert_eq!(bm_bit.free_count(), bm_seg.free_count());
+        }
+    }
+
+    #[test]
+    fn test_full_words() {
+        let mut bm = b::LinearBitMap::new(200);
+        bm.mark(0, 130);
+        assert_eq!(Some(130), bm.alloc(1));
+        assert_eq!(Some(131), bm.alloc(3));
+        bm.dealloc(64, 1);
+        assert_eq!(Some(64), bm.alloc(1));
+        bm.dealloc_batch(&[(127, 2), (10, 1)]);
+        assert_eq!(Ok(10), bm.try_alloc(1));
+        assert_eq!(Some(127), bm.alloc(2));
+        bm.set_all(false);
+        bm.mark_hole(0, 64);
+        assert_eq!(Some(64), bm.alloc(1));
+        assert_eq!(Some(65), bm.alloc_aligned(3, 65));
+    }
+
+    #[test]
+    fn test_sparse() {
+        let mut pool = [[0; 64]; 7];
+        let mut bm = SparseBitMap::new(1 << 30, &mut pool);
+        assert_eq!((0, None), (bm.free_count(), bm.find_prev(1 << 30, false)));
+        let base = 700_000_000;
+        assert_eq!(Ok(()), bm.add_range(base, 5000));
+        assert_eq!((5, 5000), (bm.chunks_used(), bm.free_count()));
+        assert_eq!((base, base + 5000), (bm.find_next(0, false), bm.find_next(base, true)));
+        assert_eq!(Some(base), bm.alloc(100));
+        assert_eq!(Some(base + 128), bm.alloc_aligned(1000, 64));
+        assert_eq!(Some(base + 4999), bm.alloc_from_top(1));
+        assert_eq!(Some(base + 4998), bm.find_prev(1 << 30, false));
+        assert!(bm.is_allocated(base + 99) && !bm.is_allocated(base + 100));
+        assert!(bm.is_allocated(0) && bm.is_allocated((1 << 30) - 1));
+        bm.dealloc(base, 100);
+        assert_eq!(Some(base), bm.alloc(128));
+        assert_eq!(Err(AllocError::OutOfSpace), bm.add_range(1 << 29, 1));
+        assert_eq!(Err(AllocError::OutOfBounds), bm.add_range((1 << 30) - 1, 2));
+        assert_eq!(5000 - 1128 - 1, bm.free_count());
+    }
+
+    #[test]
+    fn test_run_cache() {
+        let mut bm = RunCache::new(b::LinearBitMap::new(200));
+        bm.mark(0, 200);
+        bm.dealloc(10, 4);
+        bm.dealloc(100, 80);
+        bm.dealloc(190, 10);
+        // 190..200 was freed last, so it is tried first for classes 2 and 8.
+        assert_eq!(Some(190), bm.alloc(8));
+        assert_eq!(Some(198), bm.alloc(2));
+        assert_eq!(Some(100), bm.alloc(64));
+        assert_eq!(Some(164), bm.alloc(9));
+        // What is left of 164..180 was remembered.
+        assert_eq!(Some(173), bm.alloc(3));
+        assert_eq!(Some(10), bm.alloc(1));
+        // The cached 176..180 is gone: fall back to first fit.
+        bm.mark(176, 4);
+        assert_eq!(Some(11), bm.alloc(2));
+        assert_eq!(Some(13), bm.alloc(1));
+        assert_eq!(0, bm.free_count());
+        // Runs freed in a batch are remembered too; first fit would take 20.
+        bm.dealloc_batch(&[(20, 4), (150, 40)]);
+        assert_eq!(Some(150), bm.alloc(2));
+        assert_eq!(Ok(()), bm.alloc_at(20, 4));
+        assert_eq!(38, bm.free_count());
+    }
+
+    #[test]
+    fn test_hybrid() {
+        let mut bm = HybridBitMap::new(b::LinearBitMap::new(100));
+        assert_eq!(&[Extent { begin: 0, len: 100 }], bm.extents());
+        for i in 0..10 { bm.mark(i * 10, 1); }
+        assert_eq!(Some(1), bm.alloc(9));
+        bm.dealloc(30, 1);
+        assert_eq!(Some(Extent { begin: 21, len: 19 }), bm.extents().iter().copied().max_by_key(|e| e.len));
+        assert_eq!(Some(11), bm.alloc(9));
+        assert_eq!(Some(21), bm.alloc(10));
+        assert_eq!(None, bm.alloc(10));
+        bm.rebuild();
+        assert_eq!(7, bm.extents().len());
+        inner_test_alloc(HybridBitMap::new(b::LinearBitMap::new(10)));
+        inner_test_alloc_aligned(HybridBitMap::new(B::LinearBitMap::new(10)));
+    }
+
+    // Counts the scans made through find_next.
+    struct Scans<A> {
+        inner: A,
+        count: core::cell::Cell<usize>,
+    }
+
+    impl<A: BitAllocator> BitAllocator for Scans<A> {
+        fn alloc(&mut self, n: usize) -> Option<usize> { self.inner.alloc(n) }
+
+        fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+            self.inner.alloc_aligned(n, alignment)
+        }
+
+        fn dealloc(&mut self, begin: usize, n: usize) { self.inner.dealloc(begin, n) }
+
+        fn mark(&mut self, begin: usize, n: usize) { self.inner.mark(begin, n) }
+
+        fn size(&self) -> usize { self.inner.size() }
+
+        fn is_allocated(&self, index: usize) -> bool { self.inner.is_allocated(index) }
+
+        fn free_count(&self) -> usize { self.inner.free_count() }
+
+        fn find_next(&self, from: usize, allocated: bool) -> usize {
+            self.count.set(self.count.get() + 1);
+            self.inner.find_next(from, allocated)
+        }
+    }
+
+    #[test]
+    fn test_hybrid_scans() {
+        // 100 one-bit holes and a free tail: more runs than are listed.
+        let mut inner = b::LinearBitMap::new(300);
+        inner.mark(0, 200);
+        for i in 0..100 { inner.dealloc(2 * i, 1); }
+        let mut bm = HybridBitMap::new(Scans { inner, count: core::cell::Cell::new(0) });
+        bm.inner().count.set(0);
+        for i in 0..50 { assert_eq!(Some(2 * i), bm.alloc(1)); }
+        assert_eq!((Some(200), 0), (bm.alloc(2), bm.inner().count.get()));
+    }
+
+    #[test]
+    fn test_hybrid_bit_equivalent() {
+        const N: usize = 300;
+        let mut bm_bit = b::LinearBitMap::new(N);
+        let mut bm_hyb = HybridBitMap::new(b::LinearBitMap::new(N));
+
+        let randint = |b: usize, e: usize| { b + (rand::random::<usize>() % (e - b + 1)) };
+        for _ in 0..20000 {
+            match randint(0, 4) {
+                0 => {
+                    let n = randint(1, 60);
+                    assert_eq!(bm_bit.alloc(n), bm_hyb.alloc(n));
+                }
+                1 => {
+                    let b = randint(0, N - 1);
+                    let n = randint(1, core::cmp::min(20, N - b));
+                    bm_bit.dealloc(b, n);
+                    bm_hyb.dealloc(b, n);
+                }
+                2 => {
+                    let ranges = [(randint(0, N - 10), 10), (randint(0, N - 30), 30)];
+                    bm_bit.dealloc_batch(&ranges);
+                    bm_hyb.dealloc_batch(&ranges);
+                }
+                3 => {
+                    let b = randint(0, N - 1);
+                    let n = randint(1, core::cmp::min(20, N - b));
+                    assert_eq!(bm_bit.alloc_at(b, n), bm_hyb.alloc_at(b, n));
+                }
+                _ => {
+                    let n = randint(1, 20);
+                    let a = randint(1, 20);
+                    assert_eq!(bm_bit.alloc_aligned(n, a), bm_hyb.alloc_aligned(n, a));
+                }
+            }
+            assert_eq!(bm_bit.free_count(), bm_hyb.free_count());
+        }
+    }
+
+    #[test]
+    fn test_tlsf() {
+        inner_test_alloc(TlsfBitMap::new(10));
+        inner_test_dealloc(TlsfBitMap::new(10));
+        let mut bm = TlsfBitMap::new(MAX_BITS);
+        assert_eq!(Some(0), bm.alloc(MAX_BITS));
+        bm.dealloc(100, 20);
+        bm.dealloc(1000, 9);
+        // Every run in a class above that of 9 bits holds them.
+        assert_eq!(Some(100), bm.alloc(9));
+        assert_eq!(Some(109), bm.alloc(9));
+        // The last run of 9 bits shares their class, and is found by a scan.
+        assert_eq!(Some(1000), bm.alloc(9));
+        assert_eq!((118, 120), (bm.find_next(0, false), bm.find_next(118, true)));
+        bm.dealloc(120, 10);
+        assert_eq!(Some(120), bm.alloc_aligned(8, 8));
+        assert_eq!(Some(128), bm.alloc(2));
+        assert_eq!(Some(118), bm.alloc(2));
+        assert_eq!(None, bm.alloc(1));
+        assert_eq!(0, bm.free_count());
+        bm.dealloc(500, 1);
+        assert_eq!(Some(500), bm.find_prev(MAX_BITS, false));
+    }
+
+    #[test]
+    fn test_tlsf_bit_equivalent() {
+        const N: usize = 300;
+        let mut bm_bit = b::LinearBitMap::new(N);
+        let mut bm_tlsf = TlsfBitMap::new(N);
+
+        let randint = |b: usize, e: usize| { b + (rand::random::<usize>() % (e - b + 1)) };
+        for _ in 0..20000 {
+            let (n, a) = (randint(1, 40), randint(1, 40));
+            let (begin, a) = match randint(0, 2) {
+                0 => (bm_tlsf.alloc(n), 1),
+                1 => {
+                    let b = randint(0, N - 1);
+                    let n = randint(1, core::cmp::min(40, N - b));
+                    bm_bit.dealloc(b, n);
+                    bm_tlsf.dealloc(b, n);
+                    assert_eq!(bm_bit.free_count(), bm_tlsf.free_count());
+                    continue;
+                }
+                _ => (bm_tlsf.alloc_aligned(n, a), a),
+            };
+            // Placement differs, but a request fails only when nothing fits.
+            match begin {
+                Some(i) => {
+                    assert_eq!(0, i % a);
+                    assert!((i..i + n).all(|j| !bm_bit.is_allocated(j)));
+                    bm_bit.mark(i, n);
+                }
+                None => assert_eq!(None, bm_bit.clone().alloc_aligned(n, a)),
+            }
+            assert_eq!(bm_bit.free_count(), bm_tlsf.free_count());
+        }
+        assert!((0..N).all(|i| bm_bit.is_allocated(i) == bm_tlsf.is_allocated(i)));
+    }
+
+    #[test]
+    fn test_slab_pool() {
+        extern crate std;
+        use core::mem::MaybeUninit;
+        use std::{rc::Rc, vec::Vec};
+        let live = Rc::new(());
+        let mut storage: [MaybeUninit<(u32, Rc<()>)>; 3] = [MaybeUninit::uninit(), MaybeUninit::uninit(), MaybeUninit::uninit()];
+        let mut pool = SlabPool::new(&mut storage);
+        assert_eq!(Ok(0), pool.alloc((10, live.clone())));
+        assert_eq!(Ok(1), pool.alloc((11, live.clone())));
+        assert_eq!(Ok(2), pool.alloc((12, live.clone())));
+        assert_eq!(Some(13), pool.alloc((13, live.clone())).err().map(|(v, _)| v));
+        assert_eq!((3, 4), (pool.len(), Rc::strong_count(&live)));
+        assert_eq!(Some(11), pool.dealloc(1).map(|(v, _)| v));
+        assert_eq!(None, pool.dealloc(1).map(|(v, _)| v));
+        assert_eq!(None, pool.get(3));
+        pool.get_mut(2).unwrap().0 = 22;
+        assert_eq!(Ok(1), pool.alloc((21, live.clone())));
+        let values: Vec<_> = pool.iter().map(|(i, v)| (i, v.0)).collect();
+        assert_eq!(std::vec![(0, 10), (1, 21), (2, 22)], values);
+        drop(pool);
+        assert_eq!(1, Rc::strong_count(&live));
+        assert_eq!(Err(1), SlabPool::new(&mut []).alloc(1));
+    }
+
+    #[test]
+    fn test_id_alloc() {
+        let mut ids = IdAlloc::new(8);
+        ids.reserve(0);
+        assert_eq!((Some(1), Some(2), Some(3)), (ids.alloc(), ids.alloc(), ids.alloc()));
+        ids.dealloc(2);
+        ids.dealloc(1);
+        assert_eq!((Some(1), Some(2), Some(4)), (ids.alloc(), ids.alloc(), ids.alloc()));
+        ids.reserve(5);
+        assert_eq!((Some(6), Some(7), None), (ids.alloc(), ids.alloc(), ids.alloc()));
+        ids.dealloc(5);
+        assert_eq!((Some(5), None), (ids.alloc(), ids.alloc()));
+        assert_eq!(8, ids.used_count());
+
+        // Ids freed while the stack is full are found by a scan.
+        let mut ids = IdAlloc::new(40);
+        for i in 0..40 { assert_eq!(Some(i), ids.alloc()); }
+        for i in 0..40 { ids.dealloc(i); }
+        for i in (0..16).rev() { assert_eq!(Some(i), ids.alloc()); }
+        for i in 16..40 { assert_eq!(Some(i), ids.alloc()); }
+        assert_eq!(None, ids.alloc());
+        assert_eq!(None, IdAlloc::new(0).alloc());
+    }
+
+    #[test]
+    fn test_zone_manager() {
+        let (mut dma, mut normal, mut high) = (b::LinearBitMap::new(16), B::LinearBitMap::new(64), SegTreeBitMap::new(32));
+        let mut zones = ZoneManager::new();
+        assert_eq!(0, zones.add_zone(0, &mut dma));
+        assert_eq!(1, zones.add_zone(16, &mut normal));
+        assert_eq!(2, zones.add_zone(80, &mut high));
+        assert_eq!((Some(0), Some(1), Some(2), None), (zones.zone_of(15), zones.zone_of(16), zones.zone_of(111), zones.zone_of(112)));
+        assert_eq!(Some((2, 80)), zones.alloc(20, !0));
+        assert_eq!(Some((1, 16)), zones.alloc(20, !0));
+        assert_eq!(Some((0, 0)), zones.alloc(4, 0b001));
+        assert_eq!(None, zones.alloc(13, 0b001));
+        assert_eq!(Some((1, 40)), zones.alloc_aligned(4, 8, 0b011));
+        zones.set_fallback_order(&[0, 1, 2]);
+        assert_eq!(Some((0, 4)), zones.alloc(12, !0));
+        assert_eq!(Some((1, 36)), zones.alloc(4, !0));
+        zones.dealloc(90, 10);
+        assert_eq!(Some((2, 90)), zones.alloc(12, 0b100));
+        assert_eq!(112 - 66, zones.free_count());
+        assert_eq!((80, 10), (zones.zone(2).0, zones.zone(2).1.free_count()));
+        // Alignment is in the common index space, whatever the base.
+        let mut odd = b::LinearBitMap::new(32);
+        let mut zones = ZoneManager::new();
+        zones.add_zone(5, &mut odd);
+        assert_eq!((Some((0, 8)), Some((0, 16))), (zones.alloc_aligned(4, 8, !0), zones.alloc_aligned(1, 8, !0)));
+    }
+
+    #[test]
+    fn test_sharded() {
+        let bm: ShardedBitMap<b::LinearBitMap, 4> = ShardedBitMap::new(10);
+        assert_eq!((Some(0), Some(2), Some(3), None), (bm.shard_of(2), bm.shard_of(8), bm.shard_of(9), bm.shard_of(10)));
+        assert_eq!(Some(3), bm.alloc(1, 2));
+        assert_eq!(Some(6), bm.alloc(6, 3));
+        // Shard 3 holds a single bit, so larger requests go on to shard 0.
+        assert_eq!(Some(0), bm.alloc(3, 2));
+        assert_eq!(Some(9), bm.alloc(3, 1));
+        assert_eq!(Some(2), bm.alloc(3, 1));
+        assert_eq!(Some(5), bm.alloc(1, 1));
+        assert_eq!(None, bm.alloc_aligned(0, 1, 2));
+        bm.dealloc(3, 2);
+        assert_eq!(Some(4), bm.alloc_aligned(2, 1, 2));
+        assert_eq!((1, true, false), (bm.free_count(), bm.is_allocated(4), bm.is_allocated(3)));
+        assert_eq!((3, 2), { let (base, shard) = bm.lock_shard(1); (base, shard.used_count()) });
+
+        extern crate std;
+        let bm: ShardedBitMap<b::LinearBitMap, 4> = ShardedBitMap::new(MAX_BITS);
+        std::thread::scope(|s| {
+            for cpu in 0..4 {
+                let bm = &bm;
+                s.spawn(move || {
+                    for _ in 0..MAX_BITS / 8 {
+                        let i = bm.alloc(cpu, 2).unwrap();
+                        assert!(bm.is_allocated(i) && bm.is_allocated(i + 1));
+                    }
+                });
+            }
+        });
+        assert_eq!(0, bm.free_count());
+    }
+
+    #[test]
+    fn test_atomic() {
+        let bm = AtomicBitMap::new(130);
+        assert_eq!(Some(0), bm.alloc(1));
+        assert_eq!(Some(1), bm.alloc(60));
+        // Runs do not cross words.
+        assert_eq!(Some(64), bm.alloc(4));
+        assert_eq!(Some(61), bm.alloc(1));
+        assert_eq!(Some(72), bm.alloc_aligned(2, 8));
+        assert_eq!(Some(128), bm.alloc_aligned(2, 128));
+        assert_eq!(Some(62), bm.alloc(2));
+        assert_eq!(None, bm.alloc(60));
+        assert_eq!(130 - 72, bm.free_count());
+        bm.dealloc(60, 6);
+        assert_eq!((false, true), (bm.is_allocated(65), bm.is_allocated(66)));
+        assert_eq!(Some(60), bm.alloc(2));
+        bm.mark(62, 4);
+        assert_eq!(130 - 72, bm.free_count());
+        assert!((0..130).all(|i| bm.is_allocated(i) == (i < 68 || (72..74).contains(&i) || i >= 128)));
+
+        extern crate std;
+        static SHARED: AtomicBitMap = AtomicBitMap::new(MAX_BITS);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..MAX_BITS / 16 {
+                        let i = SHARED.alloc(3).unwrap();
+                        SHARED.dealloc(i + 1, 2);
+                        SHARED.mark(i + 1, 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(MAX_BITS / 2, SHARED.free_count());
+        assert_eq!(MAX_BITS / 2, (0..MAX_BITS).filter(|&i| SHARED.is_allocated(i)).count());
+    }
+
+    #[test]
+    #[cfg(feature = "critical-section")]
+    fn test_critical_section() {
+        static SHARED: CriticalSectionBitMap = CriticalSectionBitMap::new(16);
+        assert_eq!(Some(0), SHARED.alloc(3));
+        assert_eq!(Some(4), SHARED.alloc_aligned(2, 4));
+        assert_eq!(Ok(3), SHARED.try_alloc(1));
+        assert_eq!(Err(AllocError::DoubleFree), SHARED.dealloc_checked(8, 1));
+        SHARED.dealloc(0, 2);
+        SHARED.mark(15, 1);
+        assert_eq!(Some(0), SHARED.with(|bm| { let i = bm.alloc(1); bm.dealloc(6, 9); i }));
+        assert_eq!((16, 15 - 5, true), (SHARED.size(), SHARED.free_count(), SHARED.is_allocated(15)));
+    }
+
+    #[test]
+    #[cfg(feature = "locked")]
+    fn test_locked() {
+        extern crate std;
+        static SHARED: LockedBitMap = LockedBitMap::new(MAX_BITS);
+        SHARED.mark(0, 8);
+        assert_eq!(Some(8), SHARED.alloc(2));
+        assert_eq!(Ok(10), SHARED.try_alloc(1));
+        assert_eq!(Err(AllocError::DoubleFree), SHARED.dealloc_checked(11, 1));
+        SHARED.dealloc(8, 3);
+        assert_eq!(Some(16), SHARED.alloc_aligned(4, 16));
+        {
+            let mut bm = SHARED.lock();
+            assert!(SHARED.try_lock().is_none());
+            bm.dealloc(16, 4);
+        }
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| for _ in 0..(MAX_BITS - 8) / 4 { SHARED.alloc(1).unwrap(); });
+            }
+        });
+        assert_eq!((0, true), (SHARED.free_count(), SHARED.is_allocated(MAX_BITS - 1)));
+        assert_eq!(Ok(()), SHARED.defer_dealloc(0, 4));
+        {
+            // The lock is held, as it may be when an interrupt comes.
+            let _bm = SHARED.lock();
+            assert_eq!(Ok(()), SHARED.defer_dealloc(8, 2));
+        }
+        assert_eq!((0, 2, 6), (SHARED.free_count(), SHARED.drain_deferred_frees(), SHARED.free_count()));
+    }
+
+    #[test]
+    #[cfg(feature = "locked")]
+    fn test_locked_custom_lock() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static LOCKS: AtomicUsize = AtomicUsize::new(0);
+        // Counts how often it is taken.
+        struct Counting(RawSpinlock);
+        unsafe impl lock_api::RawMutex for Counting {
+            #[allow(clippy::declare_interior_mutable_const)]
+            const INIT: Self = Counting(RawSpinlock::INIT);
+            type GuardMarker = lock_api::GuardSend;
+            fn lock(&self) { LOCKS.fetch_add(1, Ordering::Relaxed); self.0.lock() }
+            fn try_lock(&self) -> bool { self.0.try_lock() }
+            unsafe fn unlock(&self) { self.0.unlock() }
+        }
+        let bm: LockedBitMap<Counting> = LockedBitMap::new(10);
+        assert_eq!(Some(0), bm.alloc(4));
+        bm.dealloc(0, 2);
+        assert_eq!((8, 3), (bm.free_count(), LOCKS.load(Ordering::Relaxed)));
+    }
+
+    #[test]
+    fn test_free_queue() {
+        let queue: FreeQueue<4> = FreeQueue::new();
+        let mut bm = b::LinearBitMap::new(100);
+        bm.mark(0, 100);
+        for i in 0..4 { assert_eq!(Ok(()), queue.push(i * 10, i + 1)); }
+        assert_eq!(Err(AllocError::OutOfSpace), queue.push(90, 1));
+        assert_eq!(Some((0, 1)), queue.pop());
+        assert_eq!(Ok(()), queue.push(90, 5));
+        assert_eq!(4, queue.drain_deferred_frees(&mut bm));
+        assert_eq!((None, 2 + 3 + 4 + 5), (queue.pop(), bm.free_count()));
+
+        extern crate std;
+        let queue: FreeQueue = FreeQueue::new();
+        let mut bm = b::LinearBitMap::new(MAX_BITS);
+        bm.mark(0, MAX_BITS);
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let queue = &queue;
+                s.spawn(move || {
+                    for i in (t..MAX_BITS).step_by(4) {
+                        while queue.push(i, 1).is_err() {}
+                    }
+                });
+            }
+            let mut drained = 0;
+            while drained < MAX_BITS { drained += queue.drain_deferred_frees(&mut bm); }
+        });
+        assert_eq!(MAX_BITS, bm.free_count());
+    }
+
+    #[test]
+    fn test_epoch() {
+        let mut bm = EpochAllocator::new(b::LinearBitMap::new(10));
+        assert_eq!(Some(0), bm.alloc(6));
+        bm.dealloc_deferred(0, 4);
+        bm.dealloc_deferred(2, 4);
+        assert_eq!((true, 6, 4), (bm.is_pending(5), bm.pending_count(), bm.free_count()));
+        assert_eq!(None, bm.alloc(5));
+        assert_eq!(Some(6), bm.alloc(2));
+        bm.mark(1, 1);
+        bm.dealloc(4, 1);
+        assert_eq!((4, 4, 0), (bm.pending_count(), bm.find_next(0, false), bm.epoch()));
+        assert_eq!(4, bm.advance_epoch());
+        assert_eq!((0, 1), (bm.pending_count(), bm.epoch()));
+        assert!((0..10).all(|i| bm.is_allocated(i) == (i == 1 || i == 6 || i == 7)));
+        assert_eq!(0, bm.advance_epoch());
+        bm.dealloc_deferred(8, 2);
+        assert_eq!(0, bm.pending_count());
+        bm.dealloc_deferred(6, 2);
+        bm.dealloc_batch(&[(7, 1)]);
+        assert_eq!((1, Err(AllocError::Occupied)), (bm.pending_count(), bm.alloc_at(6, 1)));
+        bm.set_all(false);
+        assert_eq!((0, 10), (bm.pending_count(), bm.free_count()));
+    }
+
+    #[test]
+    fn test_watermarks() {
+        extern crate std;
+        let mut bm = WatermarkAllocator::new(b::LinearBitMap::new(20), 6, 2);
+        assert_eq!(Some(0), bm.alloc(10));
+        assert_eq!(None, bm.alloc(5));
+        assert_eq!(Err(AllocError::OutOfSpace), bm.try_alloc(5));
+        assert_eq!(Err(AllocError::InvalidSize), bm.try_alloc(0));
+        assert_eq!(Ok(10), bm.try_alloc(4));
+        assert!(!bm.is_low());
+        assert_eq!(None, bm.alloc_aligned(1, 1));
+        assert_eq!(Some(16), bm.alloc_critical_aligned(2, 8));
+        assert_eq!((true, Some(14)), (bm.is_low(), bm.alloc_critical(2)));
+        assert_eq!((None, 2), (bm.alloc_critical(1), bm.free_count()));
+        bm.set_watermarks(0, 0);
+        assert_eq!((Some(18), (0, 0)), (bm.alloc(2), bm.watermarks()));
+        inner_test_alloc(WatermarkAllocator::new(B::LinearBitMap::new(10), 0, 0));
+        // Invalid requests panic in the wrapped allocator, as without the reserve.
+        assert!(std::panic::catch_unwind(|| WatermarkAllocator::new(b::LinearBitMap::new(20), 6, 2).alloc_aligned(21, 1)).is_err());
+        assert!(std::panic::catch_unwind(|| WatermarkAllocator::new(b::LinearBitMap::new(20), 6, 2).alloc(0)).is_err());
+    }
+
+    #[test]
+    fn test_memory_hook() {
+        extern crate std;
+        use std::{sync::Mutex, vec::Vec};
+        static EVENTS: Mutex<Vec<MemoryEvent>> = Mutex::new(Vec::new());
+        let mut bm = WatermarkAllocator::with_hook(b::LinearBitMap::new(20), 6, 2, |e| EVENTS.lock().unwrap().push(e));
+        assert_eq!(Some(0), bm.alloc(14));
+        assert_eq!(Some(14), bm.alloc_critical(1));
+        assert_eq!(Err(AllocError::OutOfSpace), bm.try_alloc(1));
+        assert_eq!(None, bm.alloc_critical(4));
+        bm.mark(15, 4);
+        bm.dealloc(0, 10);
+        assert_eq!(Ok(()), bm.alloc_at(0, 6));
+        bm.dealloc_batch(&[(0, 6)]);
+        assert_eq!(Ok(()), bm.reserve(0, 10));
+        bm.dealloc(0, 10);
+        bm.set_watermarks(0, 0);
+        assert_eq!(Err(AllocError::Fragmented), bm.try_alloc(11));
+        assert_eq!(std::vec![
+            MemoryEvent::BelowLow { free: 5 },
+            MemoryEvent::AllocFailed { n: 1, critical: false },
+            MemoryEvent::AllocFailed { n: 4, critical: true },
+            MemoryEvent::BelowMin { free: 1 },
+            MemoryEvent::BelowLow { free: 5 },
+            MemoryEvent::BelowLow { free: 1 },
+            MemoryEvent::BelowMin { free: 1 },
+            MemoryEvent::AllocFailed { n: 11, critical: false },
+        ], *EVENTS.lock().unwrap());
+    }
+
+    #[test]
+    fn test_reclaim() {
+        // Gives back one cached range of its list per call.
+        struct Cache { ranges: [(usize, usize); 3], len: usize, calls: usize }
+        impl Shrinker for Cache {
+            fn shrink(&mut self, bm: &mut dyn BitAllocator, _wanted: usize) -> usize {
+                self.calls += 1;
+                if self.len == 0 { return 0; }
+                self.len -= 1;
+                let (begin, n) = self.ranges[self.len];
+                bm.dealloc(begin, n);
+                n
+            }
+        }
+        let mut a = Cache { ranges: [(0, 2), (2, 2), (6, 2)], len: 3, calls: 0 };
+        let mut idle = Cache { ranges: [(0, 0); 3], len: 0, calls: 0 };
+        {
+            let mut bm = ReclaimAllocator::new(b::LinearBitMap::new(10));
+            bm.mark(0, 10);
+            bm.register_shrinker(&mut idle);
+            bm.register_shrinker(&mut a);
+            assert_eq!(Some(6), bm.alloc_with_reclaim(2));
+            assert_eq!(Some(0), bm.alloc_with_reclaim(4));
+            assert_eq!(None, bm.alloc_with_reclaim(1));
+            assert_eq!(0, bm.reclaim(1));
+            bm.set_retries(0);
+            bm.dealloc(9, 1);
+            assert_eq!((Some(9), None), (bm.alloc_with_reclaim(1), bm.alloc_with_reclaim(1)));
+            bm.dealloc_batch(&[(0, 2), (8, 2)]);
+            assert_eq!((Some(9), 4), (bm.find_prev(10, false), bm.free_count()));
+        }
+        assert_eq!((0, 5, 5), (a.len, a.calls, idle.calls));
+    }
+
+    #[test]
+    fn test_quotas() {
+        let mut inner = b::LinearBitMap::new(20);
+        inner.mark(0, 2);
+        let mut bm = QuotaAllocator::new(inner);
+        bm.set_quota(0, 4);
+        bm.set_quota(7, 5);
+        assert_eq!((Some(4), Some(5), None, 2), (bm.quota(0), bm.quota(7), bm.quota(3), bm.usage(0)));
+        assert_eq!(Ok(2), bm.alloc_tagged(4, 7));
+        assert_eq!(Err(AllocError::QuotaExceeded), bm.alloc_tagged(2, 7));
+        assert_eq!(Ok(8), bm.alloc_tagged_aligned(1, 8, 7));
+        assert_eq!(Ok(6), bm.alloc_tagged(2, 3));
+        assert_eq!((Some(9), None), (bm.alloc(2), bm.alloc(1)));
+        assert_eq!(Err(AllocError::QuotaExceeded), bm.try_alloc(1));
+        // Frees are credited to the tags the bits were charged to.
+        bm.dealloc(0, 8);
+        assert_eq!((2, 1, 0), (bm.usage(0), bm.usage(7), bm.usage(3)));
+        bm.set_quota(7, 1);
+        assert_eq!(Err(AllocError::QuotaExceeded), bm.alloc_tagged(1, 7));
+        bm.mark(0, 12);
+        assert_eq!((11, Err(AllocError::InvalidSize)), (bm.usage(0), bm.alloc_tagged(0, 1)));
+        assert_eq!((Ok(12), 8), (bm.alloc_tagged(8, 1), bm.usage(1)));
+        assert_eq!(Err(AllocError::OutOfSpace), bm.alloc_tagged(1, 1));
+        // Overlapping ranges of a batch are credited once.
+        bm.dealloc_batch(&[(8, 4), (10, 6)]);
+        assert_eq!((8, 0, 4), (bm.usage(0), bm.usage(7), bm.usage(1)));
+        assert_eq!(Ok(()), bm.alloc_at(8, 2));
+        assert_eq!(10, bm.usage(0));
+        bm.set_all(false);
+        assert_eq!((0, 0), (bm.usage(0), bm.usage(1)));
+        bm.set_all(true);
+        assert_eq!(20, bm.usage(0));
+    }
+
+    #[cfg(feature = "owners")]
+    #[test]
+    fn test_owners() {
+        extern crate std;
+        let mut bm = OwnerTracker::new(b::LinearBitMap::new(16));
+        assert_eq!(Some(0), bm.alloc_owned(3, 7));
+        assert_eq!(Some(4), bm.alloc_owned_aligned(2, 4, 9));
+        assert_eq!(Some(3), bm.alloc(1));
+        assert_eq!((Some(7), None, Some(9), None), (bm.owner_of(2), bm.owner_of(3), bm.owner_of(5), bm.owner_of(6)));
+        // A freed bit forgets its owner, even once allocated again.
+        bm.dealloc(1, 1);
+        assert_eq!((None, Some(7)), (bm.owner_of(1), bm.owner_of(2)));
+        assert_eq!(Some(1), bm.alloc(1));
+        assert_eq!(None, bm.owner_of(1));
+        bm.mark_owned(4, 4, 5);
+        assert_eq!((Some(9), Some(5)), (bm.owner_of(5), bm.owner_of(7)));
+        bm.mark(8, 1);
+        assert_eq!(None, bm.owner_of(8));
+        let mut outstanding = std::vec::Vec::new();
+        bm.outstanding_report(&mut |begin, len, owner| outstanding.push((begin, len, owner)));
+        let owned = [(0, 1, Some(7)), (1, 1, None), (2, 1, Some(7)), (3, 1, None), (4, 2, Some(9)), (6, 2, Some(5)), (8, 1, None)];
+        assert_eq!(&owned, &outstanding[..]);
+        // Bulk operations leave owners alone but for the bits they allocate.
+        bm.dealloc_batch(&[(0, 1), (6, 2)]);
+        assert_eq!(Ok(()), bm.alloc_at(6, 1));
+        bm.set_all(true);
+        assert_eq!((None, Some(7), Some(9), None, None), (bm.owner_of(0), bm.owner_of(2), bm.owner_of(5), bm.owner_of(7), bm.owner_of(15)));
+    }
+
+    #[test]
+    fn test_outstanding_report() {
+        extern crate std;
+        use std::vec::Vec;
+        let mut bm = b::LinearBitMap::new(16);
+        bm.mark(14, 2);
+        bm.mark_hole(2, 1);
+        assert_eq!(Some(0), bm.alloc(2));
+        assert_eq!(Some(3), bm.alloc(4));
+        bm.dealloc(4, 1);
+        let mut outstanding = Vec::new();
+        bm.outstanding_report(&mut |begin, len, owner| outstanding.push((begin, len, owner)));
+        assert_eq!(&[(0, 2, None), (3, 1, None), (5, 2, None), (14, 2, None)], &outstanding[..]);
+    }
+
+    #[cfg(feature = "audit")]
+    #[test]
+    fn test_audit_log() {
+        extern crate std;
+        use std::string::String;
+        let mut bm: AuditLog<_, 4> = AuditLog::new(b::LinearBitMap::new(8));
+        assert_eq!(Some(0), bm.alloc(3));
+        bm.dealloc(2, 2);
+        bm.mark(1, 2);
+        assert_eq!(None, bm.alloc(6));
+        assert_eq!(Some(4), bm.alloc_aligned(2, 4));
+        let op = Operation { seq: 4, kind: OpKind::Alloc { align: 4, offset: 0 }, begin: 4, n: 2, result: Ok(()) };
+        assert_eq!((5, Some(op)), (bm.op_count(), bm.iter().last()));
+        let mut out = String::new();
+        bm.dump(&mut out).unwrap();
+        assert_eq!("#1 dealloc(2, 2): range is not allocated\n#2 mark(1, 2): range is already allocated\n\
+                    #3 alloc(6): not enough free bits\n#4 alloc(2, 0 mod 4) = 4\n", out);
+        bm.clear_log();
+        assert_eq!((0, 5), (bm.iter().count(), bm.op_count()));
+        bm.dealloc_batch(&[(0, 2), (3, 1)]);
+        assert_eq!(Ok(()), bm.alloc_at(6, 2));
+        bm.set_all(true);
+        out.clear();
+        bm.dump(&mut out).unwrap();
+        assert_eq!("#5 dealloc(0, 2)\n#6 dealloc(3, 1): range is not allocated\n#7 mark(6, 2)\n\
+                    #8 mark(0, 8): range is already allocated\n", out);
+    }
+
+    #[test]
+    fn test_observer() {
+        #[derive(Default)]
+        struct Counter { allocated: usize, freed: usize, failures: usize, last_error: Option<AllocError> }
+        impl AllocObserver for Counter {
+            fn on_alloc(&mut self, _begin: usize, n: usize) { self.allocated += n; }
+            fn on_dealloc(&mut self, _begin: usize, n: usize) { self.freed += n; }
+            fn on_fail(&mut self, _n: usize, error: AllocError) {
+                self.failures += 1;
+                self.last_error = Some(error);
+            }
+        }
+        let mut bm = Observed::new(b::LinearBitMap::new(8), Counter::default());
+        assert_eq!(Some(0), bm.alloc(3));
+        assert_eq!(Some(4), bm.alloc_aligned(2, 4));
+        bm.mark(7, 1);
+        bm.dealloc(0, 2);
+        assert_eq!(None, bm.alloc(3));
+        assert_eq!(Some(AllocError::Fragmented), bm.observer().last_error);
+        assert_eq!(Err(AllocError::InvalidSize), bm.try_alloc(0));
+        bm.dealloc_batch(&[(4, 2)]);
+        assert_eq!(Err(AllocError::Occupied), bm.alloc_at(1, 2));
+        assert_eq!(Ok(()), bm.reserve(4, 2));
+        let (inner, counter) = bm.into_inner();
+        assert_eq!((8, 4, 3, Some(AllocError::Occupied)), (counter.allocated, counter.freed, counter.failures, counter.last_error));
+        assert_eq!(4, inner.free_count());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut bm = b::LinearBitMap::new(20);
+        assert_eq!(AllocStats { size: 20, free: 20, used: 0, free_runs: 1, largest_free_run: 20, mean_free_run: 20, fragmentation: 0 },
+                   bm.stats());
+        bm.mark_hole(0, 2);
+        bm.mark(4, 1);
+        bm.mark(8, 2);
+        let stats = AllocStats { size: 20, free: 15, used: 3, free_runs: 3, largest_free_run: 10, mean_free_run: 5, fragmentation: 34 };
+        assert_eq!(stats, bm.stats());
+        bm.fill();
+        assert_eq!(AllocStats { size: 20, used: 18, ..AllocStats::default() }, bm.stats());
+    }
+
+    #[test]
+    fn test_high_water_mark() {
+        let mut inner = B::LinearBitMap::new(16);
+        inner.mark(0, 2);
+        inner.mark_hole(14, 2);
+        let mut bm = HighWater::new(inner);
+        assert_eq!(2, bm.high_water_mark());
+        assert_eq!(Some(2), bm.alloc(5));
+        bm.dealloc(2, 4);
+        assert_eq!((Some(4), 7), (bm.alloc_aligned(2, 4), bm.high_water_mark()));
+        bm.mark(10, 4);
+        assert_eq!(Some(7), bm.alloc(3));
+        assert_eq!(12, bm.high_water_mark());
+        bm.dealloc(0, 14);
+        assert_eq!(12, bm.high_water_mark());
+        bm.reset_high_water_mark();
+        assert_eq!((0, Some(0)), (bm.high_water_mark(), bm.alloc(1)));
+        assert_eq!(1, bm.high_water_mark());
+        assert_eq!(Ok(()), bm.alloc_at(4, 6));
+        bm.dealloc_batch(&[(4, 6)]);
+        assert_eq!(7, bm.high_water_mark());
+        bm.set_all(true);
+        assert_eq!(14, bm.high_water_mark());
+    }
+
+    #[cfg(feature = "histogram")]
+    #[test]
+    fn test_size_histogram() {
+        let mut bm = SizeHistogram::new(b::LinearBitMap::new(MAX_BITS));
+        assert_eq!(11, bm.histogram().len());
+        for &n in &[1, 1, 2, 3, 4, 7, 8, 1024, MAX_BITS] { bm.alloc(n); }
+        assert_eq!(None, bm.alloc_aligned(MAX_BITS, 2));
+        assert_eq!(&[2, 2, 2, 1, 0, 0, 0, 0, 0, 0, 3], bm.histogram());
+        bm.reset_histogram();
+        assert_eq!(Some(1056), bm.alloc_aligned_offset(5, 16, 0));
+        assert_eq!(&[0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0], bm.histogram());
+        // Only requests to find room are counted.
+        bm.set_all(false);
+        assert_eq!((Ok(()), MAX_BITS - 4), (bm.alloc_at(0, 4), bm.free_count()));
+        assert_eq!(&[0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0], bm.histogram());
+    }
+
+    #[test]
+    fn test_fragmentation() {
+        let mut bm = B::LinearBitMap::new(20);
+        bm.mark(3, 1);
+        bm.mark(9, 2);
+        assert_eq!(FragInfo { satisfiable: 17, unusable: 0, unusable_percent: 0 }, bm.fragmentation(1));
+        // Free runs of 3, 5 and 9 bits.
+        assert_eq!(FragInfo { satisfiable: 3, unusable: 5, unusable_percent: 29 }, bm.fragmentation(4));
+        assert_eq!(FragInfo { satisfiable: 1, unusable: 8, unusable_percent: 47 }, bm.fragmentation(9));
+        assert_eq!(FragInfo { satisfiable: 0, unusable: 17, unusable_percent: 100 }, bm.fragmentation(10));
+        bm.fill();
+        assert_eq!(FragInfo::default(), bm.fragmentation(4));
+    }
+
+    #[test]
+    fn test_plan_compaction() {
+        extern crate std;
+        use std::vec::Vec;
+        let moves = |bm: &b::LinearBitMap, goal| bm.plan_compaction(goal).map(|m| (m.from, m.to, m.len)).collect::<Vec<_>>();
+        let mut bm = b::LinearBitMap::new(20);
+        bm.mark(2, 2);
+        bm.mark(7, 1);
+        bm.mark(12, 3);
+        assert_eq!((Some(Extent { begin: 8, len: 4 }), 0), (bm.plan_compaction(4).target(), moves(&bm, 4).len()));
+        assert_eq!(&[(7, 0, 1)], &moves(&bm, 7)[..]);
+        assert_eq!(&[(2, 15, 2), (7, 17, 1)], &moves(&bm, 12)[..]);
+        bm.pin(2, 1).unwrap();
+        assert_eq!(Some(Extent { begin: 8, len: 12 }), bm.plan_compaction(12).target());
+        // The run at 12 is split over the free runs at 0 and 4.
+        assert_eq!(&[(12, 0, 2), (14, 4, 1)], &moves(&bm, 12)[..]);
+        assert_eq!(Some(Extent { begin: 4, len: 14 }), bm.plan_compaction(14).target());
+        assert_eq!(&[(7, 0, 1), (12, 1, 1), (13, 18, 2)], &moves(&bm, 14)[..]);
+        assert_eq!((None, 0), (bm.plan_compaction(15).target(), moves(&bm, 15).len()));
+    }
+
+    #[test]
+    fn test_compact() {
+        extern crate std;
+        use std::vec::Vec;
+        let mut bm = B::LinearBitMap::new(16);
+        bm.mark(1, 2);
+        bm.mark(6, 1);
+        bm.mark(10, 3);
+        let mut copies = Vec::new();
+        assert_eq!(3, bm.compact(|from, to, len| { copies.push((from, to, len)); true }));
+        assert_eq!(&[(1, 13, 2), (6, 15, 1)], &copies[..]);
+        assert_eq!(10, bm.stats().largest_free_run);
+        assert_eq!(Some(0), bm.alloc(10));
+        assert_eq!(0, bm.compact(|_, _, _| unreachable!()));
+        // A pinned bit stays where it is, and a refused copy stops it all.
+        let mut bm = b::LinearBitMap::new(16);
+        bm.mark(0, 1);
+        bm.mark(4, 2);
+        bm.mark(9, 1);
+        bm.mark(12, 1);
+        bm.pin(12, 1).unwrap();
+        let mut calls = 0;
+        assert_eq!(2, bm.compact(|from, _, _| { calls += 1; from == 4 }));
+        let allocated = (0..16).filter(|&i| bm.is_allocated(i)).fold(0, |m, i| m | 1 << i);
+        assert_eq!((2, 0b0111_0010_0000_0001), (calls, allocated));
+        assert_eq!(1, bm.compact(|_, _, _| true));
+        assert_eq!(Some(Extent { begin: 1, len: 11 }), bm.plan_compaction(11).target());
+        assert_eq!(0, bm.plan_compaction(11).count());
+    }
+
+    #[test]
+    fn test_largest_free_runs() {
+        let mut bm = b::LinearBitMap::new(30);
+        for &i in &[2, 5, 9, 12, 20] { bm.mark(i, 1); }
+        let mut out = [(0, 0); 3];
+        assert_eq!(3, bm.largest_free_runs(&mut out));
+        assert_eq!([(21, 9), (13, 7), (6, 3)], out);
+        let mut out = [(0, 0); 8];
+        assert_eq!(6, bm.largest_free_runs(&mut out));
+        assert_eq!(&[(21, 9), (13, 7), (6, 3), (0, 2), (3, 2), (10, 2)], &out[..6]);
+        assert_eq!(0, bm.largest_free_runs(&mut []));
+        bm.fill();
+        assert_eq!(0, bm.largest_free_runs(&mut out));
+    }
+
+    #[test]
+    fn test_can_alloc() {
+        let mut bm = B::LinearBitMap::new(16);
+        bm.mark(0, 1);
+        bm.mark(7, 2);
+        assert!(bm.can_alloc(7, 1) && bm.can_alloc(6, 3) && bm.can_alloc(4, 4));
+        assert!(!bm.can_alloc(8, 1) && !bm.can_alloc(7, 2) && !bm.can_alloc(6, 4));
+        assert!(!bm.can_alloc(0, 1) && !bm.can_alloc(1, 0) && !bm.can_alloc(17, 1));
+        assert!(bm.can_alloc(4, 5) && !bm.can_alloc(7, 5));
+        assert_eq!(13, bm.free_count());
+        assert_eq!(Some(10), bm.alloc_aligned(4, 5));
+    }
+
+    #[test]
+    fn test_find_next_across_words() {
+        let mut bm_bit = b::LinearBitMap::new(200);
+        let mut bm_byte = B::LinearBitMap::new(200);
+        for &(begin, n) in &[(0, 1), (63, 2), (100, 92), (199, 1)] {
+            bm_bit.mark(begin, n);
+            bm_byte.mark(begin, n);
+        }
+        for from in 0..=200 {
+            for &allocated in &[false, true] {
+                assert_eq!(bm_byte.find_next(from, allocated), bm_bit.find_next(from, allocated), "{} {}", from, allocated);
+            }
+        }
+        assert_eq!((65, 100, 192), (bm_bit.find_next(63, false), bm_bit.find_next(65, true), bm_bit.find_next(128, false)));
+    }
+
+    #[test]
+    fn test_full_last_word() {
+        let mut bm = b::LinearBitMap::new(100);
+        bm.mark(0, 99);
+        assert_eq!((Some(99), None), (bm.alloc(1), bm.alloc(1)));
+        assert_eq!(100, bm.find_next(0, false));
+        // The last word is no longer full once the bitmap grows.
+        bm.resize(130).unwrap();
+        assert_eq!((100, Some(100)), (bm.find_next(64, false), bm.alloc_aligned(2, 4)));
+        bm.dealloc(64, 1);
+        assert_eq!(Some(64), bm.alloc(1));
+    }
+
+    #[test]
+    fn test_long_ranges() {
+        let mut bm = b::LinearBitMap::new(MAX_BITS);
+        bm.mark(60, 10);
+        bm.mark(0, MAX_BITS);
+        assert_eq!(0, bm.free_count());
+        bm.dealloc(5, 1500);
+        assert_eq!((1500, 5, 1505), (bm.free_count(), bm.find_next(0, false), bm.find_next(5, true)));
+        bm.mark_hole(3, 200);
+        assert_eq!((1302, 200, 203), (bm.free_count(), bm.hole_count(), bm.find_next(0, false)));
+    }
+
+    #[test]
+    fn test_alloc_1_mostly_full() {
+        let mut bm = b::LinearBitMap::new(MAX_BITS);
+        bm.fill();
+        assert_eq!(None, bm.alloc(1));
+        bm.dealloc(1000, 1);
+        bm.dealloc(1535, 1);
+        assert_eq!((Some(1000), Some(1535), None), (bm.alloc(1), bm.alloc(1), bm.alloc(1)));
+    }
+
+    #[test]
+    fn test_first_free_hint() {
+        inner_test_first_free_hint(b::LinearBitMap::new(300));
+    }
+
+    fn inner_test_first_free_hint<T: BitAllocator>(mut bm: T) {
+        assert_eq!(Some(0), bm.alloc(200));
+        assert_eq!((Some(200), Some(210)), (bm.alloc(10), bm.alloc_aligned(5, 7)));
+        // Frees below the hint are found again.
+        bm.dealloc(70, 3);
+        assert_eq!((70, Some(70)), (bm.find_next(0, false), bm.alloc(2)));
+        assert_eq!((Some(72), Some(215)), (bm.alloc(1), bm.alloc(5)));
+        bm.mark(220, 80);
+        assert_eq!((None, 300), (bm.alloc(1), bm.find_next(0, false)));
+        bm.clear();
+        assert_eq!(Some(0), bm.alloc(1));
+        bm.set_all(true);
+        bm.dealloc_batch(&[(250, 1), (9, 1)]);
+        assert_eq!((Some(9), Some(250)), (bm.alloc(1), bm.alloc(1)));
+    }
+
+    #[test]
+    fn test_free_count_in() {
+        let mut bm = b::LinearBitMap::new(300);
+        bm.mark(10, 100);
+        bm.mark(130, 2);
+        assert_eq!((10, 0), (bm.free_count_in(0, 64), bm.free_count_in(64, 46)));
+        assert_eq!((80, 188), (bm.free_count_in(64, 128), bm.free_count_in(100, 200)));
+        assert_eq!(bm.free_count(), bm.free_count_in(0, 300));
+        // Skipping the words with too few free bits leaves placement as it was.
+        bm.mark(63, 1);
+        bm.dealloc(40, 4);
+        assert_eq!((Some(110), Some(0)), (bm.alloc(11), bm.alloc(4)));
+        bm.dealloc(60, 3);
+        assert_eq!((Some(4), Some(40), Some(121)), (bm.alloc(5), bm.alloc_aligned(4, 4), bm.alloc(4)));
+        bm.resize(290).unwrap();
+        assert_eq!(bm.free_count(), bm.free_count_in(0, 290));
+    }
+
+    #[test]
+    fn test_slice_skips_words() {
+        let mut bytes = [0u8; 80];
+        let mut bm = SliceBitMap::new(&mut bytes, 620, Layout::new(4, Endian::Big, BitOrder::MsbFirst));
+        bm.mark(0, 500);
+        bm.mark(530, 90);
+        assert_eq!((500, 530, 33), (bm.find_next(0, false), bm.find_next(500, true), bm.find_next(33, true)));
+        assert_eq!((Some(500), None), (bm.alloc(30), bm.alloc(1)));
+        bm.dealloc(610, 10);
+        assert_eq!((610, 620), (bm.find_next(64, false), bm.find_next(610, true)));
+        let mut words = [!0u64; 40];
+        let mut bm = SliceBitMap::from_words(&mut words, 40 * 64);
+        bm.dealloc(2000, 1);
+        assert_eq!((2000, Some(2000), 2560), (bm.find_next(1, false), bm.alloc(1), bm.find_next(0, false)));
+    }
+
+    #[test]
+    fn test_slice_summary() {
+        let layout = Layout::new(1, Endian::Little, BitOrder::LsbFirst);
+        // Groups of 512 bits, the last of them 476 bits without the padding.
+        let mut bytes = [0u8; 188];
+        bytes[187] = 0xf0;
+        let mut groups = [!0u64];
+        assert_eq!(1, SliceBitMap::summary_len(1500, layout));
+        {
+            let mut bm = SliceBitMap::with_summary(&mut bytes, 1500, layout, &mut groups);
+            bm.mark(0, 1100);
+            assert_eq!((1100, 600), (bm.find_next(3, false), bm.find_next(600, true)));
+            assert_eq!(Some(1100), bm.alloc(400));
+            assert_eq!((1500, None), (bm.find_next(0, false), bm.alloc(1)));
+            bm.dealloc(700, 1);
+            assert_eq!((700, Some(700)), (bm.find_next(0, false), bm.alloc(1)));
+            bm.dealloc(1499, 1);
+            assert_eq!((1499, 1), (bm.find_next(0, false), bm.free_count()));
+        }
+        assert_eq!(([0b100], 0xf7), (groups, bytes[187]));
+    }
+
     #[test]
+    #[allow(unused_variables, clippy::unit_cmp)]
     fn test_byte_bit_equivalent() {
         const N: usize = 1000;
         let mut bm_bit = crate::bitalloc::LinearBitMap::new(N);
@@ -102,10 +3333,11 @@ mod tests {
                 }
                 _ => { // alloc_aligned
                     let n: usize = randint(1, N);
-                    let a: usize = 1 << randint(1, 5);
+                    let a: usize = randint(1, 40);
                     assert_eq!(bm_bit.alloc_aligned(n, a), bm_byte.alloc_aligned(n, a));
                 }
             }
+            assert_eq!(bm_bit.free_count(), bm_byte.free_count());
         }
     }
 }
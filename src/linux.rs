@@ -0,0 +1,66 @@
+//! Equivalents of the Linux kernel bitmap primitives, for porting C code.
+//!
+//! As in Linux, a bitmap is an array of unsigned longs, here `usize`, in
+//! which bit i is bit `i % BITS_PER_LONG` of word `i / BITS_PER_LONG`. The
+//! find functions return size when there is no such bit.
+
+use core::cmp::min;
+
+/// Number of bits in a word of a bitmap.
+pub const BITS_PER_LONG: usize = usize::BITS as usize;
+
+/// Index of the first set bit among the first size bits of addr.
+pub fn find_first_bit(addr: &[usize], size: usize) -> usize {
+    find_next_bit(addr, size, 0)
+}
+
+/// Index of the first clear bit among the first size bits of addr.
+pub fn find_first_zero_bit(addr: &[usize], size: usize) -> usize {
+    find_next_zero_bit(addr, size, 0)
+}
+
+/// Index of the first set bit at or after offset, below size.
+pub fn find_next_bit(addr: &[usize], size: usize, offset: usize) -> usize {
+    find_next(addr, size, offset, 0)
+}
+
+/// Index of the first clear bit at or after offset, below size.
+pub fn find_next_zero_bit(addr: &[usize], size: usize, offset: usize) -> usize {
+    find_next(addr, size, offset, !0)
+}
+
+/// Set the nbits bits starting at start.
+pub fn bitmap_set(map: &mut [usize], start: usize, nbits: usize) {
+    for_each_word(start, start + nbits, |w, mask| map[w] |= mask);
+}
+
+/// Clear the nbits bits starting at start.
+pub fn bitmap_clear(map: &mut [usize], start: usize, nbits: usize) {
+    for_each_word(start, start + nbits, |w, mask| map[w] &= !mask);
+}
+
+// The words are xor-ed with invert, so that clear bits can be found as set ones.
+fn find_next(addr: &[usize], size: usize, offset: usize, invert: usize) -> usize {
+    if offset >= size { return size; }
+    let mut w = offset / BITS_PER_LONG;
+    let mut word = (addr[w] ^ invert) & (!0 << (offset % BITS_PER_LONG));
+    loop {
+        if word != 0 {
+            return min(w * BITS_PER_LONG + word.trailing_zeros() as usize, size);
+        }
+        w += 1;
+        if w * BITS_PER_LONG >= size { return size; }
+        word = addr[w] ^ invert;
+    }
+}
+
+// Calls f(word, mask) for each word overlapping the bits [begin, end).
+fn for_each_word<F: FnMut(usize, usize)>(begin: usize, end: usize, mut f: F) {
+    let mut i = begin;
+    while i < end {
+        let (w, lo) = (i / BITS_PER_LONG, i % BITS_PER_LONG);
+        let hi = min(end - w * BITS_PER_LONG, BITS_PER_LONG);
+        f(w, (!0usize >> (BITS_PER_LONG - (hi - lo))) << lo);
+        i = (w + 1) * BITS_PER_LONG;
+    }
+}
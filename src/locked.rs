@@ -0,0 +1,112 @@
+//! A bitmap allocator behind a lock, enabled by the `locked` feature.
+
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lock_api::{GuardSend, Mutex, MutexGuard, RawMutex};
+use crate::{AllocError, BitAllocator, FreeQueue};
+use crate::bitalloc::LinearBitMap;
+
+/// A `bitalloc::LinearBitMap` behind a lock, shared through `&self`, so it
+/// can live in a `static` and be used from anywhere in a kernel.
+///
+/// The lock is any `lock_api::RawMutex`, such as the kernel's own mutex, a
+/// ticket lock or one that masks interrupts; by default a plain
+/// [`RawSpinlock`]. Every method takes the lock for the duration of one
+/// operation. Use lock to run several operations without another core
+/// getting in between.
+///
+/// Contexts that must not wait for the lock, such as interrupt handlers,
+/// can queue frees with defer_dealloc instead; they take effect at the next
+/// drain_deferred_frees.
+pub struct LockedBitMap<R: RawMutex = RawSpinlock> {
+    inner: Mutex<R, LinearBitMap>,
+    deferred: FreeQueue,
+}
+
+impl<R: RawMutex> LockedBitMap<R> {
+    /// Create a bitmap with size bits. By default, all bits are unallocated.
+    pub const fn new(size: usize) -> Self {
+        LockedBitMap { inner: Mutex::const_new(R::INIT, LinearBitMap::new(size)), deferred: FreeQueue::new() }
+    }
+
+    /// Lock the bitmap, waiting until no one else holds it.
+    pub fn lock(&self) -> MutexGuard<'_, R, LinearBitMap> { self.inner.lock() }
+
+    /// Lock the bitmap if no one else holds it.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, R, LinearBitMap>> { self.inner.try_lock() }
+
+    /// Unwrap the bitmap, with the deferred frees applied.
+    pub fn into_inner(self) -> LinearBitMap {
+        let mut bm = self.inner.into_inner();
+        self.deferred.drain_deferred_frees(&mut bm);
+        bm
+    }
+
+    /// Like `BitAllocator::alloc`.
+    pub fn alloc(&self, n: usize) -> Option<usize> { self.lock().alloc(n) }
+
+    /// Like `BitAllocator::alloc_aligned`.
+    pub fn alloc_aligned(&self, n: usize, alignment: usize) -> Option<usize> {
+        self.lock().alloc_aligned(n, alignment)
+    }
+
+    /// Like `BitAllocator::try_alloc`.
+    pub fn try_alloc(&self, n: usize) -> Result<usize, AllocError> { self.lock().try_alloc(n) }
+
+    /// Like `BitAllocator::dealloc`.
+    pub fn dealloc(&self, begin: usize, n: usize) { self.lock().dealloc(begin, n) }
+
+    /// Queue the free of the n bits at begin without taking the lock.
+    ///
+    /// Fails with `AllocError::OutOfSpace` if the queue is full.
+    pub fn defer_dealloc(&self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.deferred.push(begin, n)
+    }
+
+    /// Apply the frees queued by defer_dealloc, and return how many there
+    /// were.
+    pub fn drain_deferred_frees(&self) -> usize {
+        self.deferred.drain_deferred_frees(&mut *self.lock())
+    }
+
+    /// Like `BitAllocator::dealloc_checked`.
+    pub fn dealloc_checked(&self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.lock().dealloc_checked(begin, n)
+    }
+
+    /// Like `BitAllocator::mark`.
+    pub fn mark(&self, begin: usize, n: usize) { self.lock().mark(begin, n) }
+
+    /// Like `BitAllocator::size`.
+    pub fn size(&self) -> usize { self.lock().size() }
+
+    /// Like `BitAllocator::is_allocated`.
+    pub fn is_allocated(&self, index: usize) -> bool { self.lock().is_allocated(index) }
+
+    /// Like `BitAllocator::free_count`, as of when the lock was held.
+    pub fn free_count(&self) -> usize { self.lock().free_count() }
+}
+
+/// A test-and-set spinlock, the default lock of a [`LockedBitMap`].
+pub struct RawSpinlock(AtomicBool);
+
+unsafe impl RawMutex for RawSpinlock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawSpinlock(AtomicBool::new(false));
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        while !self.try_lock() {
+            while self.0.load(Ordering::Relaxed) { spin_loop(); }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.0.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,118 @@
+//! Callbacks around every operation on an allocator.
+
+use crate::{AllocError, BitAllocator, ReserveConflict, check_len, exhaustion};
+
+/// Called by an [`Observed`] allocator around the operations on it, e.g. to
+/// feed tracing, count operations or check invariants, without touching the
+/// allocator itself. Every callback does nothing by default.
+pub trait AllocObserver {
+    /// The n bits at begin were allocated, or marked.
+    fn on_alloc(&mut self, _begin: usize, _n: usize) {}
+    /// The n bits at begin were freed.
+    fn on_dealloc(&mut self, _begin: usize, _n: usize) {}
+    /// A request for n bits failed, for the reason given.
+    fn on_fail(&mut self, _n: usize, _error: AllocError) {}
+}
+
+/// Wraps an allocator with an [`AllocObserver`] that is told about every
+/// allocation, free and failed request made through the wrapper.
+pub struct Observed<A, O> {
+    inner: A,
+    observer: O,
+}
+
+impl<A: BitAllocator, O: AllocObserver> Observed<A, O> {
+    /// Wrap inner, reporting to observer.
+    pub fn new(inner: A, observer: O) -> Self {
+        Observed { inner, observer }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// The observer.
+    pub fn observer(&self) -> &O { &self.observer }
+
+    /// The observer, e.g. to reset its counters.
+    pub fn observer_mut(&mut self) -> &mut O { &mut self.observer }
+
+    /// Unwrap the allocator and the observer.
+    pub fn into_inner(self) -> (A, O) { (self.inner, self.observer) }
+
+    // Run f on inner to allocate n bits, and report the outcome.
+    fn observed<F: FnOnce(&mut A) -> Option<usize>>(&mut self, n: usize, f: F) -> Option<usize> {
+        let begin = f(&mut self.inner);
+        match begin {
+            Some(begin) => self.observer.on_alloc(begin, n),
+            None => self.observer.on_fail(n, exhaustion(&self.inner, n)),
+        }
+        begin
+    }
+}
+
+impl<A: BitAllocator, O: AllocObserver> BitAllocator for Observed<A, O> {
+    fn alloc(&mut self, n: usize) -> Option<usize> { self.observed(n, |a| a.alloc(n)) }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        self.observed(n, |a| a.alloc_aligned(n, alignment))
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        self.observed(n, |a| a.alloc_aligned_offset(n, align, offset))
+    }
+
+    /// Like alloc, but invalid requests are reported to the observer too.
+    fn try_alloc(&mut self, n: usize) -> Result<usize, AllocError> {
+        if let Err(e) = check_len(self.size(), n) {
+            self.observer.on_fail(n, e);
+            return Err(e);
+        }
+        self.alloc(n).ok_or_else(|| exhaustion(&self.inner, n))
+    }
+
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.inner.dealloc(begin, n);
+        self.observer.on_dealloc(begin, n);
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.inner.mark(begin, n);
+        self.observer.on_alloc(begin, n);
+    }
+
+    fn dealloc_batch(&mut self, ranges: &[(usize, usize)]) {
+        self.inner.dealloc_batch(ranges);
+        for &(begin, n) in ranges { self.observer.on_dealloc(begin, n); }
+    }
+
+    /// Reported as an allocation or a free of the whole bitmap.
+    fn set_all(&mut self, allocated: bool) {
+        self.inner.set_all(allocated);
+        let size = self.inner.size();
+        if size == 0 { return; }
+        if allocated { self.observer.on_alloc(0, size) } else { self.observer.on_dealloc(0, size) }
+    }
+
+    /// Like alloc, a failure is reported to the observer too.
+    fn alloc_at(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        let result = self.inner.alloc_at(begin, n);
+        match result {
+            Ok(()) => self.observer.on_alloc(begin, n),
+            Err(e) => self.observer.on_fail(n, e),
+        }
+        result
+    }
+
+    /// Like alloc_at, a conflict being reported as `AllocError::Occupied`.
+    fn reserve(&mut self, begin: usize, n: usize) -> Result<(), ReserveConflict> {
+        let result = self.inner.reserve(begin, n);
+        match result {
+            Ok(()) => self.observer.on_alloc(begin, n),
+            Err(ReserveConflict::OutOfBounds) => self.observer.on_fail(n, AllocError::OutOfBounds),
+            Err(ReserveConflict::Allocated(_)) => self.observer.on_fail(n, AllocError::Occupied),
+        }
+        result
+    }
+
+    forward_bit_allocator!();
+}
@@ -0,0 +1,122 @@
+//! Owner ids recorded for allocated ranges, enabled by the `owners` feature.
+
+use crate::{AllocError, BitAllocator, MAX_BITS, ReserveConflict, Tag, outstanding};
+
+/// Wraps an allocator with a side table recording who allocated every bit,
+/// so that "who allocated frame X?" can be answered while debugging.
+///
+/// Bits allocated through alloc_owned or alloc_owned_aligned, or marked
+/// through mark_owned, remember the owner id given; those allocated through
+/// the [`BitAllocator`] methods have no owner. The owner of a bit is
+/// forgotten as soon as it is freed.
+pub struct OwnerTracker<A> {
+    inner: A,
+    owners: [Option<Tag>; MAX_BITS],
+}
+
+impl<A: BitAllocator> OwnerTracker<A> {
+    /// Wrap inner. Bits already allocated have no owner.
+    ///
+    /// Panics if the size of inner exceeds the capacity.
+    pub fn new(inner: A) -> Self {
+        assert!(inner.size() <= MAX_BITS);
+        OwnerTracker { inner, owners: [None; MAX_BITS] }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator, forgetting all owners.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// Who allocated the index-th bit, if it is allocated and has an owner.
+    ///
+    /// Panics if index is not smaller than the size.
+    pub fn owner_of(&self, index: usize) -> Option<Tag> {
+        assert!(index < self.inner.size());
+        if self.inner.is_allocated(index) { self.owners[index] } else { None }
+    }
+
+    /// Like alloc, but the bits are recorded as allocated by owner.
+    pub fn alloc_owned(&mut self, n: usize, owner: Tag) -> Option<usize> {
+        self.alloc_owned_aligned(n, 1, owner)
+    }
+
+    /// Like alloc_aligned, but the bits are recorded as allocated by owner.
+    pub fn alloc_owned_aligned(&mut self, n: usize, alignment: usize, owner: Tag) -> Option<usize> {
+        let begin = self.inner.alloc_aligned(n, alignment)?;
+        self.set_owner(begin, begin + n, Some(owner));
+        Some(begin)
+    }
+
+    /// Like mark, but the bits that were free are recorded as allocated by
+    /// owner.
+    pub fn mark_owned(&mut self, begin: usize, n: usize, owner: Tag) {
+        self.mark_as(begin, n, Some(owner));
+    }
+
+    fn mark_as(&mut self, begin: usize, n: usize, owner: Option<Tag>) {
+        assert!(begin + n <= self.inner.size());
+        for i in begin..begin + n {
+            if !self.inner.is_allocated(i) { self.owners[i] = owner; }
+        }
+        self.inner.mark(begin, n);
+    }
+
+    fn set_owner(&mut self, begin: usize, end: usize, owner: Option<Tag>) {
+        for o in &mut self.owners[begin..end] { *o = owner; }
+    }
+}
+
+impl<A: BitAllocator> BitAllocator for OwnerTracker<A> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        let begin = self.inner.alloc(n)?;
+        self.set_owner(begin, begin + n, None);
+        Some(begin)
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        let begin = self.inner.alloc_aligned(n, alignment)?;
+        self.set_owner(begin, begin + n, None);
+        Some(begin)
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        let begin = self.inner.alloc_aligned_offset(n, align, offset)?;
+        self.set_owner(begin, begin + n, None);
+        Some(begin)
+    }
+
+    /// Mark the bits, without an owner for those that were free.
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.mark_as(begin, n, None);
+    }
+
+    /// Set every bit, without an owner for those allocated.
+    fn set_all(&mut self, allocated: bool) {
+        if allocated {
+            for i in 0..self.inner.size() {
+                if !self.inner.is_allocated(i) { self.owners[i] = None; }
+            }
+        }
+        self.inner.set_all(allocated);
+    }
+
+    fn alloc_at(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.inner.alloc_at(begin, n)?;
+        self.set_owner(begin, begin + n, None);
+        Ok(())
+    }
+
+    fn reserve(&mut self, begin: usize, n: usize) -> Result<(), ReserveConflict> {
+        self.inner.reserve(begin, n)?;
+        self.set_owner(begin, begin + n, None);
+        Ok(())
+    }
+
+    forward_bit_allocator!(dealloc, dealloc_batch);
+
+    fn outstanding_report(&self, report: &mut dyn FnMut(usize, usize, Option<Tag>)) {
+        outstanding(self, |i| self.owners[i], report)
+    }
+}
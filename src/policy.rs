@@ -0,0 +1,225 @@
+//! Placement policies: which free run an allocation is carved out of.
+
+use core::cmp::min;
+use crate::{BitAllocator, BitIndex, Runs};
+
+/// How an allocator chooses among the free runs that can hold a request.
+///
+/// `bitalloc::LinearBitMap` and `bytealloc::LinearBitMap` are generic over
+/// their policy and default to [`FirstFit`].
+pub trait FitPolicy {
+    /// Whether place always returns the lowest index at which the request
+    /// fits. Allocators may use their own first-fit scan for such policies
+    /// instead of calling place and allocated.
+    const FIRST_FIT: bool = false;
+
+    /// Index i at which n free bits should be allocated in bm, such that
+    /// `i % align == offset`, or `None` if there is no room. Must not change
+    /// anything.
+    fn place<T: BitAllocator + ?Sized>(&self, bm: &T, n: usize, align: usize,
+                                       offset: usize) -> Option<usize>;
+    /// Called once the n bits at begin returned by place have been allocated.
+    fn allocated(&mut self, _begin: usize, _n: usize) {}
+}
+
+/// Take the lowest free run that fits.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FirstFit;
+
+impl FitPolicy for FirstFit {
+    const FIRST_FIT: bool = true;
+
+    fn place<T: BitAllocator + ?Sized>(&self, bm: &T, n: usize, align: usize,
+                                       offset: usize) -> Option<usize> {
+        first_fit(bm, 0, bm.size(), n, align, offset)
+    }
+}
+
+/// Resume the search where the previous allocation ended, wrapping around at
+/// the end of the bitmap.
+///
+/// The cursor is stored as I, so a bitmap of at most `I::MAX` bits can use a
+/// narrow index type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NextFit<I = usize> {
+    cursor: I, // right after the latest allocation
+}
+
+impl NextFit {
+    /// Start the first search at the beginning of the bitmap.
+    pub const fn new() -> Self { Self::with_index_type() }
+}
+
+impl<I: BitIndex> NextFit<I> {
+    /// Like new, but the cursor is stored as I.
+    pub const fn with_index_type() -> Self { NextFit { cursor: I::ZERO } }
+}
+
+impl Default for NextFit {
+    fn default() -> Self { Self::new() }
+}
+
+impl<I: BitIndex> FitPolicy for NextFit<I> {
+    fn place<T: BitAllocator + ?Sized>(&self, bm: &T, n: usize, align: usize,
+                                       offset: usize) -> Option<usize> {
+        wrapping_fit(bm, self.cursor.to_usize(), n, align, offset)
+    }
+
+    fn allocated(&mut self, begin: usize, n: usize) {
+        self.cursor = I::from_usize(begin + n);
+    }
+}
+
+/// Take the smallest free run that fits, the lowest one among equals.
+/// Slower, since every free run is examined, but keeps large runs intact.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct BestFit;
+
+impl FitPolicy for BestFit {
+    fn place<T: BitAllocator + ?Sized>(&self, bm: &T, n: usize, align: usize,
+                                       offset: usize) -> Option<usize> {
+        sized_fit(bm, n, align, offset, |len, best| len < best)
+    }
+}
+
+/// Take the largest free run, the lowest one among equals, so that the
+/// remainder left behind is as large as possible.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct WorstFit;
+
+impl FitPolicy for WorstFit {
+    fn place<T: BitAllocator + ?Sized>(&self, bm: &T, n: usize, align: usize,
+                                       offset: usize) -> Option<usize> {
+        sized_fit(bm, n, align, offset, |len, best| len > best)
+    }
+}
+
+/// Satisfy small requests bottom-up and large ones top-down, each direction
+/// resuming where its previous allocation was made, so that the two kinds do
+/// not interleave and the space between them stays contiguous.
+///
+/// The cursors are stored as I, as with [`NextFit`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Bidirectional<I = usize> {
+    threshold: usize,
+    low: I,  // right after the latest small allocation
+    high: I, // start of the latest large allocation, 0 (wrapping to the top) before any
+}
+
+impl Bidirectional {
+    /// Requests of more than threshold bits are placed top-down.
+    pub const fn new(threshold: usize) -> Self { Self::with_index_type(threshold) }
+}
+
+impl<I: BitIndex> Bidirectional<I> {
+    /// Like new, but the cursors are stored as I.
+    pub const fn with_index_type(threshold: usize) -> Self {
+        Bidirectional { threshold, low: I::ZERO, high: I::ZERO }
+    }
+}
+
+impl Default for Bidirectional {
+    /// Requests of more than 8 bits are placed top-down.
+    fn default() -> Self { Self::new(8) }
+}
+
+impl<I: BitIndex> FitPolicy for Bidirectional<I> {
+    fn place<T: BitAllocator + ?Sized>(&self, bm: &T, n: usize, align: usize,
+                                       offset: usize) -> Option<usize> {
+        if n > self.threshold {
+            wrapping_last_fit(bm, self.high.to_usize(), n, align, offset)
+        } else {
+            wrapping_fit(bm, self.low.to_usize(), n, align, offset)
+        }
+    }
+
+    fn allocated(&mut self, begin: usize, n: usize) {
+        if n > self.threshold {
+            self.high = I::from_usize(begin);
+        } else {
+            self.low = I::from_usize(begin + n);
+        }
+    }
+}
+
+/// Lowest i >= begin such that `i % align == offset`.
+pub(crate) fn align_up(begin: usize, align: usize, offset: usize) -> usize {
+    begin + (offset + align - begin % align) % align
+}
+
+/// Highest i <= last such that `i % align == offset`, if any.
+fn align_down(last: usize, align: usize, offset: usize) -> Option<usize> {
+    last.checked_sub((last % align + align - offset) % align)
+}
+
+/// Where n bits at an index congruent to offset modulo align go in the free
+/// run [begin, end), if they fit.
+fn fit(begin: usize, end: usize, n: usize, align: usize, offset: usize) -> Option<usize> {
+    let aligned = align_up(begin, align, offset);
+    if end > aligned && end - aligned >= n { Some(aligned) } else { None }
+}
+
+/// Free runs of bm from the from-th bit on, as `(begin, len)` pairs.
+pub(crate) fn free_runs<T: BitAllocator + ?Sized>(bm: &T, from: usize) -> Runs<'_, T> {
+    Runs { bm, pos: from, allocated: false }
+}
+
+/// Index of n bits, placed as by fit, in the free run that holds them and whose
+/// length is preferred over all others by better(len, best_len).
+fn sized_fit<T, F>(bm: &T, n: usize, align: usize, offset: usize, better: F) -> Option<usize>
+    where T: BitAllocator + ?Sized, F: Fn(usize, usize) -> bool {
+    let mut best: Option<(usize, usize)> = None; // (run length, index)
+    for (begin, len) in free_runs(bm, 0) {
+        if let Some(i) = fit(begin, begin + len, n, align, offset) {
+            if best.map_or(true, |(l, _)| better(len, l)) { best = Some((len, i)); }
+        }
+    }
+    best.map(|(_, i)| i)
+}
+
+/// Lowest index i, with `i % align == offset`, of a free run of n bits within [lo, hi).
+pub(crate) fn first_fit<T: BitAllocator + ?Sized>(bm: &T, lo: usize, hi: usize,
+                                                   n: usize, align: usize, offset: usize) -> Option<usize> {
+    free_runs(bm, lo)
+        .take_while(|&(begin, _)| begin < hi)
+        .find_map(|(begin, len)| fit(begin, min(begin + len, hi), n, align, offset))
+}
+
+/// Like first_fit over the whole bitmap, but the search starts at start and
+/// wraps around; runs crossing start are still found.
+pub(crate) fn wrapping_fit<T: BitAllocator + ?Sized>(bm: &T, start: usize,
+                                                      n: usize, align: usize, offset: usize) -> Option<usize> {
+    let size = bm.size();
+    let start = if start < size { start } else { 0 };
+    first_fit(bm, start, size, n, align, offset)
+        .or_else(|| first_fit(bm, 0, min(size, start + n - 1), n, align, offset))
+}
+
+/// Highest index i, with `i % align == offset`, of a free run of n bits within [lo, hi).
+pub(crate) fn last_fit<T: BitAllocator + ?Sized>(bm: &T, lo: usize, hi: usize,
+                                                  n: usize, align: usize, offset: usize) -> Option<usize> {
+    let mut pos = hi;
+    loop {
+        let last = bm.find_prev(pos, false)?;
+        if last < lo { return None; }
+        let begin = bm.find_prev(last, true).map_or(0, |i| i + 1).max(lo);
+        let end = last + 1;
+        if end - begin >= n {
+            match align_down(end - n, align, offset) {
+                Some(aligned) if aligned >= begin => return Some(aligned),
+                _ => {}
+            }
+        }
+        pos = begin;
+    }
+}
+
+/// Like last_fit over the whole bitmap, but the search starts right below
+/// start and wraps around to the top; runs crossing start are still found.
+pub(crate) fn wrapping_last_fit<T: BitAllocator + ?Sized>(bm: &T, start: usize,
+                                                          n: usize, align: usize, offset: usize) -> Option<usize> {
+    let size = bm.size();
+    let start = min(start, size);
+    last_fit(bm, 0, start, n, align, offset)
+        .or_else(|| last_fit(bm, start.saturating_sub(n - 1), size, n, align, offset))
+}
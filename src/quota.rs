@@ -0,0 +1,204 @@
+//! Per-tag accounting of allocated bits against quotas.
+
+use crate::{AllocError, BitAllocator, MAX_BITS, ReserveConflict, Tag, check_len, exhaustion};
+
+/// Tags a [`QuotaAllocator`] can hold quotas for.
+pub const MAX_QUOTAS: usize = 16;
+
+#[derive(Copy, Clone, Default)]
+struct Quota {
+    tag: Tag,
+    limit: usize,
+    used: usize,
+}
+
+/// Wraps an allocator so that every allocation is charged to a [`Tag`], such as
+/// the subsystem it is made for, and tags can be given a quota of bits.
+///
+/// Each allocated bit remembers the tag it was charged to, so frees are
+/// credited back to the right tag whoever makes them. Allocations through
+/// the [`BitAllocator`] methods, and marks, are charged to tag 0; those
+/// through alloc_tagged to the tag given. A request that would take a tag
+/// over its quota fails with `AllocError::QuotaExceeded`. Up to MAX_QUOTAS
+/// tags have quotas; the others are unlimited.
+pub struct QuotaAllocator<A> {
+    inner: A,
+    tags: [Tag; MAX_BITS],
+    quotas: [Quota; MAX_QUOTAS],
+    count: usize,
+}
+
+impl<A: BitAllocator> QuotaAllocator<A> {
+    /// Wrap inner, without quotas. Bits already allocated are charged to
+    /// tag 0.
+    ///
+    /// Panics if the size of inner exceeds the capacity.
+    pub fn new(inner: A) -> Self {
+        assert!(inner.size() <= MAX_BITS);
+        QuotaAllocator { inner, tags: [0; MAX_BITS], quotas: [Quota::default(); MAX_QUOTAS], count: 0 }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator, forgetting tags and quotas.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// Let tag hold at most limit bits. Bits it holds already count, even
+    /// if they are over the limit.
+    ///
+    /// Panics if MAX_QUOTAS other tags already have quotas.
+    pub fn set_quota(&mut self, tag: Tag, limit: usize) {
+        if let Some(q) = self.quota_mut(tag) {
+            q.limit = limit;
+            return;
+        }
+        assert!(self.count < MAX_QUOTAS);
+        let used = self.usage(tag);
+        self.quotas[self.count] = Quota { tag, limit, used };
+        self.count += 1;
+    }
+
+    /// The quota of tag, if it has one.
+    pub fn quota(&self, tag: Tag) -> Option<usize> {
+        self.quotas[..self.count].iter().find(|q| q.tag == tag).map(|q| q.limit)
+    }
+
+    /// Number of allocated bits charged to tag.
+    ///
+    /// Takes a scan of the bitmap for tags without a quota.
+    pub fn usage(&self, tag: Tag) -> usize {
+        match self.quotas[..self.count].iter().find(|q| q.tag == tag) {
+            Some(q) => q.used,
+            None => (0..self.inner.size()).filter(|&i| self.tags[i] == tag && self.inner.is_allocated(i)).count(),
+        }
+    }
+
+    /// Allocate n consecutive bits charged to tag.
+    pub fn alloc_tagged(&mut self, n: usize, tag: Tag) -> Result<usize, AllocError> {
+        self.alloc_tagged_aligned(n, 1, tag)
+    }
+
+    /// Like alloc_tagged, but the returned index is a multiple of alignment.
+    pub fn alloc_tagged_aligned(&mut self, n: usize, alignment: usize, tag: Tag) -> Result<usize, AllocError> {
+        check_len(self.inner.size(), n)?;
+        if alignment == 0 { return Err(AllocError::InvalidAlignment); }
+        self.charged(n, tag, |a| a.alloc_aligned(n, alignment))
+    }
+
+    // Run f on inner to allocate n bits charged to tag, if its quota allows.
+    fn charged<F>(&mut self, n: usize, tag: Tag, f: F) -> Result<usize, AllocError>
+        where F: FnOnce(&mut A) -> Option<usize> {
+        if let Some(q) = self.quota_mut(tag) {
+            if q.used.saturating_add(n) > q.limit { return Err(AllocError::QuotaExceeded); }
+        }
+        let begin = f(&mut self.inner).ok_or_else(|| exhaustion(&self.inner, n))?;
+        self.charge(begin, n, tag);
+        Ok(begin)
+    }
+
+    fn quota_mut(&mut self, tag: Tag) -> Option<&mut Quota> {
+        self.quotas[..self.count].iter_mut().find(|q| q.tag == tag)
+    }
+
+    // Run f on inner to free bits within ranges, crediting each bit it
+    // frees to the tag it was charged to. Holes and pinned bits stay
+    // allocated, so they are not credited.
+    fn credited<F: FnOnce(&mut A)>(&mut self, ranges: &[(usize, usize)], f: F) {
+        let mut was = [0u64; MAX_BITS / 64];
+        for &(begin, n) in ranges {
+            assert!(begin + n <= self.inner.size());
+            for i in (begin..begin + n).filter(|&i| self.inner.is_allocated(i)) {
+                was[i / 64] |= 1 << (i % 64);
+            }
+        }
+        f(&mut self.inner);
+        for &(begin, n) in ranges {
+            for i in begin..begin + n {
+                if (was[i / 64] >> (i % 64)) & 1 == 0 || self.inner.is_allocated(i) { continue; }
+                // Clear it, so ranges that overlap credit each bit once.
+                was[i / 64] &= !(1 << (i % 64));
+                let tag = self.tags[i];
+                if let Some(q) = self.quota_mut(tag) { q.used -= 1; }
+            }
+        }
+    }
+
+    // Charge the n freshly allocated bits at begin to tag.
+    fn charge(&mut self, begin: usize, n: usize, tag: Tag) {
+        for t in &mut self.tags[begin..begin + n] { *t = tag; }
+        if let Some(q) = self.quota_mut(tag) { q.used += n; }
+    }
+}
+
+impl<A: BitAllocator> BitAllocator for QuotaAllocator<A> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        self.alloc_tagged(n, 0).ok()
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        self.alloc_tagged_aligned(n, alignment, 0).ok()
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        self.charged(n, 0, |a| a.alloc_aligned_offset(n, align, offset)).ok()
+    }
+
+    fn try_alloc(&mut self, n: usize) -> Result<usize, AllocError> {
+        self.alloc_tagged(n, 0)
+    }
+
+    fn try_alloc_aligned(&mut self, n: usize, alignment: usize) -> Result<usize, AllocError> {
+        self.alloc_tagged_aligned(n, alignment, 0)
+    }
+
+    /// Free the bits, crediting each to the tag it was charged to.
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.credited(&[(begin, n)], |a| a.dealloc(begin, n));
+    }
+
+    /// Mark the bits, charging those that were free to tag 0, quota or not.
+    fn mark(&mut self, begin: usize, n: usize) {
+        assert!(begin + n <= self.inner.size());
+        for i in begin..begin + n {
+            if self.inner.is_allocated(i) { continue; }
+            self.inner.mark(i, 1);
+            self.charge(i, 1, 0);
+        }
+    }
+
+    /// Free the ranges in one call to the wrapped allocator, crediting each
+    /// bit to the tag it was charged to.
+    fn dealloc_batch(&mut self, ranges: &[(usize, usize)]) {
+        self.credited(ranges, |a| a.dealloc_batch(ranges));
+    }
+
+    /// Set every bit, crediting the bits freed to their tags or charging
+    /// those allocated to tag 0.
+    fn set_all(&mut self, allocated: bool) {
+        let size = self.inner.size();
+        if !allocated {
+            return self.credited(&[(0, size)], |a| a.set_all(false));
+        }
+        for i in 0..size {
+            if !self.inner.is_allocated(i) { self.charge(i, 1, 0); }
+        }
+        self.inner.set_all(true);
+    }
+
+    /// Like alloc_at, charging the bits to tag 0, quota or not.
+    fn alloc_at(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.inner.alloc_at(begin, n)?;
+        self.charge(begin, n, 0);
+        Ok(())
+    }
+
+    /// Like reserve, charging the bits to tag 0, quota or not.
+    fn reserve(&mut self, begin: usize, n: usize) -> Result<(), ReserveConflict> {
+        self.inner.reserve(begin, n)?;
+        self.charge(begin, n, 0);
+        Ok(())
+    }
+
+    forward_bit_allocator!();
+}
@@ -0,0 +1,83 @@
+//! Allocation that asks caches to give memory back before failing.
+
+use crate::BitAllocator;
+
+/// Shrinkers a [`ReclaimAllocator`] can hold.
+pub const MAX_SHRINKERS: usize = 8;
+
+/// Something holding bits it can give back under memory pressure, such as
+/// a page cache.
+pub trait Shrinker {
+    /// Free about wanted bits of bm that can be done without, and return how
+    /// many bits were freed.
+    fn shrink(&mut self, bm: &mut dyn BitAllocator, wanted: usize) -> usize;
+}
+
+/// Wraps an allocator with a list of [`Shrinker`]s that alloc_with_reclaim
+/// calls upon when a request fails.
+///
+/// After a failure, every shrinker is asked for the number of bits
+/// requested, in the order they were registered, and the request is tried
+/// again, up to a bounded number of times. It gives up early once no
+/// shrinker frees anything.
+pub struct ReclaimAllocator<'s, A> {
+    inner: A,
+    shrinkers: [Option<&'s mut dyn Shrinker>; MAX_SHRINKERS],
+    count: usize,
+    retries: usize,
+}
+
+impl<'s, A: BitAllocator> ReclaimAllocator<'s, A> {
+    /// Wrap inner, without shrinkers, retrying a failed request up to 3
+    /// times.
+    pub fn new(inner: A) -> Self {
+        ReclaimAllocator { inner, shrinkers: Default::default(), count: 0, retries: 3 }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator, forgetting the shrinkers.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// Call shrinker when memory runs out, after those registered before.
+    ///
+    /// Panics if MAX_SHRINKERS shrinkers were already registered.
+    pub fn register_shrinker(&mut self, shrinker: &'s mut dyn Shrinker) {
+        assert!(self.count < MAX_SHRINKERS);
+        self.shrinkers[self.count] = Some(shrinker);
+        self.count += 1;
+    }
+
+    /// Retry a failed request up to retries times.
+    pub fn set_retries(&mut self, retries: usize) { self.retries = retries; }
+
+    /// Like alloc, but on failure calls the shrinkers and retries.
+    pub fn alloc_with_reclaim(&mut self, n: usize) -> Option<usize> {
+        for _ in 0..self.retries {
+            if let Some(begin) = self.inner.alloc(n) { return Some(begin); }
+            if self.reclaim(n) == 0 { return None; }
+        }
+        self.inner.alloc(n)
+    }
+
+    /// Ask every shrinker for wanted bits, and return how many were freed.
+    pub fn reclaim(&mut self, wanted: usize) -> usize {
+        let inner = &mut self.inner;
+        self.shrinkers[..self.count].iter_mut().flatten().map(|s| s.shrink(&mut *inner, wanted)).sum()
+    }
+}
+
+impl<'s, A: BitAllocator> BitAllocator for ReclaimAllocator<'s, A> {
+    fn alloc(&mut self, n: usize) -> Option<usize> { self.inner.alloc(n) }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        self.inner.alloc_aligned(n, alignment)
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        self.inner.alloc_aligned_offset(n, align, offset)
+    }
+
+    forward_bit_allocator!(dealloc, mark, dealloc_batch, set_all, alloc_at, reserve);
+}
@@ -0,0 +1,96 @@
+//! Reference-counted allocation, for bits shared by several owners.
+
+use crate::{AllocError, BitAllocator, BitIndex, MAX_BITS};
+
+/// Wraps an allocator so that every allocated bit carries a reference count
+/// stored as C, and is freed only when its last reference is dropped.
+///
+/// This is what copy-on-write needs from a frame allocator: on fork, the
+/// frames of the parent gain a reference instead of being copied.
+pub struct RefCountAlloc<A, C = u8> {
+    inner: A,
+    counts: [C; MAX_BITS],
+}
+
+impl<A: BitAllocator> RefCountAlloc<A> {
+    /// Wrap inner, which must not have been allocated from yet. Counts
+    /// range up to `u8::MAX`.
+    pub fn new(inner: A) -> Self {
+        Self::with_count_type(inner)
+    }
+}
+
+impl<A: BitAllocator, C: BitIndex> RefCountAlloc<A, C> {
+    /// Like new, but counts range up to `C::MAX`.
+    ///
+    /// Panics if the size of inner exceeds the capacity.
+    pub fn with_count_type(inner: A) -> Self {
+        assert!(inner.size() <= MAX_BITS);
+        RefCountAlloc { inner, counts: [C::from_usize(0); MAX_BITS] }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator, forgetting all counts.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// Allocate n consecutive bits, like `BitAllocator::alloc`, each with a
+    /// count of one.
+    pub fn alloc(&mut self, n: usize) -> Option<usize> {
+        let begin = self.inner.alloc(n)?;
+        for count in &mut self.counts[begin..begin + n] { *count = C::from_usize(1); }
+        Some(begin)
+    }
+
+    /// Number of references to the index-th bit; zero if it is free.
+    ///
+    /// Panics if index is not smaller than the size.
+    pub fn count(&self, index: usize) -> usize {
+        assert!(index < self.inner.size());
+        self.counts[index].to_usize()
+    }
+
+    /// Take one more reference to each of the bits [begin, begin+n).
+    ///
+    /// Fails, without changing anything, if the range is empty or out of
+    /// bounds, if some bit of it is not allocated through self, or if a
+    /// count would exceed `C::MAX`.
+    pub fn incref(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.check(begin, n)?;
+        if self.counts[begin..begin + n].iter().any(|&count| count.to_usize() == C::MAX) {
+            return Err(AllocError::CountOverflow);
+        }
+        for count in &mut self.counts[begin..begin + n] {
+            *count = C::from_usize(count.to_usize() + 1);
+        }
+        Ok(())
+    }
+
+    /// Drop one reference to each of the bits [begin, begin+n), freeing those
+    /// left without any. Returns how many bits were freed.
+    ///
+    /// Fails, without changing anything, if the range is empty or out of
+    /// bounds, or if some bit of it is not allocated through self.
+    pub fn decref(&mut self, begin: usize, n: usize) -> Result<usize, AllocError> {
+        self.check(begin, n)?;
+        let mut freed = 0;
+        for i in begin..begin + n {
+            let count = self.counts[i].to_usize() - 1;
+            self.counts[i] = C::from_usize(count);
+            if count == 0 {
+                self.inner.dealloc(i, 1);
+                freed += 1;
+            }
+        }
+        Ok(freed)
+    }
+
+    fn check(&self, begin: usize, n: usize) -> Result<(), AllocError> {
+        crate::check_range(self.inner.size(), begin, n)?;
+        if self.counts[begin..begin + n].iter().any(|&count| count.to_usize() == 0) {
+            return Err(AllocError::DoubleFree);
+        }
+        Ok(())
+    }
+}
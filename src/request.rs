@@ -0,0 +1,112 @@
+//! Allocation requests combining several placement constraints.
+
+use core::cmp::min;
+use core::ops::Range;
+use crate::{AllocError, BitAllocator, exhaustion};
+use crate::policy::align_up;
+
+/// A request for n consecutive bits, built up one constraint at a time.
+///
+/// ```
+/// use brute_bitmap_allocator::{AllocRequest, BitAllocator, bitalloc::LinearBitMap};
+///
+/// let mut bm = LinearBitMap::new(64);
+/// let req = AllocRequest::new().size(4).align(4).within(16..32).not_crossing(8);
+/// assert_eq!(Ok(16), req.allocate(&mut bm));
+/// assert_eq!(Ok(20), req.allocate(&mut bm));
+/// ```
+///
+/// The search is first-fit (starting at the hint, if any) regardless of the
+/// policy of the allocator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllocRequest {
+    n: usize,
+    align: usize,
+    range: Option<Range<usize>>,
+    boundary: Option<usize>,
+    hint: Option<usize>,
+}
+
+impl Default for AllocRequest {
+    fn default() -> Self { Self::new() }
+}
+
+impl AllocRequest {
+    /// A request for a single bit without further constraints.
+    pub fn new() -> Self {
+        AllocRequest { n: 1, align: 1, range: None, boundary: None, hint: None }
+    }
+
+    /// Request n consecutive bits.
+    pub fn size(mut self, n: usize) -> Self { self.n = n; self }
+
+    /// The first index must be a multiple of align, which must not be zero.
+    pub fn align(mut self, align: usize) -> Self { self.align = align; self }
+
+    /// All bits must lie within range.
+    pub fn within(mut self, range: Range<usize>) -> Self { self.range = Some(range); self }
+
+    /// The bits must not straddle a multiple of boundary.
+    pub fn not_crossing(mut self, boundary: usize) -> Self { self.boundary = Some(boundary); self }
+
+    /// Start the search at hint and wrap around, like `BitAllocator::alloc_from`.
+    pub fn from_hint(mut self, hint: usize) -> Self { self.hint = Some(hint); self }
+
+    /// Allocate the bits described by this request from bm.
+    pub fn allocate<A: BitAllocator + ?Sized>(&self, bm: &mut A) -> Result<usize, AllocError> {
+        let begin = self.find(bm)?;
+        bm.mark(begin, self.n);
+        Ok(begin)
+    }
+
+    /// Index at which allocate would place the bits, without allocating them.
+    pub fn find<A: BitAllocator + ?Sized>(&self, bm: &A) -> Result<usize, AllocError> {
+        let Range { start: lo, end: hi } = self.validate(bm.size())?;
+        match self.hint {
+            Some(hint) if lo <= hint && hint < hi => self.search(bm, hint, hi)
+                .or_else(|| self.search(bm, lo, min(hi, hint + self.n - 1))),
+            _ => self.search(bm, lo, hi),
+        }.ok_or_else(|| exhaustion(bm, self.n))
+    }
+
+    /// The range to search in a bitmap of size bits, if the request makes sense.
+    fn validate(&self, size: usize) -> Result<Range<usize>, AllocError> {
+        if self.n == 0 || self.boundary.is_some_and(|b| self.n > b) {
+            return Err(AllocError::InvalidSize);
+        }
+        if self.align == 0 {
+            return Err(AllocError::InvalidAlignment);
+        }
+        let range = self.range.clone().unwrap_or(0..size);
+        if range.start > range.end || range.end > size || range.end - range.start < self.n {
+            return Err(AllocError::OutOfBounds);
+        }
+        Ok(range)
+    }
+
+    /// Lowest index satisfying the request within [lo, hi).
+    fn search<A: BitAllocator + ?Sized>(&self, bm: &A, lo: usize, hi: usize) -> Option<usize> {
+        let mut pos = lo;
+        loop {
+            let begin = bm.find_next(pos, false);
+            if begin >= hi { return None; }
+            let end = min(bm.find_next(begin, true), hi);
+            if let Some(i) = self.fit(begin, end) { return Some(i); }
+            pos = end;
+        }
+    }
+
+    /// Lowest index satisfying the request in the free run [begin, end).
+    fn fit(&self, begin: usize, end: usize) -> Option<usize> {
+        let mut i = align_up(begin, self.align, 0);
+        loop {
+            if i >= end || end - i < self.n { return None; }
+            match self.boundary {
+                Some(b) if i / b != (i + self.n - 1) / b => {
+                    i = align_up((i / b + 1) * b, self.align, 0);
+                }
+                _ => return Some(i),
+            }
+        }
+    }
+}
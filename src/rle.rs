@@ -0,0 +1,59 @@
+//! A run-length encoding of bitmaps, compact when they are mostly free or
+//! mostly allocated.
+//!
+//! An encoded bitmap is a sequence of unsigned LEB128 numbers: the size of the
+//! bitmap, followed by the lengths of its runs, alternately free and
+//! allocated. The first run is free and may be empty; the others are not.
+
+use core::slice::Iter;
+use crate::{BitAllocator, BitAllocatorInit};
+
+/// Pass the encoding of bm to emit, byte by byte.
+pub(crate) fn encode<T: BitAllocator + ?Sized, F: FnMut(u8)>(bm: &T, mut emit: F) {
+    put(bm.size(), &mut emit);
+    let (mut pos, mut allocated) = (0, false);
+    while pos < bm.size() {
+        let next = bm.find_next(pos, !allocated);
+        put(next - pos, &mut emit);
+        pos = next;
+        allocated = !allocated;
+    }
+}
+
+/// The bitmap encoded in data, or `None` if data is not a valid encoding.
+pub(crate) fn decode<T: BitAllocatorInit>(data: &[u8]) -> Option<T> {
+    let mut bytes = data.iter();
+    let size = get(&mut bytes)?;
+    if size > T::new(0).capacity() { return None; }
+    let mut bm = T::new(size);
+    let (mut pos, mut allocated) = (0, false);
+    while pos < size {
+        let n = get(&mut bytes)?;
+        // Only the first run, free and starting at 0, may be empty.
+        if n > size - pos || (n == 0 && (allocated || pos > 0)) { return None; }
+        if allocated { bm.mark(pos, n); }
+        pos += n;
+        allocated = !allocated;
+    }
+    if bytes.next().is_some() { None } else { Some(bm) }
+}
+
+fn put<F: FnMut(u8)>(mut x: usize, emit: &mut F) {
+    while x >= 0x80 {
+        emit(x as u8 | 0x80);
+        x >>= 7;
+    }
+    emit(x as u8);
+}
+
+fn get(bytes: &mut Iter<u8>) -> Option<usize> {
+    let (mut x, mut shift) = (0usize, 0);
+    loop {
+        let b = *bytes.next()?;
+        let payload = (b & 0x7f) as usize;
+        if shift >= usize::BITS || (payload << shift) >> shift != payload { return None; }
+        x |= payload << shift;
+        if b & 0x80 == 0 { return Some(x); }
+        shift += 7;
+    }
+}
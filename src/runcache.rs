@@ -0,0 +1,118 @@
+//! Caches of free runs by size class, to skip the scan for larger requests.
+
+use crate::BitAllocator;
+
+/// Smallest run length of each size class.
+const CLASSES: [usize; 3] = [2, 8, 64];
+/// Positions remembered per size class.
+const SLOTS: usize = 4;
+
+/// Wraps an allocator with, for each size class, the starts of a few free
+/// runs of at least that many bits seen on recent deallocations.
+///
+/// A request of n >= 2 bits first tries the most recently seen runs of the
+/// largest class not above n, checking each candidate against the bitmap,
+/// and only scans the wrapped allocator when none fits. Requests of a single
+/// bit, and aligned ones, always go to the wrapped allocator.
+///
+/// When a cached run is taken, the returned index is not necessarily the
+/// lowest one at which the request fits, so results differ from first fit.
+pub struct RunCache<A> {
+    inner: A,
+    // Most recent first.
+    runs: [[Option<usize>; SLOTS]; CLASSES.len()],
+}
+
+impl<A: BitAllocator> RunCache<A> {
+    /// Wrap inner, with empty caches.
+    pub fn new(inner: A) -> Self {
+        RunCache { inner, runs: [[None; SLOTS]; CLASSES.len()] }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator, forgetting the caches.
+    pub fn into_inner(self) -> A { self.inner }
+
+    // Number of free bits starting at begin.
+    fn free_len(&self, begin: usize) -> usize {
+        if begin >= self.inner.size() || self.inner.is_allocated(begin) { return 0; }
+        self.inner.find_next(begin, true) - begin
+    }
+
+    // Remember that a free run of len bits starts at begin.
+    fn remember(&mut self, begin: usize, len: usize) {
+        for (class, runs) in CLASSES.iter().zip(self.runs.iter_mut()) {
+            if len < *class { break; }
+            if runs[0] == Some(begin) { continue; }
+            let last = runs.iter().position(|&r| r == Some(begin)).unwrap_or(SLOTS - 1);
+            runs.copy_within(0..last, 1);
+            runs[0] = Some(begin);
+        }
+    }
+
+    // Remember the free run the just freed bits [begin, begin+n) are part of.
+    fn freed(&mut self, begin: usize, n: usize) {
+        if n == 0 { return; }
+        let start = match self.inner.find_prev(begin + 1, true) {
+            Some(i) if i >= begin => return, // begin stayed allocated, e.g. a hole
+            Some(i) => i + 1,
+            None => 0,
+        };
+        let len = self.free_len(start);
+        self.remember(start, len);
+    }
+
+    // Take n bits from a cached run, if one still fits.
+    fn alloc_cached(&mut self, n: usize) -> Option<usize> {
+        let class = CLASSES.iter().rposition(|&c| c <= n)?;
+        for slot in 0..SLOTS {
+            let begin = match self.runs[class][slot] {
+                Some(begin) => begin,
+                None => continue,
+            };
+            let len = self.free_len(begin);
+            if len >= n {
+                self.inner.mark(begin, n);
+                self.runs[class][slot] = None;
+                self.remember(begin + n, len - n);
+                return Some(begin);
+            }
+            if len < CLASSES[class] {
+                self.runs[class][slot] = None;
+            }
+        }
+        None
+    }
+}
+
+impl<A: BitAllocator> BitAllocator for RunCache<A> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.inner.size());
+        self.alloc_cached(n).or_else(|| self.inner.alloc(n))
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        self.inner.alloc_aligned(n, alignment)
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        self.inner.alloc_aligned_offset(n, align, offset)
+    }
+
+    /// Free the bits, then remember the free run they are part of.
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.inner.dealloc(begin, n);
+        self.freed(begin, n);
+    }
+
+    /// Free the ranges in one call to the wrapped allocator, then remember
+    /// the free run each is part of.
+    fn dealloc_batch(&mut self, ranges: &[(usize, usize)]) {
+        self.inner.dealloc_batch(ranges);
+        for &(begin, n) in ranges { self.freed(begin, n); }
+    }
+
+    forward_bit_allocator!(mark, set_all, alloc_at, reserve);
+}
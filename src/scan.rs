@@ -0,0 +1,58 @@
+//! Skipping over bytes of a bitmap that are all allocated or all free.
+//!
+//! With the `simd` feature, 16 bytes are compared at a time with SSE2 on
+//! x86_64 and NEON on aarch64; elsewhere, and without the feature, 8 at a
+//! time in a u64.
+
+use core::convert::TryInto;
+
+/// Number of leading bytes of bytes that are equal to value.
+pub(crate) fn prefix_len(bytes: &[u8], value: u8) -> usize {
+    let skipped = wide_prefix_len(bytes, value);
+    skipped + scalar_prefix_len(&bytes[skipped..], value)
+}
+
+fn scalar_prefix_len(bytes: &[u8], value: u8) -> usize {
+    let pattern = u64::from_ne_bytes([value; 8]);
+    let mut chunks = bytes.chunks_exact(8);
+    let words = chunks.by_ref().take_while(|c| u64::from_ne_bytes((*c).try_into().unwrap()) == pattern).count();
+    let rest = &bytes[words * 8..];
+    words * 8 + rest.iter().take_while(|&&b| b == value).count()
+}
+
+// A multiple of 16 bytes that are all equal to value, from the beginning:
+// the scalar loop finishes from there.
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn wide_prefix_len(bytes: &[u8], value: u8) -> usize {
+    use core::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8};
+    let mut i = 0;
+    // SSE2 is part of x86_64, and every load is of 16 bytes within bytes.
+    unsafe {
+        let pattern = _mm_set1_epi8(value as i8);
+        while i + 16 <= bytes.len() {
+            let chunk = _mm_loadu_si128(bytes.as_ptr().add(i) as *const __m128i);
+            if _mm_movemask_epi8(_mm_cmpeq_epi8(chunk, pattern)) != 0xffff { break; }
+            i += 16;
+        }
+    }
+    i
+}
+
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+fn wide_prefix_len(bytes: &[u8], value: u8) -> usize {
+    use core::arch::aarch64::{vceqq_u8, vdupq_n_u8, vld1q_u8, vminvq_u8};
+    let mut i = 0;
+    // NEON is part of aarch64, and every load is of 16 bytes within bytes.
+    unsafe {
+        let pattern = vdupq_n_u8(value);
+        while i + 16 <= bytes.len() {
+            let chunk = vld1q_u8(bytes.as_ptr().add(i));
+            if vminvq_u8(vceqq_u8(chunk, pattern)) != 0xff { break; }
+            i += 16;
+        }
+    }
+    i
+}
+
+#[cfg(not(all(feature = "simd", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+fn wide_prefix_len(_bytes: &[u8], _value: u8) -> usize { 0 }
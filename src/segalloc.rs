@@ -0,0 +1,183 @@
+//! A first-fit allocator whose free runs are summarized by a segment tree.
+
+use core::cmp::min;
+use crate::{BitAllocator, BitAllocatorInit, MAX_BITS, policy};
+
+const LEAVES: usize = MAX_BITS.next_power_of_two();
+
+/// A bitmap allocator that finds the lowest free run of n bits in
+/// O(log size) rather than by scanning.
+///
+/// Every node of a segment tree over the bits records the longest free run
+/// in its range, along with the free runs touching either end of the range,
+/// so a search only descends into subtrees that can hold the request.
+/// `alloc_aligned` jumps between the free runs long enough for the request,
+/// in O(log size) each. Freeing or marking n bits costs O(n + log size).
+///
+/// Returns the same indices as the other first-fit allocators.
+#[derive(Clone)]
+pub struct SegTreeBitMap {
+    size: usize,
+    used: usize,
+    // Indexed by node: the root is 1 and the children of i are 2i and 2i+1,
+    // so the leaf of bit i is LEAVES + i. Bits past size count as allocated.
+    prefix: [u16; 2 * LEAVES], // Free bits at the start of the range.
+    suffix: [u16; 2 * LEAVES], // Free bits at the end of the range.
+    longest: [u16; 2 * LEAVES], // Longest free run within the range.
+}
+
+impl SegTreeBitMap {
+    /// Create a bitmap with size bits. By default, all bits are unallocated.
+    pub const fn new(size: usize) -> Self {
+        assert!(size <= MAX_BITS);
+        let mut bm = SegTreeBitMap {
+            size,
+            used: 0,
+            prefix: [0; 2 * LEAVES],
+            suffix: [0; 2 * LEAVES],
+            longest: [0; 2 * LEAVES],
+        };
+        let mut i = 0;
+        while i < size {
+            bm.prefix[LEAVES + i] = 1;
+            bm.suffix[LEAVES + i] = 1;
+            bm.longest[LEAVES + i] = 1;
+            i += 1;
+        }
+        let mut node = LEAVES - 1;
+        while node > 0 {
+            (bm.prefix[node], bm.suffix[node], bm.longest[node]) = bm.combined(node);
+            node -= 1;
+        }
+        bm
+    }
+
+    // Recompute node from its children.
+    fn update(&mut self, node: usize) {
+        (self.prefix[node], self.suffix[node], self.longest[node]) = self.combined(node);
+    }
+
+    // The prefix, suffix and longest free runs of node, from its children.
+    const fn combined(&self, node: usize) -> (u16, u16, u16) {
+        let (l, r) = (2 * node, 2 * node + 1);
+        let half = (LEAVES >> (usize::BITS - 1 - node.leading_zeros())) as u16 / 2;
+        let prefix = if self.prefix[l] == half { half + self.prefix[r] } else { self.prefix[l] };
+        let suffix = if self.suffix[r] == half { half + self.suffix[l] } else { self.suffix[r] };
+        let mut longest = self.suffix[l] + self.prefix[r];
+        if self.longest[l] > longest { longest = self.longest[l]; }
+        if self.longest[r] > longest { longest = self.longest[r]; }
+        (prefix, suffix, longest)
+    }
+
+    // Set bits [begin, end) to allocated or free, then fix up their ancestors.
+    fn set_range(&mut self, begin: usize, end: usize, allocated: bool) {
+        // Bits past the size are ignored.
+        let end = min(end, self.size);
+        if begin >= end { return; }
+        let free = if allocated { 0 } else { 1 };
+        for leaf in LEAVES + begin..LEAVES + end {
+            if self.longest[leaf] != free {
+                if allocated { self.used += 1; } else { self.used -= 1; }
+            }
+            self.prefix[leaf] = free;
+            self.suffix[leaf] = free;
+            self.longest[leaf] = free;
+        }
+        let (mut lo, mut hi) = ((LEAVES + begin) / 2, (LEAVES + end - 1) / 2);
+        while lo > 0 {
+            for node in lo..=hi { self.update(node); }
+            lo /= 2;
+            hi /= 2;
+        }
+    }
+
+    // Lowest i >= from such that the n bits at i are free, within the range
+    // [lo, hi) of node. carry is the number of free bits, at or after from,
+    // right before lo.
+    fn search(&self, node: usize, lo: usize, hi: usize, from: usize,
+              n: usize, carry: &mut usize) -> Option<usize> {
+        if hi <= from { return None; }
+        if lo >= from {
+            let len = hi - lo;
+            if *carry + self.prefix[node] as usize >= n { return Some(lo - *carry); }
+            if (self.longest[node] as usize) < n {
+                *carry = if self.prefix[node] as usize == len { *carry + len } else { self.suffix[node] as usize };
+                return None;
+            }
+        }
+        let mid = (lo + hi) / 2;
+        self.search(2 * node, lo, mid, from, n, carry)
+            .or_else(|| self.search(2 * node + 1, mid, hi, from, n, carry))
+    }
+
+    // Lowest bit at or after from, within the range [lo, hi) of node, that
+    // is allocated if allocated is true and free otherwise.
+    fn first_of(&self, node: usize, lo: usize, hi: usize, from: usize, allocated: bool) -> Option<usize> {
+        let len = hi - lo;
+        let none = if allocated { self.prefix[node] as usize == len } else { self.longest[node] == 0 };
+        if hi <= from || none { return None; }
+        if len == 1 { return Some(lo); }
+        let mid = (lo + hi) / 2;
+        self.first_of(2 * node, lo, mid, from, allocated)
+            .or_else(|| self.first_of(2 * node + 1, mid, hi, from, allocated))
+    }
+
+    // Lowest i >= from such that the n bits at i are free.
+    fn first_fit_from(&self, from: usize, n: usize) -> Option<usize> {
+        self.search(1, 0, LEAVES, from, n, &mut 0)
+    }
+}
+
+impl BitAllocatorInit for SegTreeBitMap {
+    fn new(size: usize) -> Self { SegTreeBitMap::new(size) }
+}
+
+impl BitAllocator for SegTreeBitMap {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.size);
+        let begin = self.first_fit_from(0, n)?;
+        self.mark(begin, n);
+        Some(begin)
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.size);
+        assert!(alignment >= 1);
+        let mut from = 0;
+        loop {
+            // A free run of at least n bits begins at run, and ends at end.
+            let run = self.first_fit_from(from, n)?;
+            let end = self.find_next(run, true);
+            let begin = policy::align_up(run, alignment, 0);
+            if begin + n <= end {
+                self.mark(begin, n);
+                return Some(begin);
+            }
+            from = end;
+        }
+    }
+
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.set_range(begin, begin + n, false);
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.set_range(begin, begin + n, true);
+    }
+
+    fn size(&self) -> usize { self.size }
+
+    fn capacity(&self) -> usize { MAX_BITS }
+
+    fn is_allocated(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.longest[LEAVES + index] == 0
+    }
+
+    fn free_count(&self) -> usize { self.size - self.used }
+
+    fn find_next(&self, from: usize, allocated: bool) -> usize {
+        if from >= self.size { return self.size; }
+        self.first_of(1, 0, LEAVES, from, allocated).map_or(self.size, |i| i.min(self.size))
+    }
+}
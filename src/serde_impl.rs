@@ -0,0 +1,150 @@
+//! Serde support, enabled by the `serde` feature.
+//!
+//! Both LinearBitMaps are represented as a struct holding their size and
+//! their state packed into words as by `bitalloc::LinearBitMap::as_words`,
+//! so each can be read back as the other. The policy is not saved; a
+//! deserialized bitmap starts with a default one.
+
+use core::fmt;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeStruct, Serializer};
+use crate::{BitAllocator, MAX_BITS};
+
+const MAX_WORDS: usize = MAX_BITS / 64;
+const FIELDS: &[&str] = &["size", "words"];
+
+pub(crate) fn serialize<S: Serializer>(size: usize, words: &[u64], s: S) -> Result<S::Ok, S::Error> {
+    let mut st = s.serialize_struct("LinearBitMap", 2)?;
+    st.serialize_field("size", &size)?;
+    st.serialize_field("words", words)?;
+    st.end()
+}
+
+/// Deserialize the bitmap that serialize wrote into new(size), if its size
+/// is within the capacity of new(0).
+pub(crate) fn deserialize<'de, T, D, F>(d: D, new: F) -> Result<T, D::Error>
+    where T: BitAllocator, D: Deserializer<'de>, F: Fn(usize) -> T {
+    let packed = d.deserialize_struct("LinearBitMap", FIELDS, PackedVisitor)?;
+    if packed.size > new(0).capacity() {
+        let size = de::Unexpected::Unsigned(packed.size as u64);
+        return Err(de::Error::invalid_value(size, &"a size within the capacity"));
+    }
+    let mut bm = new(packed.size);
+    for i in 0..packed.size {
+        if packed.words.0[i / 64] >> (i % 64) & 1 == 1 { bm.mark(i, 1); }
+    }
+    Ok(bm)
+}
+
+struct Packed {
+    size: usize,
+    words: Words,
+}
+
+impl Packed {
+    fn new<E: de::Error>(size: usize, words: Words) -> Result<Self, E> {
+        if size > MAX_BITS {
+            return Err(E::invalid_value(de::Unexpected::Unsigned(size as u64), &"a supported size"));
+        }
+        if words.1 != size.div_ceil(64) {
+            return Err(E::invalid_length(words.1, &"one word per 64 bits"));
+        }
+        Ok(Packed { size, words })
+    }
+}
+
+/// Up to MAX_WORDS words, and how many there are.
+struct Words([u64; MAX_WORDS], usize);
+
+impl<'de> Deserialize<'de> for Words {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_seq(WordsVisitor)
+    }
+}
+
+struct WordsVisitor;
+
+impl<'de> Visitor<'de> for WordsVisitor {
+    type Value = Words;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at most {} words", MAX_WORDS)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Words, A::Error> {
+        let mut words = Words([0; MAX_WORDS], 0);
+        while let Some(w) = seq.next_element()? {
+            if words.1 == MAX_WORDS {
+                return Err(de::Error::invalid_length(words.1 + 1, &self));
+            }
+            words.0[words.1] = w;
+            words.1 += 1;
+        }
+        Ok(words)
+    }
+}
+
+struct PackedVisitor;
+
+impl<'de> Visitor<'de> for PackedVisitor {
+    type Value = Packed;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("struct LinearBitMap")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Packed, A::Error> {
+        let size = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let words = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Packed::new(size, words)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Packed, A::Error> {
+        let (mut size, mut words) = (None, None);
+        while let Some(key) = map.next_key()? {
+            match key {
+                Field::Size if size.is_some() => return Err(de::Error::duplicate_field("size")),
+                Field::Words if words.is_some() => return Err(de::Error::duplicate_field("words")),
+                Field::Size => size = Some(map.next_value()?),
+                Field::Words => words = Some(map.next_value()?),
+            }
+        }
+        let size = size.ok_or_else(|| de::Error::missing_field("size"))?;
+        let words = words.ok_or_else(|| de::Error::missing_field("words"))?;
+        Packed::new(size, words)
+    }
+}
+
+enum Field { Size, Words }
+
+impl<'de> Deserialize<'de> for Field {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_identifier(FieldVisitor)
+    }
+}
+
+struct FieldVisitor;
+
+impl<'de> Visitor<'de> for FieldVisitor {
+    type Value = Field;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("`size` or `words`")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Field, E> {
+        match v {
+            0 => Ok(Field::Size),
+            1 => Ok(Field::Words),
+            _ => Err(E::invalid_value(de::Unexpected::Unsigned(v), &self)),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Field, E> {
+        match v {
+            "size" => Ok(Field::Size),
+            "words" => Ok(Field::Words),
+            _ => Err(E::unknown_field(v, FIELDS)),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! An allocator split into per-CPU shards with a lock each.
+
+use spin::{Mutex, MutexGuard};
+use crate::{BitAllocator, BitAllocatorInit};
+
+/// An index space partitioned into N consecutive shards, each its own
+/// allocator behind its own spinlock, shared through `&self`.
+///
+/// A request names the CPU it comes from and is served by the shard of that
+/// CPU, `cpu % N`, so CPUs allocating at the same time only contend when one
+/// of them has run dry: the request then steals from the next shards in
+/// turn, wrapping around. Frees go to the shard holding the bits, whichever
+/// CPU they come from.
+pub struct ShardedBitMap<A, const N: usize> {
+    shards: [Mutex<A>; N],
+    bases: [usize; N], // Index of the first bit of each shard.
+    size: usize,
+}
+
+impl<A: BitAllocatorInit, const N: usize> ShardedBitMap<A, N> {
+    /// Split size bits, all unallocated, into N shards of equal size but
+    /// for the last one, which may be smaller.
+    ///
+    /// Panics if N is 0, or if a shard would exceed the capacity of A.
+    pub fn new(size: usize) -> Self {
+        assert!(N > 0);
+        let len = size.div_ceil(N);
+        Self::from_shards(core::array::from_fn(|i| A::new(len.min(size.saturating_sub(len * i)))))
+    }
+}
+
+impl<A: BitAllocator, const N: usize> ShardedBitMap<A, N> {
+    /// Make shards, in order, the shards of a single index space.
+    pub fn from_shards(shards: [A; N]) -> Self {
+        let mut bases = [0; N];
+        let mut size = 0;
+        for (base, shard) in bases.iter_mut().zip(&shards) {
+            *base = size;
+            size += shard.size();
+        }
+        ShardedBitMap { shards: shards.map(Mutex::new), bases, size }
+    }
+
+    /// Total number of bits.
+    pub fn size(&self) -> usize { self.size }
+
+    /// The shard holding index, if it is smaller than the size.
+    pub fn shard_of(&self, index: usize) -> Option<usize> {
+        if index >= self.size { return None; }
+        Some(self.bases.partition_point(|&base| base <= index) - 1)
+    }
+
+    /// Lock shard i, whose index 0 is its base in the whole index space.
+    ///
+    /// Panics if i is not smaller than N.
+    pub fn lock_shard(&self, i: usize) -> (usize, MutexGuard<'_, A>) {
+        (self.bases[i], self.shards[i].lock())
+    }
+
+    /// Total number of free bits, shard by shard, so not a snapshot while
+    /// other CPUs allocate.
+    pub fn free_count(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().free_count()).sum()
+    }
+
+    /// Allocate n consecutive bits for cpu, from its own shard if it has
+    /// room, otherwise from the first of the following shards that does.
+    pub fn alloc(&self, cpu: usize, n: usize) -> Option<usize> {
+        self.alloc_aligned(cpu, n, 1)
+    }
+
+    /// Like alloc, but the allocated index is a multiple of alignment.
+    pub fn alloc_aligned(&self, cpu: usize, n: usize, alignment: usize) -> Option<usize> {
+        assert!(alignment >= 1);
+        (0..N).map(|k| (cpu + k) % N).find_map(|i| {
+            let base = self.bases[i];
+            let mut shard = self.shards[i].lock();
+            if n > shard.size() { return None; }
+            let offset = (alignment - base % alignment) % alignment;
+            shard.alloc_aligned_offset(n, alignment, offset).map(|begin| base + begin)
+        })
+    }
+
+    /// Free the n bits at begin.
+    ///
+    /// Panics if they do not all lie in one shard.
+    pub fn dealloc(&self, begin: usize, n: usize) {
+        let i = self.shard_of(begin).expect("bits past the end");
+        let mut shard = self.shards[i].lock();
+        assert!(begin + n <= self.bases[i] + shard.size());
+        shard.dealloc(begin - self.bases[i], n);
+    }
+
+    /// Whether the index-th bit is allocated.
+    ///
+    /// Panics if index is not smaller than the size.
+    pub fn is_allocated(&self, index: usize) -> bool {
+        let i = self.shard_of(index).expect("index past the end");
+        self.shards[i].lock().is_allocated(index - self.bases[i])
+    }
+}
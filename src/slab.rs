@@ -0,0 +1,99 @@
+//! A typed object pool over caller-provided storage.
+
+use core::mem::MaybeUninit;
+use crate::{BitAllocator, MAX_BITS};
+use crate::bitalloc::LinearBitMap;
+
+/// An allocator of fixed-size slots for values of type T, such as thread
+/// control blocks, living in a region provided by the caller, so no heap is
+/// needed.
+///
+/// Slot i of the region holds a value exactly when bit i of a
+/// [`LinearBitMap`] is allocated. Values are moved in by alloc and out by
+/// dealloc, and those still in the pool are dropped along with it.
+pub struct SlabPool<'a, T> {
+    slots: &'a mut [MaybeUninit<T>],
+    map: LinearBitMap,
+}
+
+impl<'a, T> SlabPool<'a, T> {
+    /// Manage the slots of storage, all empty.
+    ///
+    /// Panics if storage has more than MAX_BITS slots.
+    pub fn new(storage: &'a mut [MaybeUninit<T>]) -> Self {
+        assert!(storage.len() <= MAX_BITS);
+        let map = LinearBitMap::new(storage.len());
+        SlabPool { slots: storage, map }
+    }
+
+    /// Number of slots.
+    pub fn capacity(&self) -> usize { self.slots.len() }
+
+    /// Number of slots holding a value.
+    pub fn len(&self) -> usize { self.map.used_count() }
+
+    /// Whether no slot holds a value.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// The index space of the slots.
+    pub fn map(&self) -> &LinearBitMap { &self.map }
+
+    /// Move value into the lowest free slot, returning its index, or give
+    /// the value back if every slot is taken.
+    pub fn alloc(&mut self, value: T) -> Result<usize, T> {
+        if self.slots.is_empty() { return Err(value); }
+        match self.map.alloc(1) {
+            Some(index) => {
+                self.slots[index].write(value);
+                Ok(index)
+            }
+            None => Err(value),
+        }
+    }
+
+    /// Move the value out of the index-th slot, freeing it, or return `None`
+    /// if the slot is empty or out of bounds.
+    pub fn dealloc(&mut self, index: usize) -> Option<T> {
+        if !self.contains(index) { return None; }
+        self.map.dealloc(index, 1);
+        // The bit was allocated, so the slot had been written and not yet
+        // read out; freeing the bit makes this the only read.
+        Some(unsafe { self.slots[index].assume_init_read() })
+    }
+
+    /// Whether the index-th slot holds a value.
+    pub fn contains(&self, index: usize) -> bool {
+        index < self.slots.len() && self.map.is_allocated(index)
+    }
+
+    /// The value in the index-th slot, if any.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if !self.contains(index) { return None; }
+        // An allocated bit means the slot holds a value.
+        Some(unsafe { self.slots[index].assume_init_ref() })
+    }
+
+    /// The value in the index-th slot, if any, mutably.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if !self.contains(index) { return None; }
+        // An allocated bit means the slot holds a value.
+        Some(unsafe { self.slots[index].assume_init_mut() })
+    }
+
+    /// Iterate over the occupied slots, in increasing order, as
+    /// `(index, value)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> + '_ {
+        (0..self.slots.len()).filter_map(move |i| self.get(i).map(|v| (i, v)))
+    }
+}
+
+impl<'a, T> Drop for SlabPool<'a, T> {
+    fn drop(&mut self) {
+        for i in 0..self.slots.len() {
+            if self.map.is_allocated(i) {
+                // Occupied slots hold a value, dropped exactly once here.
+                unsafe { self.slots[i].assume_init_drop() };
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Allocators working in place on bitmaps stored elsewhere.
+
+use core::cmp::min;
+use core::mem::size_of;
+use crate::{BitAllocator, BitOrder, Endian, Layout, policy, scan};
+
+/// An allocator whose bits live in a caller-provided buffer of bytes, laid
+/// out as described by a [`Layout`], or of words.
+///
+/// Every change is made directly to the buffer, so a bitmap read from disk
+/// can be allocated from and written back as is. Placement is first-fit.
+///
+/// Made with [`with_summary`](Self::with_summary), it also keeps one bit
+/// for each group of GROUP_WORDS words of the buffer, set if the group has a
+/// free bit, and searches for free bits skip the groups without one.
+pub struct SliceBitMap<'a> {
+    bytes: &'a mut [u8],
+    size: usize,
+    layout: Layout,
+    free: usize,
+    groups: &'a mut [u64],
+}
+
+/// Words of the buffer summarized by one bit of the group summary.
+pub const GROUP_WORDS: usize = 64;
+
+impl<'a> SliceBitMap<'a> {
+    /// Manage the first size bits stored in bytes, keeping their current
+    /// state. Padding bits are never touched.
+    ///
+    /// Panics if bytes is shorter than `layout.byte_len(size)`.
+    pub fn new(bytes: &'a mut [u8], size: usize, layout: Layout) -> Self {
+        assert!(bytes.len() >= layout.byte_len(size));
+        let mut bm = SliceBitMap { bytes, size, layout, free: 0, groups: &mut [] };
+        bm.free = (0..size).filter(|&i| !bm.is_allocated(i)).count();
+        bm
+    }
+
+    /// Like new, keeping the summary of groups with a free bit in groups,
+    /// whatever it holds now.
+    ///
+    /// Panics if groups is shorter than `summary_len(size, layout)`.
+    pub fn with_summary(bytes: &'a mut [u8], size: usize, layout: Layout, groups: &'a mut [u64]) -> Self {
+        assert!(groups.len() >= Self::summary_len(size, layout));
+        for w in &mut *groups { *w = 0; }
+        let mut bm = Self::new(bytes, size, layout);
+        bm.groups = groups;
+        for g in 0..size.div_ceil(bm.group_bits()) { bm.sync_group(g); }
+        bm
+    }
+
+    /// Number of words the summary of size bits in layout takes.
+    pub fn summary_len(size: usize, layout: Layout) -> usize {
+        size.div_ceil(GROUP_WORDS * 8 * layout.word_bytes).div_ceil(64)
+    }
+
+    /// Manage the first size bits of words, in the layout of
+    /// `bitalloc::LinearBitMap::as_words`: bit i is bit `i % 64` of
+    /// `words[i / 64]`.
+    ///
+    /// Panics if words holds fewer than size bits.
+    pub fn from_words(words: &'a mut [u64], size: usize) -> Self {
+        let len = core::mem::size_of_val(words);
+        // Any bytes are a valid u64 and u8 has no alignment requirement, so
+        // the words can be accessed as bytes in native order.
+        let bytes = unsafe { core::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, len) };
+        Self::new(bytes, size, Layout::new(size_of::<u64>(), Endian::NATIVE, BitOrder::LsbFirst))
+    }
+
+    /// Manage the block bitmap of an ext2/ext3/ext4 block group with the
+    /// given number of blocks: bytes in LSB-first bit order, a set bit
+    /// meaning the block is in use.
+    ///
+    /// Bits for blocks past the end of the group, which ext4 keeps set, are
+    /// left alone. The same layout is used by inode bitmaps.
+    pub fn ext2(bytes: &'a mut [u8], blocks: usize) -> Self {
+        Self::new(bytes, blocks, Layout::EXT2)
+    }
+
+    /// Manage the data of the NTFS `$Bitmap` file of a volume with the
+    /// given number of clusters.
+    ///
+    /// The bits past the last cluster, which pad the data to a multiple of
+    /// 8 bytes, are left alone.
+    pub fn ntfs(bytes: &'a mut [u8], clusters: usize) -> Self {
+        Self::new(bytes, clusters, Layout::NTFS)
+    }
+
+    /// The layout of the buffer.
+    pub fn layout(&self) -> Layout { self.layout }
+
+    /// Give the buffer back.
+    pub fn into_bytes(self) -> &'a mut [u8] { self.bytes }
+
+    fn set_range(&mut self, begin: usize, n: usize, b: bool) {
+        assert!(begin + n <= self.size);
+        for i in begin..begin + n {
+            let (byte, mask) = self.layout.locate(i);
+            if (self.bytes[byte] & mask != 0) != b {
+                self.bytes[byte] ^= mask;
+                if b { self.free -= 1; } else { self.free += 1; }
+            }
+        }
+        if self.groups.is_empty() || n == 0 { return; }
+        for g in begin / self.group_bits()..=(begin + n - 1) / self.group_bits() {
+            if b { self.sync_group(g); } else { self.groups[g / 64] |= 1 << (g % 64); }
+        }
+    }
+
+    fn group_bits(&self) -> usize { GROUP_WORDS * 8 * self.layout.word_bytes }
+
+    // Whether group g may have a free bit: always, without a summary.
+    fn group_free(&self, g: usize) -> bool {
+        self.groups.is_empty() || (self.groups[g / 64] >> (g % 64)) & 1 != 0
+    }
+
+    // Recompute the summary bit of group g.
+    fn sync_group(&mut self, g: usize) {
+        let word_bits = 8 * self.layout.word_bytes;
+        let begin = g * self.group_bits();
+        let end = min(begin + self.group_bits(), self.size);
+        // The bytes of whole words hold only bits of the group; the bits of
+        // a partial last word are checked one by one.
+        let whole = min(end, self.size / word_bits * word_bits);
+        let free = scan::prefix_len(&self.bytes[begin / 8..whole / 8], 0xff) < (whole - begin) / 8
+            || (whole..end).any(|i| !self.is_allocated(i));
+        if free { self.groups[g / 64] |= 1 << (g % 64); } else { self.groups[g / 64] &= !(1 << (g % 64)); }
+    }
+}
+
+impl<'a> BitAllocator for SliceBitMap<'a> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        self.alloc_aligned(n, 1)
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.size);
+        assert!(alignment >= 1);
+        let begin = policy::first_fit(self, 0, self.size, n, alignment, 0)?;
+        self.mark(begin, n);
+        Some(begin)
+    }
+
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.set_range(begin, n, false);
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.set_range(begin, n, true);
+    }
+
+    fn size(&self) -> usize { self.size }
+
+    fn free_count(&self) -> usize { self.free }
+
+    fn is_allocated(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        let (byte, mask) = self.layout.locate(index);
+        self.bytes[byte] & mask != 0
+    }
+
+    // Whole words of the buffer in the wrong state are skipped at once: each
+    // holds the bits of a range of indexes, whatever the layout. Looking for
+    // a free bit, so are the groups the summary has as full.
+    fn find_next(&self, from: usize, allocated: bool) -> usize {
+        let word_bits = 8 * self.layout.word_bytes;
+        let whole_words = self.size / word_bits;
+        let skip = if allocated { 0x00 } else { 0xff };
+        let mut i = from;
+        while i < self.size {
+            let group_end = (i / self.group_bits() + 1) * self.group_bits();
+            if !allocated && !self.group_free(i / self.group_bits()) {
+                i = group_end;
+                continue;
+            }
+            if i % word_bits == 0 && i / word_bits < whole_words {
+                let last = min(whole_words, group_end / word_bits);
+                let bytes = &self.bytes[i / 8..last * self.layout.word_bytes];
+                let words = scan::prefix_len(bytes, skip) / self.layout.word_bytes;
+                i += words * word_bits;
+                if i >= self.size || i == group_end { continue; }
+            }
+            if self.is_allocated(i) == allocated { return i; }
+            i += 1;
+        }
+        self.size
+    }
+}
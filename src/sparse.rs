@@ -0,0 +1,248 @@
+//! A sparse allocator for huge, mostly nonexistent index spaces.
+
+use core::cmp::{max, min};
+use crate::{AllocError, BitAllocator, policy};
+
+/// Bits per leaf chunk of a [`SparseBitMap`].
+pub const CHUNK_BITS: usize = 64 * 64;
+
+/// Children per interior node of a [`SparseBitMap`].
+const FANOUT: usize = 64;
+const FANOUT_SHIFT: u32 = 6;
+
+/// A node of a [`SparseBitMap`]: either a leaf holding CHUNK_BITS bits, or
+/// an interior node holding the pool indices, plus one, of its children.
+pub type Chunk = [u64; 64];
+
+/// An allocator over an index space of up to `usize::MAX` bits, such as the
+/// frames of a 52-bit physical address space, that only stores the parts of
+/// it that exist.
+///
+/// The bits are grouped into chunks of CHUNK_BITS bits under a radix tree
+/// with 64 children per node. Nodes are taken from a pool provided by the
+/// caller when a range is first made available, and are never given back;
+/// a missing subtree stands for bits that are all allocated. A new map
+/// therefore has no free bit at all, and memory is added to it with
+/// add_range, so holes in the address space cost nothing.
+///
+/// Searches skip missing subtrees, so their cost depends on how much of the
+/// space exists rather than on its size. Placement is first-fit.
+pub struct SparseBitMap<'a> {
+    pool: &'a mut [Chunk],
+    taken: usize, // Nodes of the pool in use, from its beginning.
+    root: Option<usize>,
+    depth: u32,   // Levels of interior nodes above the leaves.
+    size: usize,
+    free: usize,
+}
+
+impl<'a> SparseBitMap<'a> {
+    /// Manage size bits, all allocated, with the nodes of pool.
+    pub fn new(size: usize, pool: &'a mut [Chunk]) -> Self {
+        let (mut depth, mut nodes) = (0, size.div_ceil(CHUNK_BITS));
+        while nodes > 1 {
+            nodes = nodes.div_ceil(FANOUT);
+            depth += 1;
+        }
+        SparseBitMap { pool, taken: 0, root: None, depth, size, free: 0 }
+    }
+
+    /// Number of nodes taken from the pool so far.
+    pub fn chunks_used(&self) -> usize { self.taken }
+
+    /// Free the bits [begin, begin+n), e.g. for memory found in a memory map,
+    /// taking the nodes they need from the pool.
+    ///
+    /// Fails with `AllocError::OutOfSpace`, without changing the state of
+    /// any bit, if the pool runs out; nodes taken before that stay in use.
+    /// Fails with `AllocError::OutOfBounds` if the range reaches past the end
+    /// of the bitmap.
+    pub fn add_range(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        let end = begin.checked_add(n).filter(|&end| end <= self.size).ok_or(AllocError::OutOfBounds)?;
+        let mut i = begin;
+        while i < end {
+            self.leaf_or_insert(i)?;
+            i = (i / CHUNK_BITS + 1) * CHUNK_BITS;
+        }
+        for i in begin..end {
+            self.set(i, false);
+        }
+        Ok(())
+    }
+
+    // Number of bits below a node at the given level, leaves being level 0,
+    // saturated to usize::MAX.
+    fn span(level: u32) -> usize {
+        let span = (CHUNK_BITS as u128) << (FANOUT_SHIFT * level);
+        min(span, usize::MAX as u128) as usize
+    }
+
+    // Which child of a node at the given level leads to bit i.
+    fn digit(i: usize, level: u32) -> usize {
+        ((i / CHUNK_BITS) >> (FANOUT_SHIFT * (level - 1))) % FANOUT
+    }
+
+    fn child(&self, node: usize, digit: usize) -> Option<usize> {
+        match self.pool[node][digit] {
+            0 => None,
+            c => Some(c as usize - 1),
+        }
+    }
+
+    // Pool index of the leaf holding bit i, if any.
+    fn leaf(&self, i: usize) -> Option<usize> {
+        let mut node = self.root?;
+        for level in (1..=self.depth).rev() {
+            node = self.child(node, Self::digit(i, level))?;
+        }
+        Some(node)
+    }
+
+    // Pool index of the leaf holding bit i, creating it and the nodes above
+    // it if needed.
+    fn leaf_or_insert(&mut self, i: usize) -> Result<usize, AllocError> {
+        let mut node = match self.root {
+            Some(root) => root,
+            None => {
+                let root = self.take_node(self.depth == 0)?;
+                self.root = Some(root);
+                root
+            }
+        };
+        for level in (1..=self.depth).rev() {
+            let digit = Self::digit(i, level);
+            node = match self.child(node, digit) {
+                Some(child) => child,
+                None => {
+                    let child = self.take_node(level == 1)?;
+                    self.pool[node][digit] = child as u64 + 1;
+                    child
+                }
+            };
+        }
+        Ok(node)
+    }
+
+    // Take a node from the pool: a leaf with all bits allocated, or an
+    // interior node without children.
+    fn take_node(&mut self, leaf: bool) -> Result<usize, AllocError> {
+        let node = self.taken;
+        let chunk = self.pool.get_mut(node).ok_or(AllocError::OutOfSpace)?;
+        *chunk = [if leaf { !0 } else { 0 }; 64];
+        self.taken += 1;
+        Ok(node)
+    }
+
+    // Set bit i, whose leaf must exist, keeping the free counter in sync.
+    fn set(&mut self, i: usize, allocated: bool) {
+        let leaf = self.leaf(i).expect("bit has no chunk");
+        let (word, mask) = (i % CHUNK_BITS / 64, 1u64 << (i % 64));
+        let w = &mut self.pool[leaf][word];
+        if (*w & mask != 0) != allocated {
+            *w ^= mask;
+            if allocated { self.free -= 1; } else { self.free += 1; }
+        }
+    }
+
+    // First bit at or after from, within the subtree of node covering
+    // [base, base + span(level)), whose state is allocated.
+    fn next_in(&self, node: Option<usize>, level: u32, base: usize,
+               from: usize, allocated: bool) -> Option<usize> {
+        let end = base.saturating_add(Self::span(level));
+        let from = max(from, base);
+        if from >= end { return None; }
+        let node = match node {
+            None => return if allocated { Some(from) } else { None },
+            Some(node) => node,
+        };
+        if level == 0 {
+            return (from - base..CHUNK_BITS)
+                .find(|&b| (self.pool[node][b / 64] >> (b % 64) & 1 == 1) == allocated)
+                .map(|b| base + b);
+        }
+        let child_span = Self::span(level - 1);
+        (Self::digit(from, level)..FANOUT).find_map(|digit| {
+            let child_base = base.saturating_add(digit.saturating_mul(child_span));
+            self.next_in(self.child(node, digit), level - 1, child_base, from, allocated)
+        })
+    }
+
+    // Last bit before before, within the subtree of node covering
+    // [base, base + span(level)), whose state is allocated.
+    fn prev_in(&self, node: Option<usize>, level: u32, base: usize,
+               before: usize, allocated: bool) -> Option<usize> {
+        let before = min(before, base.saturating_add(Self::span(level)));
+        if before <= base { return None; }
+        let node = match node {
+            None => return if allocated { Some(before - 1) } else { None },
+            Some(node) => node,
+        };
+        if level == 0 {
+            return (0..before - base).rev()
+                .find(|&b| (self.pool[node][b / 64] >> (b % 64) & 1 == 1) == allocated)
+                .map(|b| base + b);
+        }
+        let child_span = Self::span(level - 1);
+        (0..=Self::digit(before - 1, level)).rev().find_map(|digit| {
+            let child_base = base.saturating_add(digit.saturating_mul(child_span));
+            self.prev_in(self.child(node, digit), level - 1, child_base, before, allocated)
+        })
+    }
+}
+
+impl<'a> BitAllocator for SparseBitMap<'a> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        self.alloc_aligned(n, 1)
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.size);
+        assert!(alignment >= 1);
+        let begin = policy::first_fit(self, 0, self.size, n, alignment, 0)?;
+        self.mark(begin, n);
+        Some(begin)
+    }
+
+    /// Free the bits, like add_range.
+    ///
+    /// Panics if the pool runs out.
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.add_range(begin, n).expect("chunk pool exhausted");
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        assert!(begin + n <= self.size);
+        let mut i = begin;
+        while i < begin + n {
+            if let Some(free) = self.next_in(self.root, self.depth, 0, i, false) {
+                if free >= begin + n { break; }
+                self.set(free, true);
+                i = free + 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn size(&self) -> usize { self.size }
+
+    fn is_allocated(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        match self.leaf(index) {
+            None => true,
+            Some(leaf) => self.pool[leaf][index % CHUNK_BITS / 64] >> (index % 64) & 1 == 1,
+        }
+    }
+
+    fn free_count(&self) -> usize { self.free }
+
+    fn find_next(&self, from: usize, allocated: bool) -> usize {
+        if from >= self.size { return self.size; }
+        let next = self.next_in(self.root, self.depth, 0, from, allocated);
+        min(next.unwrap_or(self.size), self.size)
+    }
+
+    fn find_prev(&self, before: usize, allocated: bool) -> Option<usize> {
+        self.prev_in(self.root, self.depth, 0, min(before, self.size), allocated)
+    }
+}
@@ -0,0 +1,169 @@
+//! An allocator tracking one of four states per entry in two bits.
+
+use crate::{AllocError, BitAllocator, BitAllocatorInit, MAX_BITS, policy};
+
+const STATE_BITS: usize = 2;
+const PER_WORD: usize = 64 / STATE_BITS;
+const WORD_CNT: usize = MAX_BITS / PER_WORD;
+
+/// The state of an entry of a [`StateBitMap`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlotState {
+    /// Available for allocation.
+    Free = 0,
+    /// Handed out by alloc or mark.
+    Allocated = 1,
+    /// Set aside, e.g. for firmware, and not handed out until released.
+    Reserved = 2,
+    /// Faulty; never handed out again.
+    Bad = 3,
+}
+
+impl SlotState {
+    fn from_bits(bits: u64) -> Self {
+        match bits {
+            0 => SlotState::Free,
+            1 => SlotState::Allocated,
+            2 => SlotState::Reserved,
+            _ => SlotState::Bad,
+        }
+    }
+
+    /// Whether an entry in this state may be moved to the state to. Free
+    /// entries can be allocated or reserved, allocated and reserved ones
+    /// freed, and any entry can go bad; bad entries stay bad.
+    pub fn can_become(self, to: SlotState) -> bool {
+        use SlotState::*;
+        matches!((self, to), (_, Bad) | (Free, Allocated) | (Free, Reserved)
+                             | (Allocated, Free) | (Reserved, Free))
+    }
+}
+
+/// An allocator whose entries are each free, allocated, reserved or bad, so
+/// that a single structure replaces a bitmap plus parallel reserved and bad
+/// bitmaps that could disagree.
+///
+/// Through [`BitAllocator`], every entry that is not free counts as
+/// allocated and bad entries are holes. Placement is first-fit.
+#[derive(Clone)]
+pub struct StateBitMap {
+    size: usize,
+    words: [u64; WORD_CNT],
+    counts: [usize; 4], // Number of entries in each state.
+}
+
+impl StateBitMap {
+    /// Create a bitmap with size entries, all free.
+    pub const fn new(size: usize) -> Self {
+        assert!(size <= MAX_BITS);
+        StateBitMap { size, words: [0; WORD_CNT], counts: [size, 0, 0, 0] }
+    }
+
+    /// State of the index-th entry.
+    ///
+    /// Panics if index is not smaller than the size.
+    pub fn state(&self, index: usize) -> SlotState {
+        assert!(index < self.size);
+        let shift = index % PER_WORD * STATE_BITS;
+        SlotState::from_bits((self.words[index / PER_WORD] >> shift) & 0b11)
+    }
+
+    /// Number of entries in the given state.
+    pub fn count(&self, state: SlotState) -> usize { self.counts[state as usize] }
+
+    /// Move the entries [begin, begin+n) to the state to.
+    ///
+    /// Fails, without changing anything, if the range is empty or out of
+    /// bounds, or if `can_become` forbids the move for any entry: with
+    /// `AllocError::Hole` for a bad entry, `AllocError::DoubleFree` for
+    /// freeing a free one and `AllocError::Occupied` otherwise.
+    pub fn transition(&mut self, begin: usize, n: usize, to: SlotState) -> Result<(), AllocError> {
+        crate::check_range(self.size, begin, n)?;
+        for i in begin..begin + n {
+            let from = self.state(i);
+            if !from.can_become(to) {
+                return Err(match from {
+                    SlotState::Bad => AllocError::Hole,
+                    SlotState::Free => AllocError::DoubleFree,
+                    _ => AllocError::Occupied,
+                });
+            }
+        }
+        for i in begin..begin + n {
+            self.set(i, to);
+        }
+        Ok(())
+    }
+
+    /// Set aside the entries [begin, begin+n), which must all be free.
+    pub fn reserve_range(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.transition(begin, n, SlotState::Reserved)
+    }
+
+    /// Retire the entries [begin, begin+n) for good, whatever their state.
+    pub fn mark_bad(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.transition(begin, n, SlotState::Bad)
+    }
+
+    fn set(&mut self, index: usize, state: SlotState) {
+        let old = self.state(index);
+        let (w, shift) = (index / PER_WORD, index % PER_WORD * STATE_BITS);
+        self.words[w] = (self.words[w] & !(0b11 << shift)) | (state as u64) << shift;
+        self.counts[old as usize] -= 1;
+        self.counts[state as usize] += 1;
+    }
+
+    // Move every entry of [begin, begin+n) in state from to the state to.
+    fn replace(&mut self, begin: usize, n: usize, from: SlotState, to: SlotState) {
+        assert!(begin + n <= self.size);
+        for i in begin..begin + n {
+            if self.state(i) == from { self.set(i, to); }
+        }
+    }
+}
+
+impl BitAllocatorInit for StateBitMap {
+    fn new(size: usize) -> Self { StateBitMap::new(size) }
+}
+
+impl BitAllocator for StateBitMap {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        self.alloc_aligned(n, 1)
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.size);
+        assert!(alignment >= 1);
+        let begin = policy::first_fit(self, 0, self.size, n, alignment, 0)?;
+        self.mark(begin, n);
+        Some(begin)
+    }
+
+    /// Free the allocated and reserved entries of the range; bad ones are
+    /// left alone.
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.replace(begin, n, SlotState::Allocated, SlotState::Free);
+        self.replace(begin, n, SlotState::Reserved, SlotState::Free);
+    }
+
+    /// Allocate the free entries of the range; the others are left alone.
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.replace(begin, n, SlotState::Free, SlotState::Allocated);
+    }
+
+    fn size(&self) -> usize { self.size }
+
+    fn capacity(&self) -> usize { MAX_BITS }
+
+    fn is_allocated(&self, index: usize) -> bool { self.state(index) != SlotState::Free }
+
+    fn free_count(&self) -> usize { self.count(SlotState::Free) }
+
+    fn is_hole(&self, index: usize) -> bool { self.is_bad(index) }
+
+    fn hole_count(&self) -> usize { self.bad_count() }
+
+    fn is_bad(&self, index: usize) -> bool { self.state(index) == SlotState::Bad }
+
+    fn bad_count(&self) -> usize { self.count(SlotState::Bad) }
+}
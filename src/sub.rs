@@ -0,0 +1,108 @@
+//! Windows onto part of a larger allocator.
+
+use core::cmp::min;
+use crate::{BitAllocator, policy};
+
+/// A view of the bits `[base, base + len)` of a parent allocator, itself
+/// usable as a [`BitAllocator`] whose index 0 is the parent's index base.
+///
+/// Allocations never leave the window, so handing each subsystem its own
+/// window keeps them from allocating each other's bits. Placement is always
+/// first-fit, regardless of the policy of the parent.
+pub struct SubAllocator<'a, T: BitAllocator + ?Sized> {
+    parent: &'a mut T,
+    base: usize,
+    len: usize,
+    free: usize,
+}
+
+impl<'a, T: BitAllocator + ?Sized> SubAllocator<'a, T> {
+    /// View the len bits of parent starting at base.
+    ///
+    /// Panics if the window reaches past the end of parent.
+    pub fn new(parent: &'a mut T, base: usize, len: usize) -> Self {
+        assert!(base + len <= parent.size());
+        let free = (base..base + len).filter(|&i| !parent.is_allocated(i)).count();
+        SubAllocator { parent, base, len, free }
+    }
+
+    /// Index in the parent of the first bit of the window.
+    pub fn base(&self) -> usize { self.base }
+
+    /// Run f on the parent, and account for the bits it changed in the window.
+    fn update<F: FnOnce(&mut T)>(&mut self, f: F) {
+        let before = self.parent.free_count();
+        f(self.parent);
+        self.free = self.free + self.parent.free_count() - before;
+    }
+}
+
+impl<'a, T: BitAllocator + ?Sized> BitAllocator for SubAllocator<'a, T> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        self.alloc_aligned(n, 1)
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.len);
+        assert!(alignment >= 1);
+        let begin = policy::first_fit(self, 0, self.len, n, alignment, 0)?;
+        self.mark(begin, n);
+        Some(begin)
+    }
+
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        assert!(begin + n <= self.len);
+        let base = self.base;
+        self.update(|parent| parent.dealloc(base + begin, n));
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        assert!(begin + n <= self.len);
+        let base = self.base;
+        self.update(|parent| parent.mark(base + begin, n));
+    }
+
+    fn size(&self) -> usize { self.len }
+
+    fn free_count(&self) -> usize { self.free }
+
+    fn find_next(&self, from: usize, allocated: bool) -> usize {
+        if from >= self.len { return self.len; }
+        min(self.parent.find_next(self.base + from, allocated), self.base + self.len) - self.base
+    }
+
+    fn is_allocated(&self, index: usize) -> bool {
+        assert!(index < self.len);
+        self.parent.is_allocated(self.base + index)
+    }
+
+    fn is_hole(&self, index: usize) -> bool {
+        assert!(index < self.len);
+        self.parent.is_hole(self.base + index)
+    }
+
+    fn hole_count(&self) -> usize {
+        if self.parent.hole_count() == 0 { return 0; }
+        (0..self.len).filter(|&i| self.is_hole(i)).count()
+    }
+
+    fn is_bad(&self, index: usize) -> bool {
+        assert!(index < self.len);
+        self.parent.is_bad(self.base + index)
+    }
+
+    fn bad_count(&self) -> usize {
+        if self.parent.bad_count() == 0 { return 0; }
+        (0..self.len).filter(|&i| self.is_bad(i)).count()
+    }
+
+    fn is_pinned(&self, index: usize) -> bool {
+        assert!(index < self.len);
+        self.parent.is_pinned(self.base + index)
+    }
+
+    fn pinned_count(&self) -> usize {
+        if self.parent.pinned_count() == 0 { return 0; }
+        (0..self.len).filter(|&i| self.is_pinned(i)).count()
+    }
+}
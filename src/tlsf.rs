@@ -0,0 +1,266 @@
+//! A two-level segregated-fit index over a bitmap, for allocation in
+//! bounded time.
+
+use core::cmp::{max, min};
+use crate::{BitAllocator, BitAllocatorInit, MAX_BITS, policy};
+
+const WORDS: usize = MAX_BITS.div_ceil(64);
+
+/// Second-level classes per first-level class, as a power of two.
+const SL_SHIFT: usize = 2;
+const SL: usize = 1 << SL_SHIFT;
+/// First-level classes: the lengths below SL, then one per power of two.
+const FL: usize = (usize::BITS - MAX_BITS.leading_zeros()) as usize - SL_SHIFT + 1;
+
+/// End of a free list.
+const NIL: u16 = u16::MAX;
+
+const _: () = assert!(MAX_BITS < NIL as usize);
+const _: () = assert!(FL <= u32::BITS as usize);
+
+/// A bitmap allocator that finds a free run for any request in constant time,
+/// for real-time kernels that cannot afford a scan.
+///
+/// Free runs are kept in segregated lists, as in TLSF: the first level splits
+/// run lengths by power of two, the second splits each power of two into SL
+/// equal classes, and a bitmap at each level tells which lists are nonempty.
+/// A request takes the head of the first nonempty list whose runs all hold
+/// it, found with a couple of trailing-zero counts. Freeing coalesces with
+/// the neighbouring runs through their boundary tags, at the cost of setting
+/// the bits.
+///
+/// Only when every run that fits shares its class with the request, or for
+/// aligned requests when no run is long enough to hold any alignment, does
+/// allocation fall back to a first-fit scan, so it fails only when nothing
+/// fits. Placement otherwise follows the lists, and differs from first fit.
+#[derive(Clone)]
+pub struct TlsfBitMap {
+    size: usize,
+    used: usize,
+    bits: [u64; WORDS], // Bits past size count as allocated.
+    // Boundary tags, valid at the first and last bit of every free run.
+    len: [u16; MAX_BITS],   // At the first bit: length of the run.
+    start: [u16; MAX_BITS], // At the last bit: first bit of the run.
+    // Links of the free lists, indexed by the first bit of a run.
+    next: [u16; MAX_BITS],
+    prev: [u16; MAX_BITS],
+    heads: [[u16; SL]; FL],
+    fl_map: u32,        // Bit f set iff some list of heads[f] is nonempty.
+    sl_map: [u32; FL], // Bit s of sl_map[f] set iff heads[f][s] is nonempty.
+}
+
+impl TlsfBitMap {
+    /// Create a bitmap with size bits. By default, all bits are unallocated.
+    pub fn new(size: usize) -> Self {
+        assert!(size <= MAX_BITS);
+        let mut bm = TlsfBitMap {
+            size,
+            used: 0,
+            bits: [!0; WORDS],
+            len: [0; MAX_BITS],
+            start: [0; MAX_BITS],
+            next: [NIL; MAX_BITS],
+            prev: [NIL; MAX_BITS],
+            heads: [[NIL; SL]; FL],
+            fl_map: 0,
+            sl_map: [0; FL],
+        };
+        for i in 0..size {
+            bm.bits[i / 64] &= !(1 << (i % 64));
+        }
+        if size > 0 { bm.insert(0, size); }
+        bm
+    }
+
+    // The list holding free runs of len bits.
+    fn class(len: usize) -> (usize, usize) {
+        if len < SL { return (0, len); }
+        let log = (usize::BITS - 1 - len.leading_zeros()) as usize;
+        (log - SL_SHIFT + 1, (len >> (log - SL_SHIFT)) - SL)
+    }
+
+    // The first list, at or after that of n bits, whose runs all hold n bits.
+    fn class_holding(n: usize) -> (usize, usize) {
+        if n < SL { return (0, n); }
+        let log = (usize::BITS - 1 - n.leading_zeros()) as usize;
+        Self::class(n.saturating_add((1 << (log - SL_SHIFT)) - 1))
+    }
+
+    // The first nonempty list at or after (f, s).
+    fn nonempty_from(&self, f: usize, s: usize) -> Option<(usize, usize)> {
+        if f >= FL { return None; }
+        let sl = self.sl_map[f] & (!0 << s);
+        if sl != 0 { return Some((f, sl.trailing_zeros() as usize)); }
+        let fl = self.fl_map & (!0 << (f + 1));
+        if fl == 0 { return None; }
+        let f = fl.trailing_zeros() as usize;
+        Some((f, self.sl_map[f].trailing_zeros() as usize))
+    }
+
+    // First bit of a free run of at least n bits, if one is found without a
+    // scan.
+    fn good_fit(&self, n: usize) -> Option<usize> {
+        let (f, s) = Self::class_holding(n);
+        let (f, s) = self.nonempty_from(f, s)?;
+        Some(self.heads[f][s] as usize)
+    }
+
+    // Add the free run [begin, begin+len) to the index.
+    fn insert(&mut self, begin: usize, len: usize) {
+        let (f, s) = Self::class(len);
+        self.len[begin] = len as u16;
+        self.start[begin + len - 1] = begin as u16;
+        let head = self.heads[f][s];
+        self.next[begin] = head;
+        self.prev[begin] = NIL;
+        if head != NIL { self.prev[head as usize] = begin as u16; }
+        self.heads[f][s] = begin as u16;
+        self.sl_map[f] |= 1 << s;
+        self.fl_map |= 1 << f;
+    }
+
+    // Take the free run beginning at begin out of the index.
+    fn remove(&mut self, begin: usize) {
+        let (f, s) = Self::class(self.len[begin] as usize);
+        let (next, prev) = (self.next[begin], self.prev[begin]);
+        if next != NIL { self.prev[next as usize] = prev; }
+        if prev != NIL {
+            self.next[prev as usize] = next;
+        } else {
+            self.heads[f][s] = next;
+            if next == NIL {
+                self.sl_map[f] &= !(1 << s);
+                if self.sl_map[f] == 0 { self.fl_map &= !(1 << f); }
+            }
+        }
+    }
+
+    fn get(&self, i: usize) -> bool {
+        self.bits[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    // First bit of the free run holding the free bit i.
+    fn run_start(&self, i: usize) -> usize {
+        if i == 0 || self.get(i - 1) {
+            i
+        } else if i + 1 == self.size || self.get(i + 1) {
+            self.start[i] as usize
+        } else {
+            self.find_prev(i, true).map_or(0, |j| j + 1)
+        }
+    }
+
+    // First bit in [from, to) whose state is allocated, or to.
+    fn next_in(&self, from: usize, to: usize, allocated: bool) -> usize {
+        if from >= to { return to; }
+        let flip = if allocated { 0 } else { !0 };
+        let mut w = from / 64;
+        let mut word = (self.bits[w] ^ flip) & (!0 << (from % 64));
+        while word == 0 {
+            w += 1;
+            if w * 64 >= to { return to; }
+            word = self.bits[w] ^ flip;
+        }
+        min(w * 64 + word.trailing_zeros() as usize, to)
+    }
+
+    // Set bits [begin, end) to allocated or free, keeping the index in step.
+    fn set_range(&mut self, begin: usize, end: usize, allocated: bool) {
+        // Bits past the size are ignored.
+        let end = min(end, self.size);
+        if begin >= end { return; }
+        // Take the free runs overlapping the range, or when freeing touching
+        // it, out of the index; [lo, hi) spans them and the range.
+        let (from, to) = if allocated {
+            (begin, end)
+        } else {
+            (begin.saturating_sub(1), min(end + 1, self.size))
+        };
+        let (mut lo, mut hi) = (begin, end);
+        let mut run = self.next_in(from, to, false);
+        if run < to { run = self.run_start(run); }
+        while run < to {
+            let len = self.len[run] as usize;
+            self.remove(run);
+            lo = min(lo, run);
+            hi = max(hi, run + len);
+            run = self.next_in(run + len, to, false);
+        }
+        for w in begin / 64..=(end - 1) / 64 {
+            let first = if w == begin / 64 { begin % 64 } else { 0 };
+            let last = if w == (end - 1) / 64 { (end - 1) % 64 } else { 63 };
+            let mask = (!0u64 >> (63 - last + first)) << first;
+            self.used -= (self.bits[w] & mask).count_ones() as usize;
+            if allocated {
+                self.bits[w] |= mask;
+                self.used += mask.count_ones() as usize;
+            } else {
+                self.bits[w] &= !mask;
+            }
+        }
+        if allocated {
+            if lo < begin { self.insert(lo, begin - lo); }
+            if hi > end { self.insert(end, hi - end); }
+        } else {
+            self.insert(lo, hi - lo);
+        }
+    }
+}
+
+impl BitAllocatorInit for TlsfBitMap {
+    fn new(size: usize) -> Self { TlsfBitMap::new(size) }
+}
+
+impl BitAllocator for TlsfBitMap {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        self.alloc_aligned(n, 1)
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        assert!(0 < n && n <= self.size);
+        assert!(alignment >= 1);
+        let begin = match self.good_fit(n.saturating_add(alignment - 1)) {
+            Some(run) => policy::align_up(run, alignment, 0),
+            None => policy::first_fit(self, 0, self.size, n, alignment, 0)?,
+        };
+        self.mark(begin, n);
+        Some(begin)
+    }
+
+    fn dealloc(&mut self, begin: usize, n: usize) {
+        self.set_range(begin, begin + n, false);
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.set_range(begin, begin + n, true);
+    }
+
+    fn size(&self) -> usize { self.size }
+
+    fn capacity(&self) -> usize { MAX_BITS }
+
+    fn is_allocated(&self, index: usize) -> bool {
+        assert!(index < self.size);
+        self.get(index)
+    }
+
+    fn free_count(&self) -> usize { self.size - self.used }
+
+    fn find_next(&self, from: usize, allocated: bool) -> usize {
+        self.next_in(from, self.size, allocated)
+    }
+
+    fn find_prev(&self, before: usize, allocated: bool) -> Option<usize> {
+        let before = min(before, self.size);
+        if before == 0 { return None; }
+        let flip = if allocated { 0 } else { !0 };
+        let mut w = (before - 1) / 64;
+        let mut word = (self.bits[w] ^ flip) & (!0 >> (63 - (before - 1) % 64));
+        while word == 0 {
+            if w == 0 { return None; }
+            w -= 1;
+            word = self.bits[w] ^ flip;
+        }
+        Some(w * 64 + 63 - word.leading_zeros() as usize)
+    }
+}
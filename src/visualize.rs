@@ -0,0 +1,72 @@
+//! Rendering bitmaps as text, for debugging over a console.
+
+use core::cmp::{max, min};
+use core::fmt::{self, Write};
+use crate::BitAllocator;
+
+/// Displays a bitmap as rows of `#` (allocated) and `.` (free), each row
+/// labelled with the index of its first bit.
+///
+/// When several bits are shown as one character, the character is `+` if
+/// only some of them are allocated.
+///
+/// Created by [`BitAllocator::visualize`].
+pub struct Visualize<'a, T: ?Sized> {
+    bm: &'a T,
+    columns: usize, // characters per row
+    scale: usize,   // bits per character
+}
+
+impl<'a, T: BitAllocator + ?Sized> Visualize<'a, T> {
+    pub(crate) fn new(bm: &'a T) -> Self {
+        Visualize { bm, columns: 64, scale: 1 }
+    }
+
+    /// Show columns characters per row instead of 64. Must not be zero.
+    pub fn columns(mut self, columns: usize) -> Self {
+        assert!(columns > 0);
+        self.columns = columns;
+        self
+    }
+
+    /// Show the whole bitmap in a single row of at most width characters,
+    /// each standing for as many bits as needed. Must not be zero.
+    pub fn fit_width(mut self, width: usize) -> Self {
+        assert!(width > 0);
+        self.scale = max(1, self.bm.size().div_ceil(width));
+        self.columns = width;
+        self
+    }
+}
+
+impl<'a, T: BitAllocator + ?Sized> fmt::Display for Visualize<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = self.bm.size();
+        let row_bits = self.columns * self.scale;
+        let label = digits(size.saturating_sub(1));
+        for row in (0..size).step_by(row_bits) {
+            if row > 0 { f.write_char('\n')?; }
+            write!(f, "{:>w$} ", row, w = label)?;
+            for begin in (row..min(row + row_bits, size)).step_by(self.scale) {
+                let end = min(begin + self.scale, size);
+                let used = (begin..end).filter(|&i| self.bm.is_allocated(i)).count();
+                f.write_char(match used {
+                    0 => '.',
+                    _ if used == end - begin => '#',
+                    _ => '+',
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Number of decimal digits of n.
+fn digits(mut n: usize) -> usize {
+    let mut d = 1;
+    while n >= 10 {
+        n /= 10;
+        d += 1;
+    }
+    d
+}
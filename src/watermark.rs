@@ -0,0 +1,172 @@
+//! An emergency reserve kept by refusing requests below a watermark.
+
+use crate::{AllocError, BitAllocator, ReserveConflict, check_len, exhaustion};
+
+/// Wraps an allocator with a reserve of free bits that only critical
+/// requests may use, so that the path freeing memory when it runs low is not
+/// starved itself.
+///
+/// A request through alloc, alloc_aligned or alloc_aligned_offset fails if
+/// it would leave fewer than `low` bits free, while alloc_critical may go
+/// on down to `min`.
+///
+/// An optional hook, given at construction, is called with a
+/// [`MemoryEvent`] right where a request fails or the free count falls
+/// below a watermark, e.g. to kick page reclaim or log the failure.
+pub struct WatermarkAllocator<A> {
+    inner: A,
+    low: usize,
+    min: usize,
+    hook: Option<fn(MemoryEvent)>,
+}
+
+/// What the hook of a [`WatermarkAllocator`] is called for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryEvent {
+    /// A request for n bits failed, whether for lack of space or to keep the
+    /// reserve. critical is true for alloc_critical.
+    AllocFailed { n: usize, critical: bool },
+    /// The free count fell below the low watermark, to free.
+    BelowLow { free: usize },
+    /// The free count fell below the min watermark, to free.
+    BelowMin { free: usize },
+}
+
+impl<A: BitAllocator> WatermarkAllocator<A> {
+    /// Wrap inner, with the given watermarks.
+    ///
+    /// Panics if min is above low.
+    pub fn new(inner: A, low: usize, min: usize) -> Self {
+        assert!(min <= low);
+        WatermarkAllocator { inner, low, min, hook: None }
+    }
+
+    /// Like new, but hook is called on every [`MemoryEvent`].
+    pub fn with_hook(inner: A, low: usize, min: usize, hook: fn(MemoryEvent)) -> Self {
+        WatermarkAllocator { hook: Some(hook), ..Self::new(inner, low, min) }
+    }
+
+    /// The wrapped allocator.
+    pub fn inner(&self) -> &A { &self.inner }
+
+    /// Unwrap the allocator.
+    pub fn into_inner(self) -> A { self.inner }
+
+    /// The low and min watermarks.
+    pub fn watermarks(&self) -> (usize, usize) { (self.low, self.min) }
+
+    /// Change the watermarks.
+    ///
+    /// Panics if min is above low.
+    pub fn set_watermarks(&mut self, low: usize, min: usize) {
+        assert!(min <= low);
+        self.low = low;
+        self.min = min;
+    }
+
+    /// Whether the free count is below the low watermark, so that only
+    /// critical requests succeed.
+    pub fn is_low(&self) -> bool { self.inner.free_count() < self.low }
+
+    /// Like alloc, but may leave as few as min bits free.
+    pub fn alloc_critical(&mut self, n: usize) -> Option<usize> {
+        self.alloc_critical_aligned(n, 1)
+    }
+
+    /// Like alloc_aligned, but may leave as few as min bits free.
+    pub fn alloc_critical_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        self.guarded(n, true, |a| a.alloc_aligned(n, alignment))
+    }
+
+    // Whether n more bits can be allocated while keeping the reserve of
+    // critical or normal requests free.
+    fn leaves(&self, n: usize, critical: bool) -> bool {
+        let reserve = if critical { self.min } else { self.low };
+        self.inner.free_count() >= n.saturating_add(reserve)
+    }
+
+    // Run f on inner to allocate n bits, unless that would eat into the
+    // reserve, and report what happened to the hook.
+    fn guarded<F: FnOnce(&mut A) -> Option<usize>>(&mut self, n: usize, critical: bool, f: F) -> Option<usize> {
+        // Requests inner rejects as invalid go straight to it, to fail the
+        // same way rather than as if the reserve were short.
+        if check_len(self.inner.size(), n).is_err() { return f(&mut self.inner); }
+        let before = self.inner.free_count();
+        let begin = if self.leaves(n, critical) { f(&mut self.inner) } else { None };
+        if begin.is_none() { self.notify(MemoryEvent::AllocFailed { n, critical }); }
+        self.crossed(before);
+        begin
+    }
+
+    // Run f on inner, which may allocate anything, and report the
+    // watermarks it crosses to the hook.
+    fn watched<R, F: FnOnce(&mut A) -> R>(&mut self, f: F) -> R {
+        let before = self.inner.free_count();
+        let result = f(&mut self.inner);
+        self.crossed(before);
+        result
+    }
+
+    // Why a normal request for n bits failed.
+    fn failure(&self, n: usize) -> AllocError {
+        if self.leaves(n, false) { exhaustion(&self.inner, n) } else { AllocError::OutOfSpace }
+    }
+
+    // Report the watermarks the free count fell below since it was before.
+    fn crossed(&self, before: usize) {
+        let free = self.inner.free_count();
+        if free < self.low && before >= self.low { self.notify(MemoryEvent::BelowLow { free }); }
+        if free < self.min && before >= self.min { self.notify(MemoryEvent::BelowMin { free }); }
+    }
+
+    fn notify(&self, event: MemoryEvent) {
+        if let Some(hook) = self.hook { hook(event); }
+    }
+}
+
+impl<A: BitAllocator> BitAllocator for WatermarkAllocator<A> {
+    fn alloc(&mut self, n: usize) -> Option<usize> {
+        self.guarded(n, false, |a| a.alloc(n))
+    }
+
+    fn alloc_aligned(&mut self, n: usize, alignment: usize) -> Option<usize> {
+        self.guarded(n, false, |a| a.alloc_aligned(n, alignment))
+    }
+
+    fn alloc_aligned_offset(&mut self, n: usize, align: usize, offset: usize) -> Option<usize> {
+        self.guarded(n, false, |a| a.alloc_aligned_offset(n, align, offset))
+    }
+
+    /// Like alloc, but a request refused to keep the reserve fails with
+    /// `AllocError::OutOfSpace`.
+    fn try_alloc(&mut self, n: usize) -> Result<usize, AllocError> {
+        check_len(self.size(), n)?;
+        self.alloc(n).ok_or_else(|| self.failure(n))
+    }
+
+    /// Like alloc_aligned, but a request refused to keep the reserve fails
+    /// with `AllocError::OutOfSpace`.
+    fn try_alloc_aligned(&mut self, n: usize, alignment: usize) -> Result<usize, AllocError> {
+        check_len(self.size(), n)?;
+        if alignment == 0 { return Err(AllocError::InvalidAlignment); }
+        self.alloc_aligned(n, alignment).ok_or_else(|| self.failure(n))
+    }
+
+    fn mark(&mut self, begin: usize, n: usize) {
+        self.watched(|a| a.mark(begin, n))
+    }
+
+    fn set_all(&mut self, allocated: bool) {
+        self.watched(|a| a.set_all(allocated))
+    }
+
+    fn alloc_at(&mut self, begin: usize, n: usize) -> Result<(), AllocError> {
+        self.watched(|a| a.alloc_at(begin, n))
+    }
+
+    fn reserve(&mut self, begin: usize, n: usize) -> Result<(), ReserveConflict> {
+        self.watched(|a| a.reserve(begin, n))
+    }
+
+    forward_bit_allocator!(dealloc, dealloc_batch);
+}
@@ -0,0 +1,120 @@
+//! Several allocators managed as the zones of one index space.
+
+use crate::BitAllocator;
+
+/// Zones a [`ZoneManager`] can hold.
+pub const MAX_ZONES: usize = 8;
+
+const _: () = assert!(MAX_ZONES <= u32::BITS as usize);
+
+/// A set of allocators, each managing a zone of a common index space, such
+/// as the DMA, NORMAL and HIGH zones of physical memory.
+///
+/// Zone z covers the indices `[base, base + size)` given when it was added,
+/// and is allowed for a request when bit z of its mask is set. A request is
+/// tried in each allowed zone in the fallback order, by default from the last
+/// zone added to the first, so that the scarcer low zones are only used
+/// when the others are full or when the mask requires it.
+pub struct ZoneManager<'a> {
+    zones: [Option<(usize, &'a mut dyn BitAllocator)>; MAX_ZONES],
+    count: usize,
+    order: [usize; MAX_ZONES],
+}
+
+impl<'a> Default for ZoneManager<'a> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<'a> ZoneManager<'a> {
+    /// Create a manager without zones.
+    pub fn new() -> Self {
+        ZoneManager { zones: Default::default(), count: 0, order: [0; MAX_ZONES] }
+    }
+
+    /// Add a zone managed by alloc, whose index 0 is base in the common
+    /// index space, and return its number. The new zone is tried first.
+    ///
+    /// Panics if MAX_ZONES zones were already added, or if the zone overlaps
+    /// another one.
+    pub fn add_zone(&mut self, base: usize, alloc: &'a mut dyn BitAllocator) -> usize {
+        assert!(self.count < MAX_ZONES);
+        let end = base + alloc.size();
+        assert!(self.zones().all(|(b, a)| end <= b || b + a.size() <= base));
+        let zone = self.count;
+        self.zones[zone] = Some((base, alloc));
+        self.order.copy_within(0..zone, 1);
+        self.order[0] = zone;
+        self.count += 1;
+        zone
+    }
+
+    /// Try zones in the given order instead, first to last.
+    ///
+    /// Panics unless order holds every zone number exactly once.
+    pub fn set_fallback_order(&mut self, order: &[usize]) {
+        assert!(order.len() == self.count);
+        assert!((0..self.count).all(|z| order.contains(&z)));
+        self.order[..self.count].copy_from_slice(order);
+    }
+
+    /// Number of zones.
+    pub fn zone_count(&self) -> usize { self.count }
+
+    /// The allocator of zone, and the index its index 0 stands for.
+    ///
+    /// Panics if there is no such zone.
+    pub fn zone(&self, zone: usize) -> (usize, &dyn BitAllocator) {
+        self.zones().nth(zone).expect("no such zone")
+    }
+
+    /// The zone holding index, if any.
+    pub fn zone_of(&self, index: usize) -> Option<usize> {
+        self.zones().position(|(base, alloc)| base <= index && index < base + alloc.size())
+    }
+
+    /// Total number of free bits in all zones.
+    pub fn free_count(&self) -> usize {
+        self.zones().map(|(_, alloc)| alloc.free_count()).sum()
+    }
+
+    /// Allocate n consecutive bits in one of the zones allowed by mask,
+    /// returning the zone that satisfied the request and the index of the
+    /// first bit in the common index space, or `None` if no allowed zone
+    /// has room.
+    pub fn alloc(&mut self, n: usize, mask: u32) -> Option<(usize, usize)> {
+        self.alloc_aligned(n, 1, mask)
+    }
+
+    /// Like alloc, but the allocated index, in the common index space, is a
+    /// multiple of alignment.
+    pub fn alloc_aligned(&mut self, n: usize, alignment: usize, mask: u32) -> Option<(usize, usize)> {
+        assert!(alignment >= 1);
+        for &zone in &self.order[..self.count] {
+            if mask >> zone & 1 == 0 { continue; }
+            if let Some((base, alloc)) = &mut self.zones[zone] {
+                if n > alloc.size() { continue; }
+                let offset = (alignment - *base % alignment) % alignment;
+                if let Some(begin) = alloc.alloc_aligned_offset(n, alignment, offset) {
+                    return Some((zone, *base + begin));
+                }
+            }
+        }
+        None
+    }
+
+    /// Free the n bits at begin in the common index space.
+    ///
+    /// Panics if they do not all lie in one zone.
+    pub fn dealloc(&mut self, begin: usize, n: usize) {
+        let zone = self.zone_of(begin).expect("bits outside all zones");
+        if let Some((base, alloc)) = &mut self.zones[zone] {
+            assert!(begin + n <= *base + alloc.size());
+            alloc.dealloc(begin - *base, n);
+        }
+    }
+
+    // Base and allocator of every zone, by number.
+    fn zones(&self) -> impl Iterator<Item = (usize, &(dyn BitAllocator + 'a))> + '_ {
+        self.zones[..self.count].iter().flatten().map(|(base, alloc)| (*base, &**alloc))
+    }
+}